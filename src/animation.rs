@@ -0,0 +1,122 @@
+use glam::Vec3;
+use serde::Deserialize;
+
+/**
+ * Values that can be blended, curves are built from repeated blends so that orientations
+ * interpolate correctly when blended spherically
+ */
+pub trait Lerp: Copy {
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &f32, t: f32) -> f32 {
+        return self + (other - self) * t;
+    }
+}
+
+impl Lerp for Vec3 {
+    fn lerp(&self, other: &Vec3, t: f32) -> Vec3 {
+        return Vec3::lerp(*self, *other, t);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    CatmullRom,
+    Bezier,
+}
+
+/**
+ * Value at a point in time, the handles are the inner control points of bezier segments
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe<T: Lerp> {
+    pub time: f32,
+    pub value: T,
+    pub handle_in: T,
+    pub handle_out: T,
+}
+
+impl<T: Lerp> Keyframe<T> {
+    pub fn new(time: f32, value: T) -> Keyframe<T> {
+        Keyframe {
+            time,
+            value,
+            handle_in: value,
+            handle_out: value,
+        }
+    }
+}
+
+/**
+ * Keyframed value, held constant before the first and after the last key. Catmull-Rom
+ * splines pass through every key with non-uniform key times, bezier segments bend towards
+ * the key handles and ease in and out without them
+ * References: https://en.wikipedia.org/wiki/Centripetal_Catmull%E2%80%93Rom_spline#Definition,
+ *             https://en.wikipedia.org/wiki/De_Casteljau%27s_algorithm
+ */
+#[derive(Debug, Clone)]
+pub struct Track<T: Lerp> {
+    pub keys: Vec<Keyframe<T>>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Lerp> Track<T> {
+    pub fn new(mut keys: Vec<Keyframe<T>>, interpolation: Interpolation) -> Track<T> {
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Track {
+            keys,
+            interpolation,
+        }
+    }
+
+    pub fn eval(&self, time: f32) -> T {
+        let i = self.keys.partition_point(|k| k.time <= time);
+        if i == 0 {
+            return self.keys[0].value;
+        }
+        if i == self.keys.len() {
+            return self.keys[i - 1].value;
+        }
+
+        let k1 = &self.keys[i - 1];
+        let k2 = &self.keys[i];
+        let t = (time - k1.time) / (k2.time - k1.time);
+
+        match self.interpolation {
+            Interpolation::Linear => return k1.value.lerp(&k2.value, t),
+            Interpolation::CatmullRom => {
+                // mirror the neighbouring key times at the ends of the track
+                let (t0, p0) = match i {
+                    1 => (2.0 * k1.time - k2.time, k1.value),
+                    _ => (self.keys[i - 2].time, self.keys[i - 2].value),
+                };
+                let (t3, p3) = match self.keys.get(i + 1) {
+                    Some(k3) => (k3.time, k3.value),
+                    None => (2.0 * k2.time - k1.time, k2.value),
+                };
+                let (t1, p1, t2, p2) = (k1.time, k1.value, k2.time, k2.value);
+
+                // barry-goldman pyramid
+                let a1 = p0.lerp(&p1, (time - t0) / (t1 - t0));
+                let a2 = p1.lerp(&p2, t);
+                let a3 = p2.lerp(&p3, (time - t2) / (t3 - t2));
+                let b1 = a1.lerp(&a2, (time - t0) / (t2 - t0));
+                let b2 = a2.lerp(&a3, (time - t1) / (t3 - t1));
+                return b1.lerp(&b2, t);
+            },
+            Interpolation::Bezier => {
+                let a1 = k1.value.lerp(&k1.handle_out, t);
+                let a2 = k1.handle_out.lerp(&k2.handle_in, t);
+                let a3 = k2.handle_in.lerp(&k2.value, t);
+                let b1 = a1.lerp(&a2, t);
+                let b2 = a2.lerp(&a3, t);
+                return b1.lerp(&b2, t);
+            },
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use bvh::ray::Ray;
+use glam::{Vec3, Vec2};
+
+use crate::{
+    renderer::{is_transparent, diffuse_color, graph_values},
+    scene::Scene,
+    texture::Footprint,
+};
+
+/**
+ * Arbitrary output variables, auxiliary passes describing the first visible surface
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    Depth,
+    Position,
+    Normal,
+    Albedo,
+    Uv,
+    MaterialId,
+    ObjectId,
+}
+
+/**
+ * Surface attributes recorded by a camera ray at its first opaque hit
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AovSample {
+    pub hit: bool,
+    pub depth: f32,
+    pub pos: Vec3,
+    pub nrm: Vec3,
+    pub albedo: Vec3,
+    pub uv: Vec2,
+    pub mat_id: u32,
+    pub obj_id: u32,
+}
+
+impl Aov {
+    pub const NAMES: [&'static str; 7] = ["depth", "position", "normal", "albedo", "uv", "material_id", "object_id"];
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        match name {
+            "depth" => return Some(Aov::Depth),
+            "position" => return Some(Aov::Position),
+            "normal" => return Some(Aov::Normal),
+            "albedo" => return Some(Aov::Albedo),
+            "uv" => return Some(Aov::Uv),
+            "material_id" => return Some(Aov::MaterialId),
+            "object_id" => return Some(Aov::ObjectId),
+            _ => return None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => return "depth",
+            Aov::Position => return "position",
+            Aov::Normal => return "normal",
+            Aov::Albedo => return "albedo",
+            Aov::Uv => return "uv",
+            Aov::MaterialId => return "material_id",
+            Aov::ObjectId => return "object_id",
+        }
+    }
+
+    /**
+     * Channel names used when the pass is written to an image, one per component
+     */
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => return &["Z"],
+            Aov::Position | Aov::Normal => return &["X", "Y", "Z"],
+            Aov::Albedo => return &["R", "G", "B"],
+            Aov::Uv => return &["U", "V"],
+            Aov::MaterialId | Aov::ObjectId => return &["id"],
+        }
+    }
+
+    /**
+     * Whether the pass holds identifiers that must not be blended between samples
+     */
+    pub fn is_id(&self) -> bool {
+        return matches!(self, Aov::MaterialId | Aov::ObjectId);
+    }
+}
+
+impl AovSample {
+    pub fn eval(scene: &Scene, ray: &Ray, time: f32) -> AovSample {
+        let mut ray = Ray::new(ray.origin, ray.direction);
+
+        // skip surfaces cut out by alpha textures, limited like the raytracer recursion
+        for _ in 0..16 {
+            let hit_result = match scene.intersect(&ray, f32::MAX, time) {
+                Some(hit_result) => hit_result,
+                None => break,
+            };
+            let hit_mat = scene.materials.get(hit_result.mat).unwrap();
+            if is_transparent(hit_mat, &hit_result) {
+                ray = Ray::new(hit_result.pos, ray.direction);
+                continue;
+            }
+
+            let fp = Footprint::point(&hit_result, scene.texture_filter);
+            return AovSample {
+                hit: true,
+                depth: scene.camera.depth(hit_result.pos),
+                pos: hit_result.pos,
+                nrm: hit_result.nrm,
+                albedo: diffuse_color(hit_mat, &fp, &graph_values(hit_mat, &fp)),
+                uv: hit_result.tex,
+                mat_id: hit_mat.id,
+                obj_id: hit_result.obj,
+            };
+        }
+
+        return AovSample::default();
+    }
+
+    pub fn value(&self, aov: Aov) -> Vec3 {
+        match aov {
+            Aov::Depth => return Vec3::splat(self.depth),
+            Aov::Position => return self.pos,
+            Aov::Normal => return self.nrm,
+            Aov::Albedo => return self.albedo,
+            Aov::Uv => return self.uv.extend(0.0),
+            Aov::MaterialId if self.hit => return Vec3::splat(self.mat_id as f32),
+            Aov::ObjectId if self.hit => return Vec3::splat(self.obj_id as f32),
+            Aov::MaterialId | Aov::ObjectId => return Vec3::splat(-1.0),
+        }
+    }
+}
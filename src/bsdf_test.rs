@@ -0,0 +1,111 @@
+use glam::{Vec2, Vec3};
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+use std::fmt::Debug;
+
+use crate::utils::luminance;
+
+const SAMPLE_COUNT: usize = 100_000;
+
+/**
+ * Scattering model checked by the shared BSDF tests, in a frame whose normal is +Z
+ */
+pub trait TestBsdf: Debug {
+    fn sample(&self, v: &Vec3, u_lobe: f32, u: Vec2) -> Option<(Vec3, Vec3)>;
+    fn eval(&self, v: &Vec3, l: &Vec3) -> Vec3;
+    fn pdf(&self, v: &Vec3, l: &Vec3) -> f32;
+
+    /**
+     * Whether light leaves through the back of the surface, uniform sampling then covers the sphere
+     */
+    fn transmits(&self) -> bool {
+        return false;
+    }
+
+    /**
+     * Energy carried by a unit of radiance scattered towards l
+     */
+    fn energy_scale(&self, _l: &Vec3) -> f32 {
+        return 1.0;
+    }
+}
+
+fn view(cos_theta: f32) -> Vec3 {
+    return Vec3::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta);
+}
+
+/**
+ * Scattered radiance estimated with importance sampling and with uniform sampling,
+ * and the scattered energy estimated with importance sampling
+ */
+fn albedo(bsdf: &impl TestBsdf, v: &Vec3, rng: &mut SmallRng) -> (f32, f32, f32) {
+    let mut sampled = 0.0f64;
+    let mut uniform = 0.0f64;
+    let mut energy = 0.0f64;
+    for _ in 0..SAMPLE_COUNT {
+        if let Some((l, weight)) = bsdf.sample(v, rng.gen(), Vec2::new(rng.gen(), rng.gen())) {
+            sampled += luminance(&weight) as f64;
+            energy += (luminance(&weight) * bsdf.energy_scale(&l)) as f64;
+        }
+
+        let (z, solid_angle) = match bsdf.transmits() {
+            true => (2.0 * rng.gen::<f32>() - 1.0, 4.0 * std::f32::consts::PI),
+            false => (rng.gen::<f32>(), 2.0 * std::f32::consts::PI),
+        };
+        let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+        let r = (1.0 - z * z).sqrt();
+        let l = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+        uniform += (luminance(&bsdf.eval(v, &l)) * solid_angle) as f64;
+    }
+
+    let n = SAMPLE_COUNT as f64;
+    return ((sampled / n) as f32, (uniform / n) as f32, (energy / n) as f32);
+}
+
+/**
+ * The weight returned by sample() equals eval() over pdf() for the sampled direction
+ */
+pub fn check_sample_weight(bsdfs: impl IntoIterator<Item = impl TestBsdf>) {
+    let mut rng = SmallRng::seed_from_u64(1);
+    for bsdf in bsdfs {
+        for cos_theta in [0.1, 0.5, 1.0] {
+            let v = view(cos_theta);
+            for _ in 0..1000 {
+                let Some((l, weight)) = bsdf.sample(&v, rng.gen(), Vec2::new(rng.gen(), rng.gen())) else {
+                    continue;
+                };
+                let expected = bsdf.eval(&v, &l) / bsdf.pdf(&v, &l);
+                assert!((weight - expected).abs().max_element() <= 1e-4 * expected.max_element().max(1.0),
+                    "{:?} v: {:?} l: {:?} weight: {:?} expected: {:?}", bsdf, v, l, weight, expected);
+            }
+        }
+    }
+}
+
+/**
+ * Importance sampling and uniform sampling agree on the scattered radiance within the relative tolerance
+ */
+pub fn check_pdf(bsdfs: impl IntoIterator<Item = impl TestBsdf>, tolerance: f32) {
+    let mut rng = SmallRng::seed_from_u64(2);
+    for bsdf in bsdfs {
+        for cos_theta in [0.2, 0.7, 1.0] {
+            let (sampled, uniform, _) = albedo(&bsdf, &view(cos_theta), &mut rng);
+            assert!((sampled - uniform).abs() < tolerance * uniform,
+                "{:?} cos_theta: {} sampled: {} uniform: {}", bsdf, cos_theta, sampled, uniform);
+        }
+    }
+}
+
+/**
+ * A white surface neither creates energy nor loses most of it
+ */
+pub fn check_white_furnace(bsdfs: impl IntoIterator<Item = impl TestBsdf>) {
+    let mut rng = SmallRng::seed_from_u64(3);
+    for bsdf in bsdfs {
+        for cos_theta in [0.05, 0.3, 0.7, 1.0] {
+            let (_, _, energy) = albedo(&bsdf, &view(cos_theta), &mut rng);
+            assert!(energy <= 1.01, "{:?} cos_theta: {} scatters {}", bsdf, cos_theta, energy);
+            assert!(energy >= 0.25, "{:?} cos_theta: {} scatters {}", bsdf, cos_theta, energy);
+        }
+    }
+}
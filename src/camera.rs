@@ -1,14 +1,237 @@
 use bvh::ray::Ray;
-use glam::{Vec3, Quat};
+use glam::{Vec3, Vec2, Quat, Mat3, Mat4};
+use image::ImageResult;
+use std::f32::consts::PI;
+use serde::Deserialize;
 
-use crate::transform::Transform;
+use crate::distortion::Distortion;
+use crate::texture::RayCone;
+use crate::transform::{AnimatedTransform, Transform};
+use crate::utils::{luminance, sample_concentric_disk};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FovAxis {
+    #[default]
+    Horizontal,
+    Vertical,
+    Diagonal,
+}
+
+/**
+ * Mapping from film positions to ray directions. Fisheye projections use the focal length
+ * as the radius of one radian in pixels, panoramas cover the whole film and ignore the intrinsics.
+ * Cubemaps lay out six 90 degree faces left to right as right, left, up, down, front, back
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Projection {
+    #[default]
+    Perspective,
+    Orthographic,
+    FisheyeEquidistant,
+    FisheyeEquisolid,
+    Equirectangular,
+    Cubemap,
+}
+
+impl Projection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Projection::Perspective => return "perspective",
+            Projection::Orthographic => return "orthographic",
+            Projection::FisheyeEquidistant => return "fisheye_equidistant",
+            Projection::FisheyeEquisolid => return "fisheye_equisolid",
+            Projection::Equirectangular => return "equirectangular",
+            Projection::Cubemap => return "cubemap",
+        }
+    }
+
+    /**
+     * Whether the projection keeps straight lines straight, these use a focal plane and planar depth
+     */
+    pub fn is_planar(&self) -> bool {
+        return matches!(self, Projection::Perspective | Projection::Orthographic);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+/**
+ * Arrangement of both eyes on the film, the left eye comes first
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StereoLayout {
+    #[default]
+    SideBySide,
+    TopBottom,
+}
+
+/**
+ * How the eyes of planar projections converge, off-axis shifts the frustums and toe-in rotates
+ * the eyes so that both meet at the given distance
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Convergence {
+    #[default]
+    Parallel,
+    OffAxis(f32),
+    ToeIn(f32),
+}
+
+/**
+ * Stereo rig with eyes ipd scene units apart, panoramic projections render omni-directional
+ * stereo with ray origins on a circle of diameter ipd
+ * Reference: https://developers.google.com/vr/jump/rendering-ods-content.pdf
+ */
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Stereo {
+    #[serde(default = "default_ipd")]
+    pub ipd: f32,
+    #[serde(default)]
+    pub convergence: Convergence,
+    #[serde(default)]
+    pub layout: StereoLayout,
+}
+
+fn default_ipd() -> f32 {
+    // average adult interpupillary distance in meters
+    return 0.064;
+}
+
+impl Stereo {
+    /**
+     * Offset of the eye along camera space +X, which points to the left
+     */
+    pub fn eye_offset(&self, eye: Eye) -> f32 {
+        match eye {
+            Eye::Left => return self.ipd * 0.5,
+            Eye::Right => return -self.ipd * 0.5,
+        }
+    }
+}
+
+/**
+ * Bokeh shape of the lens aperture, points are sampled within [-1, 1]^2
+ */
+#[derive(Debug, Clone)]
+pub enum Aperture {
+    Circle,
+    Polygon { blades: u32, rotation: f32 },
+    Image(ApertureImage),
+}
+
+/**
+ * Aperture mask sampled proportional to its luminance, e.g. a bokeh texture
+ */
+#[derive(Debug, Clone)]
+pub struct ApertureImage {
+    pub width: u32,
+    pub height: u32,
+    cdf: Vec<f32>,
+}
+
+impl ApertureImage {
+    pub fn load(path: &str) -> ImageResult<ApertureImage> {
+        let img = image::open(path)?.into_rgb32f();
+
+        let mut cdf: Vec<f32> = Vec::with_capacity((img.width() * img.height()) as usize);
+        let mut sum = 0.0;
+        for pix in img.pixels() {
+            sum += luminance(&Vec3::from(pix.0)).max(0.0);
+            cdf.push(sum);
+        }
+        // fall back to the full square for an empty mask
+        if sum > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= sum);
+        } else {
+            let n = cdf.len() as f32;
+            cdf.iter_mut().enumerate().for_each(|(i, c)| *c = (i + 1) as f32 / n);
+        }
+
+        return Ok(ApertureImage {
+            width: img.width(),
+            height: img.height(),
+            cdf,
+        });
+    }
+
+    pub fn sample(&self, u: Vec2) -> Vec2 {
+        // pick a pixel by inverting the cdf, then reuse the remainder of u.x to jitter within it
+        let i = self.cdf.partition_point(|c| *c <= u.x).min(self.cdf.len() - 1);
+        let c0 = if i > 0 { self.cdf[i - 1] } else { 0.0 };
+        let jitter_x = ((u.x - c0) / (self.cdf[i] - c0).max(f32::MIN_POSITIVE)).clamp(0.0, 1.0);
+
+        let px = (i as u32 % self.width) as f32 + jitter_x;
+        let py = (i as u32 / self.width) as f32 + u.y;
+
+        // fit the longer side into [-1, 1], image rows go downwards
+        let size = self.width.max(self.height) as f32;
+        return Vec2::new(
+            (px - self.width as f32 * 0.5) / size * 2.0,
+            (self.height as f32 * 0.5 - py) / size * 2.0,
+        );
+    }
+}
+
+impl Aperture {
+    /**
+     * Maps a uniform 2D sample to a point on the aperture shape
+     */
+    pub fn sample(&self, u: Vec2) -> Vec2 {
+        match self {
+            Aperture::Circle => return sample_concentric_disk(u.x, u.y),
+            Aperture::Polygon { blades, rotation } => {
+                // pick one of the equally sized triangles around the center, then sample it uniformly
+                let n = *blades as f32;
+                let k = (u.x * n).floor().min(n - 1.0);
+                let u1 = u.x * n - k;
+                let step = 2.0 * PI / n;
+                let a = Vec2::from_angle(rotation + k * step);
+                let b = Vec2::from_angle(rotation + (k + 1.0) * step);
+
+                let su = u1.sqrt();
+                return a * (su * (1.0 - u.y)) + b * (su * u.y);
+            },
+            Aperture::Image(img) => return img.sample(u),
+        }
+    }
+}
+
+/**
+ * Thin lens camera looking down its local +Z axis with +Y up. Intrinsics are kept in
+ * pixels, the principal point is measured from the image corner with pixel centers at +0.5.
+ * A zero lens radius gives a pinhole camera with everything in focus. Orthographic cameras
+ * cover ortho_width scene units across the film, perspective cameras may apply lens distortion.
+ * Fisheye cameras only see within their image circle, fisheye_fov across (in radians)
+ */
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub trf: Transform,
     pub viewport_w: f32,
     pub viewport_h: f32,
     pub viewport_a: f32,
+    pub fx: f32,
+    pub fy: f32,
+    pub cx: f32,
+    pub cy: f32,
+    pub projection: Projection,
+    pub ortho_width: f32,
+    pub fisheye_fov: f32,
+    pub lens_radius: f32,
+    pub focus_dist: f32,
+    pub aperture: Aperture,
+    pub stereo: Option<Stereo>,
+    pub distortion: Distortion,
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+    pub motion: Option<AnimatedTransform>,
 }
 
 impl Camera {
@@ -18,45 +241,343 @@ impl Camera {
             viewport_w,
             viewport_h,
             viewport_a: viewport_h / viewport_w,
+            fx: viewport_w,
+            fy: viewport_w,
+            cx: viewport_w * 0.5,
+            cy: viewport_h * 0.5,
+            projection: Projection::Perspective,
+            ortho_width: 2.0,
+            fisheye_fov: PI,
+            lens_radius: 0.0,
+            focus_dist: 1.0,
+            aperture: Aperture::Circle,
+            stereo: None,
+            distortion: Distortion::None,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            motion: None,
         }
     }
 
     pub fn from_axis_angle(pos: Vec3, axis: Vec3, angle: f32, viewport_w: f32, viewport_h: f32) -> Camera {
-        Camera {
-            trf: Transform::from_axis_angle(pos, axis, angle),
-            viewport_w,
-            viewport_h,
-            viewport_a: viewport_h / viewport_w,
+        return Camera::new(Transform::from_axis_angle(pos, axis, angle), viewport_w, viewport_h);
+    }
+
+    pub fn from_lookat(pos: Vec3, obj: Vec3, up: Vec3, viewport_w: f32, viewport_h: f32) -> Camera {
+        return Camera::new(Transform::from_lookat(pos, obj, up), viewport_w, viewport_h);
+    }
+
+    /**
+     * Switches the projection, fisheyes default to a 180 degree image circle fitting the film height
+     */
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+        if matches!(projection, Projection::FisheyeEquidistant | Projection::FisheyeEquisolid) {
+            self.set_fov(PI, FovAxis::Vertical);
         }
     }
 
-    pub fn from_lookat(pos: Vec3, obj: Vec3, viewport_w: f32, viewport_h: f32) -> Camera {
-        Camera {
-            trf: Transform::from_lookat(pos, obj),
-            viewport_w,
-            viewport_h,
-            viewport_a: viewport_h / viewport_w,
+    /**
+     * Sets square pixel focal lengths from a field of view (in radians) along the given image
+     * axis, following the radial mapping of the projection. For fisheyes it is also the image circle
+     */
+    pub fn set_fov(&mut self, fov: f32, axis: FovAxis) {
+        let extent = match axis {
+            FovAxis::Horizontal => self.viewport_w,
+            FovAxis::Vertical => self.viewport_h,
+            FovAxis::Diagonal => Vec2::new(self.viewport_w, self.viewport_h).length(),
+        };
+        let f = match self.projection {
+            Projection::FisheyeEquidistant => extent * 0.5 / (fov * 0.5),
+            Projection::FisheyeEquisolid => extent * 0.5 / (2.0 * (fov * 0.25).sin()),
+            _ => extent * 0.5 / (fov * 0.5).tan(),
+        };
+
+        self.fisheye_fov = fov;
+        self.fx = f;
+        self.fy = f;
+    }
+
+    /**
+     * Sets focal lengths from physical lens and sensor sizes (in millimeters), pixels are
+     * square unless a sensor height is given. Fisheyes then see as far as their mapping reaches
+     */
+    pub fn set_focal_length(&mut self, focal_length: f32, sensor_w: f32, sensor_h: Option<f32>) {
+        self.fisheye_fov = 2.0 * PI;
+        self.fx = focal_length / sensor_w * self.viewport_w;
+        self.fy = match sensor_h {
+            Some(sensor_h) => focal_length / sensor_h * self.viewport_h,
+            None => self.fx,
+        };
+    }
+
+    /**
+     * Moves the principal point away from the image center, in pixels
+     */
+    pub fn set_principal_point_offset(&mut self, offset: Vec2) {
+        self.cx = self.viewport_w * 0.5 + offset.x;
+        self.cy = self.viewport_h * 0.5 + offset.y;
+    }
+
+    /**
+     * Sets the lens radius from an f-number, with the physical focal length derived from
+     * the horizontal focal length in pixels and the sensor width in millimeters. Scene units
+     * are assumed to be meters
+     */
+    pub fn set_f_stop(&mut self, f_stop: f32, sensor_w: f32) {
+        let focal_length = self.fx / self.viewport_w * sensor_w * 0.001;
+        self.lens_radius = focal_length / (2.0 * f_stop);
+    }
+
+    /**
+     * Size of the whole film in pixels, holding both eyes of a stereo camera
+     */
+    pub fn film_size(&self) -> (u32, u32) {
+        let w = self.viewport_w as u32;
+        let h = self.viewport_h as u32;
+        match self.stereo.map(|stereo| stereo.layout) {
+            None => return (w, h),
+            Some(StereoLayout::SideBySide) => return (w * 2, h),
+            Some(StereoLayout::TopBottom) => return (w, h * 2),
+        }
+    }
+
+    /**
+     * Film origins of the views, one per eye for stereo cameras
+     */
+    pub fn views(&self) -> Vec<(u32, u32)> {
+        match self.stereo.map(|stereo| stereo.layout) {
+            None => return vec![(0, 0)],
+            Some(StereoLayout::SideBySide) => return vec![(0, 0), (self.viewport_w as u32, 0)],
+            Some(StereoLayout::TopBottom) => return vec![(0, 0), (0, self.viewport_h as u32)],
         }
     }
 
-    pub fn calc_ray(&self, x: f32, y: f32) -> Ray {
-        // calculate ray direction vector
-        let x_norm = (self.viewport_w * 0.5 - x) / self.viewport_w;
-        let y_norm = (self.viewport_h * 0.5 - y) / self.viewport_h * self.viewport_a;
-        let v_norm = Vec3 {
-            x: x_norm,
-            y: y_norm,
-            z: 1.0,
+    /**
+     * Splits a film position into the eye and the position within its view
+     */
+    fn film_to_eye(&self, x: f32, y: f32) -> (Option<Eye>, f32, f32) {
+        match self.stereo.map(|stereo| stereo.layout) {
+            None => return (None, x, y),
+            Some(StereoLayout::SideBySide) if x >= self.viewport_w => return (Some(Eye::Right), x - self.viewport_w, y),
+            Some(StereoLayout::TopBottom) if y >= self.viewport_h => return (Some(Eye::Right), x, y - self.viewport_h),
+            Some(_) => return (Some(Eye::Left), x, y),
+        }
+    }
+
+    /**
+     * Camera space offset and toe-in rotation of an eye
+     */
+    fn eye_to_camera(&self, eye: Option<Eye>) -> (Vec3, Quat) {
+        let (stereo, eye) = match (self.stereo, eye) {
+            (Some(stereo), Some(eye)) => (stereo, eye),
+            _ => return (Vec3::ZERO, Quat::IDENTITY),
         };
-        let q = &self.trf.ori;
-        let q_inv = q.conjugate();
-        let w = Quat::from_xyzw(v_norm.x, v_norm.y, v_norm.z, 0.0);
-        let r = (*q * w * q_inv).normalize();
-
-        return Ray::new(self.trf.pos, Vec3 {
-            x: r.x,
-            y: r.y,
-            z: r.z,
-        });
+        let e = stereo.eye_offset(eye);
+        let rot = match stereo.convergence {
+            Convergence::ToeIn(dist) if self.projection.is_planar() => Quat::from_rotation_y(-(e / dist).atan()),
+            _ => Quat::IDENTITY,
+        };
+
+        return (Vec3::new(e, 0.0, 0.0), rot);
+    }
+
+    /**
+     * Intrinsic matrix of the camera or one of its eyes in OpenCV conventions, x right, y down
+     * and pixel centers at integers
+     */
+    pub fn intrinsics(&self, eye: Option<Eye>) -> Mat3 {
+        // off-axis eyes shift their principal point
+        let cx = match (self.stereo, eye) {
+            (Some(stereo), Some(eye)) => match stereo.convergence {
+                Convergence::OffAxis(dist) => self.cx - stereo.eye_offset(eye) * self.fx / dist,
+                _ => self.cx,
+            },
+            _ => self.cx,
+        };
+
+        return Mat3::from_cols(
+            Vec3::new(self.fx, 0.0, 0.0),
+            Vec3::new(0.0, self.fy, 0.0),
+            Vec3::new(cx - 0.5, self.cy - 0.5, 1.0),
+        );
+    }
+
+    /**
+     * World to camera matrix of the camera or one of its eyes in OpenCV conventions, x right,
+     * y down and z forward
+     */
+    pub fn extrinsics(&self, eye: Option<Eye>) -> Mat4 {
+        let flip = Mat4::from_scale(Vec3::new(-1.0, -1.0, 1.0));
+        let (eye_pos, eye_rot) = self.eye_to_camera(eye);
+        let cam_to_world = Mat4::from_rotation_translation(self.trf.ori, self.trf.pos)
+            * Mat4::from_rotation_translation(eye_rot, eye_pos);
+
+        return flip * cam_to_world.inverse();
+    }
+
+    /**
+     * Distance used for the depth pass, along the view axis for planar projections and
+     * along the ray otherwise
+     */
+    pub fn depth(&self, pos: Vec3) -> f32 {
+        match self.projection.is_planar() {
+            true => return (pos - self.trf.pos).dot(self.trf.ori * Vec3::Z),
+            false => return (pos - self.trf.pos).length(),
+        }
+    }
+
+    /**
+     * Maps a uniform sample in [0, 1) to a time within the shutter interval
+     */
+    pub fn sample_time(&self, u: f32) -> f32 {
+        return self.shutter_open + (self.shutter_close - self.shutter_open) * u;
+    }
+
+    /**
+     * Camera to world transform at the given time, following the camera motion if any
+     */
+    pub fn transform_at(&self, time: f32) -> Transform {
+        match self.motion {
+            Some(ref motion) => return motion.eval(time),
+            None => return self.trf,
+        }
+    }
+
+    /**
+     * Cosine of the angle between the optical axis and the ray through a film position
+     */
+    pub fn cos_theta(&self, x: f32, y: f32) -> Option<f32> {
+        let (_, x, y) = self.film_to_eye(x, y);
+        let (_, dir) = self.project(x, y)?;
+        return Some(dir.normalize().z);
+    }
+
+    /**
+     * Generates the ray through the given film position, lens is a uniform sample in [0, 1)^2
+     * mapped onto the aperture. All rays through a pixel meet on the plane at focus_dist, which
+     * only applies to planar projections. Returns None for film positions outside the image circle.
+     * The camera is placed at its position at the given time
+     * Reference: https://www.pbr-book.org/3ed-2018/Camera_Models/Projective_Camera_Models#TheThinLensModelandDepthofField
+     */
+    pub fn calc_ray(&self, x: f32, y: f32, lens: Vec2, time: f32) -> Option<Ray> {
+        let (eye, x, y) = self.film_to_eye(x, y);
+        let (mut org, mut dir) = self.project(x, y)?;
+        let (eye_pos, eye_rot) = self.eye_to_camera(eye);
+
+        if let Some(stereo) = self.stereo {
+            if self.projection.is_planar() {
+                org += eye_pos;
+                if let Convergence::OffAxis(dist) = stereo.convergence {
+                    dir.x -= eye_pos.x / dist;
+                }
+            } else {
+                // omni-directional stereo, rays are tangent to the viewing circle
+                let h = Vec3::new(dir.x, 0.0, dir.z).normalize_or_zero();
+                org += Vec3::Y.cross(h) * eye_pos.x;
+            }
+        }
+
+        // refocus through a point on the lens
+        if self.lens_radius > 0.0 && self.projection.is_planar() && dir.z > 0.0 {
+            let p = self.aperture.sample(lens) * self.lens_radius;
+            let focus = org + dir * self.focus_dist;
+            org += Vec3::new(p.x, p.y, 0.0);
+            dir = focus - org;
+        }
+
+        // toe-in rotates each eye around its own position
+        org = eye_pos + eye_rot * (org - eye_pos);
+        dir = eye_rot * dir;
+
+        let trf = self.transform_at(time);
+        return Some(Ray::new(trf.pos + trf.ori * org, trf.ori * dir));
+    }
+
+    /**
+     * Ray cone covering one pixel around the pinhole ray through the given film position, from
+     * the rays through the neighbouring film positions. The closest neighbour is used so that
+     * cones stay narrow next to the seams of stereo views and cubemap faces. Returns a thin
+     * cone for film positions without neighbours inside the image circle
+     */
+    pub fn ray_cone(&self, x: f32, y: f32) -> RayCone {
+        let thin = RayCone { width: 0.0, spread: 0.0 };
+        let ray = match self.calc_ray(x, y, Vec2::ZERO, self.shutter_open) {
+            Some(ray) => ray,
+            None => return thin,
+        };
+
+        let mut cone: Option<RayCone> = None;
+        for (dx, dy) in [(1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0)] {
+            if let Some(n_ray) = self.calc_ray(x + dx, y + dy, Vec2::ZERO, self.shutter_open) {
+                let width = (n_ray.origin - ray.origin).length();
+                let spread = n_ray.direction.angle_between(ray.direction);
+                cone = match cone {
+                    Some(c) => Some(RayCone { width: c.width.min(width), spread: c.spread.min(spread) }),
+                    None => Some(RayCone { width, spread }),
+                };
+            }
+        }
+
+        return cone.unwrap_or(thin);
+    }
+
+    /**
+     * Camera space origin and direction for a film position, planar projections keep dir.z = 1
+     * References: https://en.wikipedia.org/wiki/Fisheye_lens#Mapping_function,
+     *             https://en.wikipedia.org/wiki/Equirectangular_projection
+     */
+    fn project(&self, x: f32, y: f32) -> Option<(Vec3, Vec3)> {
+        // camera space +X points to the left of the image
+        let x_norm = (self.cx - x) / self.fx;
+        let y_norm = (self.cy - y) / self.fy;
+
+        match self.projection {
+            Projection::Perspective => {
+                // distortion models work in OpenCV coordinates with x right and y down
+                let d = self.distortion.undistort(Vec2::new(-x_norm, -y_norm))?;
+                let dir = Vec3::new(-d.x, -d.y, d.z);
+                return Some((Vec3::ZERO, if dir.z > 0.0 { dir / dir.z } else { dir }));
+            },
+            Projection::Orthographic => {
+                let scale = self.ortho_width / self.viewport_w;
+                let org = Vec3::new((self.cx - x) * scale, (self.cy - y) * scale * self.fx / self.fy, 0.0);
+                return Some((org, Vec3::Z));
+            },
+            Projection::FisheyeEquidistant | Projection::FisheyeEquisolid => {
+                let r = Vec2::new(x_norm, y_norm).length();
+                let theta = match self.projection {
+                    Projection::FisheyeEquidistant => r,
+                    _ => 2.0 * (r * 0.5).min(1.0).asin(),
+                };
+                if theta > (self.fisheye_fov * 0.5).min(PI) || (self.projection == Projection::FisheyeEquisolid && r > 2.0) {
+                    return None;
+                }
+                let phi = y_norm.atan2(x_norm);
+                return Some((Vec3::ZERO, Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())));
+            },
+            Projection::Equirectangular => {
+                let lon = (0.5 - x / self.viewport_w) * 2.0 * PI;
+                let lat = (0.5 - y / self.viewport_h) * PI;
+                return Some((Vec3::ZERO, Vec3::new(lon.sin() * lat.cos(), lat.sin(), lon.cos() * lat.cos())));
+            },
+            Projection::Cubemap => {
+                let face_w = self.viewport_w / 6.0;
+                let face = ((x / face_w) as usize).min(5);
+                let s = (x - face as f32 * face_w) / face_w * 2.0 - 1.0;
+                let t = 1.0 - y / self.viewport_h * 2.0;
+
+                // forward, right and up of each face in camera space
+                let (f, r, u) = match face {
+                    0 => (Vec3::NEG_X, Vec3::NEG_Z, Vec3::Y),
+                    1 => (Vec3::X, Vec3::Z, Vec3::Y),
+                    2 => (Vec3::Y, Vec3::NEG_X, Vec3::NEG_Z),
+                    3 => (Vec3::NEG_Y, Vec3::NEG_X, Vec3::Z),
+                    4 => (Vec3::Z, Vec3::NEG_X, Vec3::Y),
+                    _ => (Vec3::NEG_Z, Vec3::X, Vec3::Y),
+                };
+                return Some((Vec3::ZERO, f + r * s + u * t));
+            },
+        }
     }
 }
@@ -0,0 +1,209 @@
+use glam::Vec3;
+use image::{ImageBuffer, Rgb32FImage};
+
+use crate::aov::Aov;
+use crate::film::Film;
+use crate::utils::{EPSILON, luminance};
+
+/**
+ * Edge-avoiding à-trous wavelet filter guided by albedo, normal and depth passes,
+ * with the luminance edge-stopping function driven by per-pixel variance estimates
+ * Reference: https://research.nvidia.com/publication/2017-07_spatiotemporal-variance-guided-filtering-real-time-reconstruction-path-traced
+ */
+pub struct Denoiser {
+    pub iterations: u32,
+    pub sigma_l: f32,
+    pub sigma_n: f32,
+    pub sigma_z: f32,
+}
+
+/**
+ * Passes the film needs to provide for the denoiser
+ */
+pub const DENOISE_AOVS: [Aov; 3] = [Aov::Albedo, Aov::Normal, Aov::Depth];
+
+const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// pixels with fewer samples estimate their variance from the neighbourhood instead
+const MIN_VARIANCE_SAMPLES: u32 = 4;
+
+impl Default for Denoiser {
+    fn default() -> Denoiser {
+        Denoiser {
+            iterations: 5,
+            sigma_l: 4.0,
+            sigma_n: 128.0,
+            sigma_z: 1.0,
+        }
+    }
+}
+
+impl Denoiser {
+    pub fn denoise(&self, film: &Film) -> Rgb32FImage {
+        let w = film.width as i32;
+        let h = film.height as i32;
+        let idx = |x: i32, y: i32| (y * w + x) as usize;
+
+        // pixel bounds of the view containing a pixel, views are filtered independently
+        let view_w = film.view_w as i32;
+        let view_h = film.view_h as i32;
+        let view = |x: i32, y: i32| {
+            let x0 = x / view_w * view_w;
+            let y0 = y / view_h * view_h;
+            return (x0, y0, (x0 + view_w).min(w), (y0 + view_h).min(h));
+        };
+
+        // gather guide buffers
+        let mut illum: Vec<Vec3> = Vec::with_capacity((w * h) as usize);
+        let mut variance_pixel: Vec<f32> = Vec::with_capacity((w * h) as usize);
+        let mut albedo: Vec<Vec3> = Vec::with_capacity((w * h) as usize);
+        let mut normal: Vec<Vec3> = Vec::with_capacity((w * h) as usize);
+        let mut depth: Vec<f32> = Vec::with_capacity((w * h) as usize);
+        for y in 0..film.height {
+            for x in 0..film.width {
+                // demodulate albedo so textures are not blurred, untextured misses keep their color
+                let a = film.get_aov_pixel(Aov::Albedo, x, y);
+                let a = Vec3::select(a.cmpgt(Vec3::splat(EPSILON)), a, Vec3::ONE);
+                let a_lum = luminance(&a).max(EPSILON);
+
+                illum.push(film.get_pixel(x, y) / a);
+                variance_pixel.push(film.get_variance(x, y) / (a_lum * a_lum));
+                albedo.push(a);
+                normal.push(film.get_aov_pixel(Aov::Normal, x, y));
+                depth.push(film.get_aov_pixel(Aov::Depth, x, y).x);
+            }
+        }
+
+        // spatial variance estimate where the per-pixel sample count is too low
+        let mut variance = variance_pixel;
+        for y in 0..h {
+            for x in 0..w {
+                let count = film.get_sample_count(x as u32, y as u32);
+                if count >= MIN_VARIANCE_SAMPLES {
+                    continue;
+                }
+
+                let (x0, y0, x1, y1) = view(x, y);
+                let mut m1 = 0.0;
+                let mut m2 = 0.0;
+                let mut n = 0.0;
+                for qy in (y - 3).max(y0)..=(y + 3).min(y1 - 1) {
+                    for qx in (x - 3).max(x0)..=(x + 3).min(x1 - 1) {
+                        let l = luminance(&illum[idx(qx, qy)]);
+                        m1 += l;
+                        m2 += l * l;
+                        n += 1.0;
+                    }
+                }
+                // the spread of the neighbours stands in for the variance of a sample, like the
+                // film estimate it is divided by the samples of the pixel mean
+                let mean = m1 / n;
+                variance[idx(x, y)] = (m2 / n - mean * mean).max(0.0) / count.max(1) as f32;
+            }
+        }
+
+        // screen space depth gradient for the depth edge-stopping function
+        let mut depth_grad: Vec<f32> = vec![0.0; (w * h) as usize];
+        for y in 0..h {
+            for x in 0..w {
+                let (x0, y0, x1, y1) = view(x, y);
+                let dx = depth[idx((x + 1).min(x1 - 1), y)] - depth[idx((x - 1).max(x0), y)];
+                let dy = depth[idx(x, (y + 1).min(y1 - 1))] - depth[idx(x, (y - 1).max(y0))];
+                depth_grad[idx(x, y)] = (dx.abs() + dy.abs()) * 0.5;
+            }
+        }
+
+        // filter with increasing step size, each pass feeds the next one
+        for i in 0..self.iterations {
+            let step = 1 << i;
+            let mut illum_next = illum.clone();
+            let mut variance_next = variance.clone();
+
+            for y in 0..h {
+                for x in 0..w {
+                    let p = idx(x, y);
+                    let (x0, y0, x1, y1) = view(x, y);
+                    let var_p = self.blurred_variance(&variance, w, x, y, (x0, y0, x1, y1));
+                    let l_p = luminance(&illum[p]);
+
+                    let mut sum_w = 0.0;
+                    let mut sum_c = Vec3::ZERO;
+                    let mut sum_v = 0.0;
+                    for ky in -2..=2_i32 {
+                        for kx in -2..=2_i32 {
+                            let qx = x + kx * step;
+                            let qy = y + ky * step;
+                            if qx < x0 || qx >= x1 || qy < y0 || qy >= y1 {
+                                continue;
+                            }
+                            let q = idx(qx, qy);
+
+                            let w_h = KERNEL[kx.unsigned_abs() as usize] * KERNEL[ky.unsigned_abs() as usize];
+                            let w_n = self.normal_weight(&normal[p], &normal[q]);
+                            let w_z = (-(depth[p] - depth[q]).abs()
+                                / (self.sigma_z * depth_grad[p] * step as f32 * (kx.abs() + ky.abs()) as f32 + EPSILON)).exp();
+                            let w_l = (-(l_p - luminance(&illum[q])).abs()
+                                / (self.sigma_l * var_p.sqrt() + EPSILON)).exp();
+                            let weight = w_h * w_n * w_z * w_l;
+
+                            sum_w += weight;
+                            sum_c += illum[q] * weight;
+                            sum_v += weight * weight * variance[q];
+                        }
+                    }
+
+                    if sum_w > 0.0 {
+                        illum_next[p] = sum_c / sum_w;
+                        variance_next[p] = sum_v / (sum_w * sum_w);
+                    }
+                }
+            }
+
+            illum = illum_next;
+            variance = variance_next;
+        }
+
+        // remodulate albedo
+        let mut img: Rgb32FImage = ImageBuffer::new(film.width, film.height);
+        for (x, y, pix) in img.enumerate_pixels_mut() {
+            let p = idx(x as i32, y as i32);
+            let c = illum[p] * albedo[p];
+            *pix = image::Rgb([c.x, c.y, c.z]);
+        }
+
+        return img;
+    }
+
+    fn normal_weight(&self, n_p: &Vec3, n_q: &Vec3) -> f32 {
+        // pixels without geometry only blend with each other
+        let p_valid = n_p.length_squared() > EPSILON;
+        let q_valid = n_q.length_squared() > EPSILON;
+        if !p_valid || !q_valid {
+            return if p_valid == q_valid { 1.0 } else { 0.0 };
+        }
+
+        return n_p.normalize().dot(n_q.normalize()).max(0.0).powf(self.sigma_n);
+    }
+
+    fn blurred_variance(&self, variance: &[f32], w: i32, x: i32, y: i32, view: (i32, i32, i32, i32)) -> f32 {
+        let (x0, y0, x1, y1) = view;
+        // 3x3 gaussian prefilter stabilizes the luminance edge-stopping function
+        let k = [0.25, 0.5, 0.25];
+        let mut sum = 0.0;
+        let mut sum_w = 0.0;
+        for ky in -1..=1_i32 {
+            for kx in -1..=1_i32 {
+                let qx = x + kx;
+                let qy = y + ky;
+                if qx < x0 || qx >= x1 || qy < y0 || qy >= y1 {
+                    continue;
+                }
+                let weight = k[(kx + 1) as usize] * k[(ky + 1) as usize];
+                sum += variance[(qy * w + qx) as usize] * weight;
+                sum_w += weight;
+            }
+        }
+
+        return sum / sum_w;
+    }
+}
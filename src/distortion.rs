@@ -0,0 +1,86 @@
+use glam::{Vec2, Vec3};
+use serde::Deserialize;
+
+/**
+ * Lens distortion in OpenCV conventions, acting on normalized image coordinates with
+ * x right and y down. Coefficients are listed in the order of OpenCV's distCoeffs, that is
+ * [k1, k2, p1, p2, k3] for Brown-Conrady and [k1, k2, k3, k4] for the Kannala-Brandt fisheye
+ * References: https://docs.opencv.org/4.x/d9/d0c/group__calib3d.html,
+ *             https://docs.opencv.org/4.x/db/d58/group__calib3d__fisheye.html
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Distortion {
+    #[default]
+    None,
+    BrownConrady([f32; 5]),
+    KannalaBrandt([f32; 4]),
+}
+
+// OpenCV's undistortPoints uses the same number of fixed point iterations
+const UNDISTORT_ITERATIONS: u32 = 20;
+
+impl Distortion {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Distortion::None => return "none",
+            Distortion::BrownConrady(_) => return "brown_conrady",
+            Distortion::KannalaBrandt(_) => return "kannala_brandt",
+        }
+    }
+
+    pub fn coefficients(&self) -> Vec<f32> {
+        match self {
+            Distortion::None => return Vec::new(),
+            Distortion::BrownConrady(k) => return k.to_vec(),
+            Distortion::KannalaBrandt(k) => return k.to_vec(),
+        }
+    }
+
+    /**
+     * Inverts the distortion of a normalized image point and returns the direction of the
+     * incoming ray, None if the point lies outside the valid range of the model
+     */
+    pub fn undistort(&self, p: Vec2) -> Option<Vec3> {
+        match *self {
+            Distortion::None => return Some(p.extend(1.0)),
+            Distortion::BrownConrady([k1, k2, p1, p2, k3]) => {
+                let mut u = p;
+                for _ in 0..UNDISTORT_ITERATIONS {
+                    let r2 = u.length_squared();
+                    let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+                    let delta = Vec2::new(
+                        2.0 * p1 * u.x * u.y + p2 * (r2 + 2.0 * u.x * u.x),
+                        p1 * (r2 + 2.0 * u.y * u.y) + 2.0 * p2 * u.x * u.y,
+                    );
+                    u = (p - delta) / radial;
+                }
+                if !u.is_finite() {
+                    return None;
+                }
+
+                return Some(u.extend(1.0));
+            },
+            Distortion::KannalaBrandt([k1, k2, k3, k4]) => {
+                // solve theta_d = theta * (1 + k1 theta^2 + k2 theta^4 + k3 theta^6 + k4 theta^8) by newton iterations
+                let theta_d = p.length();
+                if theta_d == 0.0 {
+                    return Some(Vec3::Z);
+                }
+
+                let mut theta = theta_d;
+                for _ in 0..UNDISTORT_ITERATIONS {
+                    let t2 = theta * theta;
+                    let f = theta * (1.0 + t2 * (k1 + t2 * (k2 + t2 * (k3 + t2 * k4)))) - theta_d;
+                    let df = 1.0 + t2 * (3.0 * k1 + t2 * (5.0 * k2 + t2 * (7.0 * k3 + t2 * 9.0 * k4)));
+                    theta -= f / df;
+                }
+                if !theta.is_finite() || !(0.0..=std::f32::consts::PI).contains(&theta) {
+                    return None;
+                }
+
+                return Some((p / theta_d * theta.sin()).extend(theta.cos()));
+            },
+        }
+    }
+}
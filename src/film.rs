@@ -0,0 +1,284 @@
+use glam::{Vec3, Vec2};
+use image::{ImageBuffer, Rgb32FImage};
+
+use crate::aov::{Aov, AovSample};
+use crate::filter::Filter;
+use crate::utils::luminance;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FilmPixel {
+    pub sum: Vec3,
+    pub weight: f32,
+    pub lum_sum: f32,
+    pub lum_sq_sum: f32,
+    pub count: f32,
+}
+
+/**
+ * Per-pixel bookkeeping for AOV passes, which are box filtered within the pixel and
+ * keep identifier passes from the sample closest to the pixel center
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct AovPixel {
+    pub count: f32,
+    pub id_dist: f32,
+}
+
+impl Default for AovPixel {
+    fn default() -> AovPixel {
+        AovPixel {
+            count: 0.0,
+            id_dist: f32::MAX,
+        }
+    }
+}
+
+/**
+ * Accumulates filtered radiance samples for the whole image. The image may be split into a
+ * grid of views of view_w x view_h pixels, e.g. the eyes of a stereo pair, and samples
+ * are never filtered across the border of their view
+ */
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub view_w: u32,
+    pub view_h: u32,
+    pub filter: Filter,
+    pub aovs: Vec<Aov>,
+    pixels: Vec<FilmPixel>,
+    aov_pixels: Vec<AovPixel>,
+    aov_values: Vec<Vec3>,
+}
+
+/**
+ * Part of the film written by a single rendering task, includes the border of
+ * neighbouring pixels reached by the reconstruction filter
+ */
+pub struct FilmTile {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+    filter: Filter,
+    aovs: Vec<Aov>,
+    pixels: Vec<FilmPixel>,
+    aov_pixels: Vec<AovPixel>,
+    aov_values: Vec<Vec3>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter, aovs: Vec<Aov>) -> Film {
+        let pixel_count = (width * height) as usize;
+
+        Film {
+            width,
+            height,
+            view_w: width,
+            view_h: height,
+            filter,
+            pixels: vec![FilmPixel::default(); pixel_count],
+            aov_pixels: vec![AovPixel::default(); if aovs.is_empty() { 0 } else { pixel_count }],
+            aov_values: vec![Vec3::ZERO; pixel_count * aovs.len()],
+            aovs,
+        }
+    }
+
+    /**
+     * Creates a tile for samples taken inside the pixel rectangle [x, x + w) x [y, y + h),
+     * which must lie within a single view
+     */
+    pub fn tile(&self, x: u32, y: u32, w: u32, h: u32) -> FilmTile {
+        let r = self.filter.radius();
+        let vx0 = (x / self.view_w * self.view_w) as i32;
+        let vy0 = (y / self.view_h * self.view_h) as i32;
+        let vx1 = (vx0 + self.view_w as i32).min(self.width as i32);
+        let vy1 = (vy0 + self.view_h as i32).min(self.height as i32);
+
+        let x0 = ((x as f32 - r - 0.5).ceil() as i32).max(vx0);
+        let y0 = ((y as f32 - r - 0.5).ceil() as i32).max(vy0);
+        let x1 = (((x + w) as f32 + r - 0.5).floor() as i32 + 1).min(vx1);
+        let y1 = (((y + h) as f32 + r - 0.5).floor() as i32 + 1).min(vy1);
+        let pixel_count = ((x1 - x0).max(0) * (y1 - y0).max(0)) as usize;
+
+        FilmTile {
+            x0,
+            y0,
+            x1,
+            y1,
+            filter: self.filter,
+            aovs: self.aovs.clone(),
+            pixels: vec![FilmPixel::default(); pixel_count],
+            aov_pixels: vec![AovPixel::default(); if self.aovs.is_empty() { 0 } else { pixel_count }],
+            aov_values: vec![Vec3::ZERO; pixel_count * self.aovs.len()],
+        }
+    }
+
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        let aov_count = self.aovs.len();
+
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let src_idx = ((y - tile.y0) * (tile.x1 - tile.x0) + (x - tile.x0)) as usize;
+                let dst_idx = (y as u32 * self.width + x as u32) as usize;
+
+                let src = &tile.pixels[src_idx];
+                let dst = &mut self.pixels[dst_idx];
+                dst.sum += src.sum;
+                dst.weight += src.weight;
+                dst.lum_sum += src.lum_sum;
+                dst.lum_sq_sum += src.lum_sq_sum;
+                dst.count += src.count;
+
+                if aov_count == 0 {
+                    continue;
+                }
+
+                let src_aov = tile.aov_pixels[src_idx];
+                let dst_aov = &mut self.aov_pixels[dst_idx];
+                let closer = src_aov.id_dist < dst_aov.id_dist;
+                for (k, aov) in self.aovs.iter().enumerate() {
+                    let src_value = tile.aov_values[src_idx * aov_count + k];
+                    let dst_value = &mut self.aov_values[dst_idx * aov_count + k];
+                    if !aov.is_id() {
+                        *dst_value += src_value;
+                    } else if closer {
+                        *dst_value = src_value;
+                    }
+                }
+                dst_aov.count += src_aov.count;
+                dst_aov.id_dist = dst_aov.id_dist.min(src_aov.id_dist);
+            }
+        }
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Vec3 {
+        let p = &self.pixels[(y * self.width + x) as usize];
+        if p.weight == 0.0 {
+            return Vec3::ZERO;
+        }
+
+        return (p.sum / p.weight).max(Vec3::ZERO);
+    }
+
+    pub fn get_sample_count(&self, x: u32, y: u32) -> u32 {
+        return self.pixels[(y * self.width + x) as usize].count as u32;
+    }
+
+    /**
+     * Estimated variance of the pixel mean luminance, from the samples taken inside the pixel
+     */
+    pub fn get_variance(&self, x: u32, y: u32) -> f32 {
+        let p = &self.pixels[(y * self.width + x) as usize];
+        if p.count < 2.0 {
+            return 0.0;
+        }
+
+        let mean = p.lum_sum / p.count;
+        let variance = (p.lum_sq_sum / p.count - mean * mean).max(0.0);
+        return variance / p.count;
+    }
+
+    pub fn get_aov_pixel(&self, aov: Aov, x: u32, y: u32) -> Vec3 {
+        let k = self.aovs.iter().position(|a| *a == aov).unwrap();
+        let idx = (y * self.width + x) as usize;
+        let value = self.aov_values[idx * self.aovs.len() + k];
+        let count = self.aov_pixels[idx].count;
+        if aov.is_id() || count == 0.0 {
+            return value;
+        }
+
+        return value / count;
+    }
+
+    /**
+     * Resolves the accumulated samples into an image of linear, unclamped radiance
+     */
+    pub fn to_rgb32f_image(&self) -> Rgb32FImage {
+        let mut img: Rgb32FImage = ImageBuffer::new(self.width, self.height);
+        for (x, y, pix) in img.enumerate_pixels_mut() {
+            let col = self.get_pixel(x, y);
+            *pix = image::Rgb([col.x, col.y, col.z]);
+        }
+
+        return img;
+    }
+
+    /**
+     * Resolves an AOV pass, passes with less than three channels leave the remaining ones at zero
+     */
+    pub fn to_aov_image(&self, aov: Aov) -> Rgb32FImage {
+        let mut img: Rgb32FImage = ImageBuffer::new(self.width, self.height);
+        for (x, y, pix) in img.enumerate_pixels_mut() {
+            let val = self.get_aov_pixel(aov, x, y);
+            *pix = image::Rgb([val.x, val.y, val.z]);
+        }
+
+        return img;
+    }
+}
+
+impl FilmTile {
+    /**
+     * Splats a radiance sample at continuous film position p to every pixel within the filter radius
+     */
+    pub fn add_sample(&mut self, p: Vec2, l: Vec3) {
+        // discard invalid samples so they do not poison the whole pixel
+        if !l.is_finite() {
+            return;
+        }
+
+        let r = self.filter.radius();
+        let px0 = ((p.x - r - 0.5).ceil() as i32).max(self.x0);
+        let py0 = ((p.y - r - 0.5).ceil() as i32).max(self.y0);
+        let px1 = ((p.x + r - 0.5).floor() as i32).min(self.x1 - 1);
+        let py1 = ((p.y + r - 0.5).floor() as i32).min(self.y1 - 1);
+
+        for y in py0..=py1 {
+            for x in px0..=px1 {
+                let w = self.filter.eval(x as f32 + 0.5 - p.x, y as f32 + 0.5 - p.y);
+                let pix = &mut self.pixels[((y - self.y0) * (self.x1 - self.x0) + (x - self.x0)) as usize];
+                pix.sum += l * w;
+                pix.weight += w;
+            }
+        }
+
+        // track luminance moments of the pixel containing the sample for variance estimates
+        let x = p.x.floor() as i32;
+        let y = p.y.floor() as i32;
+        if x >= self.x0 && x < self.x1 && y >= self.y0 && y < self.y1 {
+            let lum = luminance(&l);
+            let pix = &mut self.pixels[((y - self.y0) * (self.x1 - self.x0) + (x - self.x0)) as usize];
+            pix.lum_sum += lum;
+            pix.lum_sq_sum += lum * lum;
+            pix.count += 1.0;
+        }
+    }
+
+    /**
+     * Records the AOV values of a sample at continuous film position p in the pixel containing it
+     */
+    pub fn add_aov_sample(&mut self, p: Vec2, s: &AovSample) {
+        let x = p.x.floor() as i32;
+        let y = p.y.floor() as i32;
+        if self.aovs.is_empty() || x < self.x0 || x >= self.x1 || y < self.y0 || y >= self.y1 {
+            return;
+        }
+
+        let aov_count = self.aovs.len();
+        let idx = ((y - self.y0) * (self.x1 - self.x0) + (x - self.x0)) as usize;
+        let dist = (p - Vec2::new(x as f32 + 0.5, y as f32 + 0.5)).length_squared();
+        let pix = &mut self.aov_pixels[idx];
+        let closer = dist < pix.id_dist;
+
+        for (k, aov) in self.aovs.iter().enumerate() {
+            let value = &mut self.aov_values[idx * aov_count + k];
+            if !aov.is_id() {
+                *value += s.value(*aov);
+            } else if closer {
+                *value = s.value(*aov);
+            }
+        }
+        pix.count += 1.0;
+        pix.id_dist = pix.id_dist.min(dist);
+    }
+}
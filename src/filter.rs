@@ -0,0 +1,78 @@
+use std::f32::consts::PI;
+
+/**
+ * Pixel reconstruction filters, evaluated at an offset from the pixel center
+ * Reference: https://www.pbr-book.org
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum Filter {
+    Box { radius: f32 },
+    Tent { radius: f32 },
+    Gaussian { radius: f32, alpha: f32 },
+    Mitchell { radius: f32, b: f32, c: f32 },
+    BlackmanHarris { radius: f32 },
+}
+
+impl Filter {
+    pub fn from_name(name: &str, radius: Option<f32>) -> Option<Filter> {
+        match name {
+            "box" => return Some(Filter::Box { radius: radius.unwrap_or(0.5) }),
+            "tent" => return Some(Filter::Tent { radius: radius.unwrap_or(1.0) }),
+            "gaussian" => return Some(Filter::Gaussian { radius: radius.unwrap_or(1.5), alpha: 2.0 }),
+            "mitchell" => return Some(Filter::Mitchell { radius: radius.unwrap_or(2.0), b: 1.0 / 3.0, c: 1.0 / 3.0 }),
+            "blackman-harris" => return Some(Filter::BlackmanHarris { radius: radius.unwrap_or(1.5) }),
+            _ => return None,
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Filter::Box { radius } => return radius,
+            Filter::Tent { radius } => return radius,
+            Filter::Gaussian { radius, .. } => return radius,
+            Filter::Mitchell { radius, .. } => return radius,
+            Filter::BlackmanHarris { radius } => return radius,
+        }
+    }
+
+    pub fn eval(&self, x: f32, y: f32) -> f32 {
+        let r = self.radius();
+        if x.abs() > r || y.abs() > r {
+            return 0.0;
+        }
+
+        match *self {
+            Filter::Box { .. } => return 1.0,
+            Filter::Tent { radius } => return (radius - x.abs()) * (radius - y.abs()),
+            Filter::Gaussian { radius, alpha } => {
+                let g = |d: f32| ((-alpha * d * d).exp() - (-alpha * radius * radius).exp()).max(0.0);
+                return g(x) * g(y);
+            },
+            Filter::Mitchell { radius, b, c } => {
+                return mitchell_1d(2.0 * x / radius, b, c) * mitchell_1d(2.0 * y / radius, b, c);
+            },
+            Filter::BlackmanHarris { radius } => {
+                return blackman_harris_1d(x, radius) * blackman_harris_1d(y, radius);
+            },
+        }
+    }
+}
+
+fn mitchell_1d(x: f32, b: f32, c: f32) -> f32 {
+    let x = x.abs();
+    if x > 1.0 {
+        return ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0;
+    }
+
+    return ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+        + (6.0 - 2.0 * b)) / 6.0;
+}
+
+fn blackman_harris_1d(x: f32, radius: f32) -> f32 {
+    // window spans the whole filter support, t = 0.5 at the pixel center
+    let t = x / (2.0 * radius) + 0.5;
+
+    return 0.35875 - 0.48829 * (2.0 * PI * t).cos() + 0.14128 * (4.0 * PI * t).cos()
+        - 0.01168 * (6.0 * PI * t).cos();
+}
@@ -0,0 +1,172 @@
+use bvh::aabb::{AABB, Bounded};
+use bvh::bounding_hierarchy::BHShape;
+use bvh::ray::Ray;
+use glam::{Mat3, Mat4, Vec3};
+
+use crate::intersection::Intersection;
+use crate::mesh::Mesh;
+use crate::transform::AnimatedTransform;
+
+/**
+ * Placement of a mesh in the scene, the top level BVH is built over instances. Animated
+ * instances are placed by motion(time) * offset, their bounds enclose the whole shutter interval
+ */
+pub struct Instance {
+    pub mesh: usize,
+    pub obj_offset: u32,
+    pub obj_to_world: Mat4,
+    pub world_to_obj: Mat4,
+    pub nrm_to_world: Mat3,
+    pub motion: Option<AnimatedTransform>,
+    pub offset: Mat4,
+    pub aabb: AABB,
+    pub node_idx: usize, // for BVH
+}
+
+// time steps per shutter interval when bounding animated instances
+const MOTION_BOUND_STEPS: u32 = 32;
+
+fn corners(aabb: &AABB) -> [Vec3; 8] {
+    return std::array::from_fn(|i| Vec3::new(
+        if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+        if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+        if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+    ));
+}
+
+impl Instance {
+    pub fn new(mesh_idx: usize, mesh: &Mesh, obj_to_world: Mat4, obj_offset: u32) -> Instance {
+        let world_to_obj = obj_to_world.inverse();
+
+        // bounds of the transformed corners of the mesh bounds
+        let mut aabb = AABB::empty();
+        for corner in corners(&mesh.aabb) {
+            aabb.grow_mut(&obj_to_world.transform_point3(corner));
+        }
+
+        Instance {
+            mesh: mesh_idx,
+            obj_offset,
+            obj_to_world,
+            world_to_obj,
+            nrm_to_world: Mat3::from_mat4(world_to_obj).transpose(),
+            motion: None,
+            offset: Mat4::IDENTITY,
+            aabb,
+            node_idx: 0,
+        }
+    }
+
+    /**
+     * Places a mesh by a keyframed transform, bounded over the shutter interval [open, close]
+     */
+    pub fn animated(mesh_idx: usize, mesh: &Mesh, motion: AnimatedTransform, offset: Mat4, obj_offset: u32, open: f32, close: f32) -> Instance {
+        let mut instance = Instance::new(mesh_idx, mesh, motion.eval(open).to_mat4() * offset, obj_offset);
+        instance.motion = Some(motion);
+        instance.offset = offset;
+        instance.update_bounds(mesh, open, close);
+        return instance;
+    }
+
+    /**
+     * Bounds an animated instance over the shutter interval [open, close]
+     */
+    pub fn update_bounds(&mut self, mesh: &Mesh, open: f32, close: f32) {
+        let motion = match self.motion {
+            Some(ref motion) => motion,
+            None => return,
+        };
+
+        // sample the motion at fixed steps and at every key within the shutter interval
+        let mut times: Vec<f32> = (0..=MOTION_BOUND_STEPS)
+            .map(|i| open + (close - open) * i as f32 / MOTION_BOUND_STEPS as f32)
+            .collect();
+        times.extend(motion.keys.iter().map(|k| k.time).filter(|t| *t > open && *t < close));
+        times.sort_by(f32::total_cmp);
+
+        // corners rotate on arcs between samples, pad the bounds by the largest deviation from the chord
+        let offset_corners = corners(&mesh.aabb).map(|c| self.offset.transform_point3(c));
+        let radius = offset_corners.iter().map(|c| c.length()).fold(0.0, f32::max);
+        let mut aabb = AABB::empty();
+        for (i, time) in times.iter().enumerate() {
+            let trf = motion.eval(*time);
+            for corner in &offset_corners {
+                aabb.grow_mut(&trf.to_mat4().transform_point3(*corner));
+            }
+
+            if i > 0 {
+                let prev = motion.eval(times[i - 1]);
+                let half_angle = prev.ori.angle_between(trf.ori) * 0.5;
+                let pad = radius * prev.scl.max(trf.scl).abs().max_element() * (1.0 - half_angle.cos());
+                aabb.min -= Vec3::splat(pad);
+                aabb.max += Vec3::splat(pad);
+            }
+        }
+
+        self.obj_to_world = motion.eval(open).to_mat4() * self.offset;
+        self.world_to_obj = self.obj_to_world.inverse();
+        self.nrm_to_world = Mat3::from_mat4(self.world_to_obj).transpose();
+        self.aabb = aabb;
+    }
+
+    /**
+     * Intersects the mesh in object space at the given time and returns the closest hit nearer
+     * than max_t in world space
+     */
+    pub fn intersect<'a>(&self, ray: &Ray, mesh: &'a Mesh, max_t: f32, time: f32) -> Option<Intersection<'a>> {
+        let (obj_to_world, world_to_obj, nrm_to_world) = match self.motion {
+            Some(ref motion) => {
+                let obj_to_world = motion.eval(time).to_mat4() * self.offset;
+                let world_to_obj = obj_to_world.inverse();
+                (obj_to_world, world_to_obj, Mat3::from_mat4(world_to_obj).transpose())
+            },
+            None => (self.obj_to_world, self.world_to_obj, self.nrm_to_world),
+        };
+
+        let obj_ray = Ray::new(
+            world_to_obj.transform_point3(ray.origin),
+            world_to_obj.transform_vector3(ray.direction),
+        );
+        let hits = mesh.bvh.as_ref().unwrap().traverse(&obj_ray, &mesh.shapes);
+
+        let mut hit_dist = max_t;
+        let mut hit_isect: Option<Intersection> = None;
+        for hit in hits {
+            if let Some(mut hit_result) = hit.intersect(&obj_ray) {
+                // distances differ between spaces under scaling, compare them in world space
+                let pos = obj_to_world.transform_point3(hit_result.pos);
+                let t = (pos - ray.origin).dot(ray.direction);
+                if t < hit_dist {
+                    hit_dist = t;
+                    hit_result.t = t;
+                    hit_result.pos = pos;
+                    hit_result.nrm = (nrm_to_world * hit_result.nrm).normalize();
+                    hit_result.tan = obj_to_world.transform_vector3(hit_result.tan);
+                    hit_result.bitan = obj_to_world.transform_vector3(hit_result.bitan);
+                    hit_result.dpdu = obj_to_world.transform_vector3(hit_result.dpdu);
+                    hit_result.dpdv = obj_to_world.transform_vector3(hit_result.dpdv);
+                    hit_result.obj += self.obj_offset;
+                    hit_isect = Some(hit_result);
+                }
+            }
+        }
+
+        return hit_isect;
+    }
+}
+
+impl Bounded for Instance {
+    fn aabb(&self) -> AABB {
+        return self.aabb;
+    }
+}
+
+impl BHShape for Instance {
+    fn set_bh_node_index(&mut self, index: usize) {
+        self.node_idx = index;
+    }
+
+    fn bh_node_index(&self) -> usize {
+        self.node_idx
+    }
+}
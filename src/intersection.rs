@@ -1,10 +1,15 @@
 use glam::{Vec3, Vec2};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Intersection<'a> {
     pub t: f32,
     pub pos: Vec3,
     pub nrm: Vec3,
+    pub tan: Vec3,
+    pub bitan: Vec3,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub tex: Vec2,
     pub mat: &'a String,
+    pub obj: u32,
 }
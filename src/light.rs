@@ -16,6 +16,7 @@ pub struct PointLight {
 pub trait Light {
     fn eval_we(&self, p: &Vec3) -> Vec3;
     fn eval_le(&self, we: &Vec3) -> Vec3;
+    fn eval_dist(&self, we: &Vec3) -> f32;
 }
 
 impl Light for DirLight {
@@ -26,6 +27,10 @@ impl Light for DirLight {
     fn eval_le(&self, _we: &Vec3) -> Vec3 {
         return self.emission;
     }
+
+    fn eval_dist(&self, _we: &Vec3) -> f32 {
+        return f32::MAX;
+    }
 }
 
 impl Light for PointLight {
@@ -38,4 +43,8 @@ impl Light for PointLight {
         let a = 1.0 / (self.c + self.l * d + self.q * d * d);
         return self.emission * a;
     }
+
+    fn eval_dist(&self, we: &Vec3) -> f32 {
+        return we.length();
+    }
 }
@@ -1,42 +1,98 @@
+#![allow(clippy::needless_return, clippy::identity_op)]
+
+pub mod animation;
+#[cfg(test)]
+mod bsdf_test;
+pub mod aov;
 pub mod camera;
+pub mod denoise;
+pub mod distortion;
+pub mod film;
+pub mod filter;
+pub mod instance;
 pub mod intersection;
 pub mod material;
 pub mod light;
+pub mod mesh;
+pub mod microfacet;
+pub mod output;
+pub mod principled;
+pub mod procedural;
 pub mod renderer;
+pub mod sampler;
 pub mod scene;
+pub mod scene_desc;
+pub mod sensor;
+pub mod shader_graph;
+pub mod tangent;
+pub mod texture;
+pub mod texture_cache;
+pub mod tonemap;
 pub mod triangle;
 pub mod transform;
 pub mod utils;
 pub mod vertex;
 
 use bvh::aabb::Bounded;
-use bvh::{bvh::BVH};
 use clap::{arg, Command};
-use glam::{Vec3, Vec2};
-use image::{ImageBuffer, RgbImage, ImageFormat, Rgb};
-use image::io::Reader as ImageReader;
-use material::{Texture, TextureType};
-use std::fs;
-use std::path::PathBuf;
+use glam::{Mat3, Mat4, Vec3, Vec2};
+use gltf::json::material::AlphaMode;
+use gltf::json::texture::WrappingMode;
+use gltf::mesh::Mode;
+use material::{Surface, Texture, TextureMap, TextureOptions, TextureType, PRINCIPLED_IOR};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
 use std::thread::{self, ScopedJoinHandle};
 
+use crate::camera::{Camera, Projection};
 use crate::light::{DirLight, PointLight};
+use crate::transform::Transform;
 use crate::scene::Scene;
+use crate::scene_desc::{LightDesc, SceneDesc};
+use crate::tangent::generate_tangents;
+use crate::texture::{Channels, ColorSpace, TextureFilter, WrapMode};
+use crate::texture_cache::{CachedTexture, TextureCache};
 use crate::{
-    camera::Camera,
+    aov::{Aov, AovSample},
+    denoise::{Denoiser, DENOISE_AOVS},
+    film::{Film, FilmTile},
+    filter::Filter,
     material::Material,
-    renderer::Raytracer,
+    mesh::Mesh,
+    renderer::Renderer,
+    sampler::Sampler,
+    output::{Output, OutputFormat},
+    tonemap::ToneMapper,
     triangle::Triangle,
     vertex::Vertex,
 };
 
-fn load_texture(model_file_name: &str, texture_name: &str, texture_type: TextureType) -> Texture {
+/**
+ * Loads the texture of an MTL texture statement, the file name may be preceded by options
+ */
+fn load_texture(textures: &mut TextureCache, model_file_name: &str, texture_statement: &str, texture_type: TextureType) -> TextureMap {
+    let (options, texture_name) = TextureOptions::parse(texture_statement);
+    let texture_type = match (texture_type, options.normal_map) {
+        (TextureType::Bump, Some(true)) => TextureType::Normal,
+        (TextureType::Normal, Some(false)) => TextureType::Bump,
+        (texture_type, _) => texture_type,
+    };
+
+    return TextureMap {
+        texture: load_texture_image(textures, model_file_name, &texture_name, texture_type, options.color_space, options.channel.is_some()),
+        options,
+    };
+}
+
+fn load_texture_image(textures: &mut TextureCache, model_file_name: &str, texture_name: &str, texture_type: TextureType, color_space: Option<ColorSpace>, single_channel: bool) -> Texture {
     // return None if nothing to load
     if texture_name.is_empty() {
         return Texture::None;
     }
 
-    // attempt to load file into memory
+    // register the file with the cache, it is decoded on the first lookup
     let file_path = PathBuf::from(model_file_name)
         .parent()
         .unwrap()
@@ -44,40 +100,102 @@ fn load_texture(model_file_name: &str, texture_name: &str, texture_type: Texture
         .to_str()
         .unwrap()
         .replace('\\', "/");
-    let image = ImageReader::open(file_path)
-        .unwrap()
-        .decode()
-        .unwrap();
+    let file_path = Path::new(&file_path);
 
-    // return type based on condition
+    // return type based on condition, color maps are sRGB encoded unless the statement says otherwise
+    let color_space_or = |default: ColorSpace| color_space.unwrap_or(default);
+    // scalar maps read their luminance unless -imfchan picks a single channel, which then needs all of them
+    let (scalar, alpha) = match single_channel {
+        true => (Channels::Rgba, Channels::Rgba),
+        false => (Channels::Luma, Channels::LumaAlpha),
+    };
     match texture_type {
-        TextureType::Diffuse => {
-            println!("loading diffuse texture ...");
-
-            match image.color().has_alpha() {
-                true => return Texture::Diffuse(image.to_rgba8()),
-                false => {
-                    let mut image_rgb = image.to_rgba8();
-                    image_rgb.pixels_mut().for_each(|p| p[3] = 255);
-                    return Texture::Diffuse(image_rgb);
-                },
-            }
-        },
-        TextureType::Alpha => {
-            println!("loading alpha texture ...");
+        TextureType::Diffuse => return Texture::Diffuse(textures.get(file_path, Channels::Rgba, color_space_or(ColorSpace::Srgb))),
+        TextureType::Alpha => return Texture::Alpha(textures.get(file_path, alpha, color_space_or(ColorSpace::Linear))),
+        TextureType::Roughness => return Texture::Scalar(textures.get(file_path, scalar, color_space_or(ColorSpace::Linear))),
+        TextureType::Metallic => return Texture::Scalar(textures.get(file_path, scalar, color_space_or(ColorSpace::Linear))),
+        TextureType::Normal => return Texture::Normal(textures.get(file_path, Channels::Rgb, color_space_or(ColorSpace::Linear))),
+        TextureType::Bump => return Texture::Bump(textures.get(file_path, scalar, color_space_or(ColorSpace::Linear))),
+        TextureType::Specular => return Texture::Color(textures.get(file_path, Channels::Rgb, color_space_or(ColorSpace::Srgb))),
+        TextureType::Shininess => return Texture::Scalar(textures.get(file_path, scalar, color_space_or(ColorSpace::Linear))),
+        TextureType::Emission => return Texture::Color(textures.get(file_path, Channels::Rgb, color_space_or(ColorSpace::Srgb))),
+        TextureType::Ambient => return Texture::Color(textures.get(file_path, Channels::Rgb, color_space_or(ColorSpace::Srgb))),
+        TextureType::None => return Texture::None,
+    }
+}
 
-            return Texture::Alpha(image.to_luma_alpha8());
-        },
-        TextureType::None => {
-            println!("loading none texture ...");
+// MTL statements selecting the principled BSDF, the PBR extensions for sheen, clearcoat and
+// anisotropy followed by the remaining principled parameters by name
+const PRINCIPLED_PARAMS: [&str; 9] = ["Ps", "Pc", "Pcr", "aniso", "anisor", "subsurface", "specular_tint", "sheen_tint", "transmission"];
 
-            return Texture::None;
-        },
+/**
+ * Parses a color from MTL statements unknown to tobj, such as the emission Ke or the
+ * transmission filter Tf. A single value applies to all channels
+ */
+fn mtl_color(mat: &tobj::Material, key: &str, default: Vec3) -> Vec3 {
+    let c = match mat.unknown_param.get(key) {
+        Some(value) => value.split_whitespace()
+            .filter_map(|s| s.parse::<f32>().ok())
+            .collect::<Vec<_>>(),
+        None => return default,
+    };
+
+    match c.len() {
+        0 => return default,
+        1 | 2 => return Vec3::splat(c[0]),
+        _ => return Vec3::new(c[0], c[1], c[2]),
     }
 }
 
-fn load_model(file_name: &str, scene: &mut Scene) {
+/**
+ * Parses a scalar from MTL statements unknown to tobj, such as the PBR extensions
+ */
+fn mtl_scalar(mat: &tobj::Material, key: &str, default: f32) -> f32 {
+    return mtl_color(mat, key, Vec3::splat(default)).x;
+}
+
+/**
+ * Adds a triangle list to the mesh, with face normals where the vertices have none and tangents
+ * for normal and bump mapping. Degenerate triangles are discarded
+ */
+fn add_triangles(mesh: &mut Mesh, mut vertices: Vec<Vertex>, mat: &str, obj_id: u32) {
+    for v in vertices.chunks_exact_mut(3) {
+        // calculate normals if not set
+        if v[0].nrm.length() == 0.0 && v[1].nrm.length() == 0.0 && v[2].nrm.length() == 0.0 {
+            let edge_a = v[0].pos - v[1].pos;
+            let edge_b = v[0].pos - v[2].pos;
+            let nrm = edge_a.cross(edge_b).normalize();
+            v[0].nrm = nrm;
+            v[1].nrm = nrm;
+            v[2].nrm = nrm;
+        }
+    }
+
+    // tangents for normal and bump mapping
+    generate_tangents(&mut vertices);
+
+    for v in vertices.chunks_exact(3) {
+        let t = Triangle {
+            vrt: [
+                v[0],
+                v[1],
+                v[2],
+            ],
+            mat: mat.to_string(),
+            obj: obj_id,
+            node_idx: 0,
+        };
+
+        // validate triangle, discard invalid triangles
+        if Bounded::aabb(&t).surface_area() > 0.0 {
+            mesh.shapes.push(t);
+        }
+    }
+}
+
+fn load_model(file_name: &str, scene: &mut Scene) -> Mesh {
     println!("loading models and materials...");
+    let mut mesh = Mesh::new(file_name.to_string());
     let tobj_load_opts = tobj::LoadOptions {
         triangulate: true,
         ignore_lines: true,
@@ -114,78 +232,576 @@ fn load_model(file_name: &str, scene: &mut Scene) {
         for (k, v) in &mat.unknown_param {
             println!("    unknown_param[{}] = {}", k, v);
         }
-        let mat_emission = mat.unknown_param.get("Ke")
-            .map_or("0 0 0", String::as_str)    
-            .split(" ")
-            .map(|s| s.parse::<f32>().unwrap())
-            .collect::<Vec<_>>();
-        println!("  material.emission = {} {} {}", mat_emission[0], mat_emission[1], mat_emission[2]);
+        // emission textures are scaled by Ke, exporters write a black Ke next to texture-driven emission
+        let mat_emission_texture = mat.unknown_param.get("map_Ke").map_or("", String::as_str);
+        let mut mat_emission = mtl_color(mat, "Ke", Vec3::ZERO);
+        if !mat_emission_texture.is_empty() && mat_emission == Vec3::ZERO {
+            mat_emission = Vec3::ONE;
+        }
+        let mat_transmission_filter = mtl_color(mat, "Tf", Vec3::ONE);
+        let mat_roughness_texture = mat.unknown_param.get("map_Pr").map_or("", String::as_str);
+        let mat_metallic_texture = mat.unknown_param.get("map_Pm").map_or("", String::as_str);
+        // norm names a tangent space normal map and map_Bump or bump a height map, norm wins if both are given
+        let (mat_normal_texture, mat_normal_type) = match mat.unknown_param.get("norm") {
+            Some(texture) => (texture.as_str(), TextureType::Normal),
+            None => (mat.normal_texture.as_str(), TextureType::Bump),
+        };
+        let mat_pbr = ["Pr", "Pm", "map_Pr", "map_Pm"].iter().any(|k| mat.unknown_param.contains_key(*k));
+        let mat_principled = PRINCIPLED_PARAMS.iter().any(|k| mat.unknown_param.contains_key(*k));
+        let mat_surface = Surface::from_mtl(mat.illumination_model, mat_pbr, mat_principled);
+        println!("  material.emission = {} {} {}", mat_emission.x, mat_emission.y, mat_emission.z);
+        println!("  material.surface = {:?}, ior = {}, dissolve = {}", mat_surface, mat.optical_density, mat.dissolve);
+        println!("  material.roughness_texture = {}", mat_roughness_texture);
+        println!("  material.metallic_texture = {}", mat_metallic_texture);
         println!("  material.diffuse_texture = {}", &mat.diffuse_texture);
         println!("  material.alpha_texture = {}", &mat.dissolve_texture);
+        println!("  material.normal_texture = {}", mat_normal_texture);
+        println!("  material.specular_texture = {}", &mat.specular_texture);
+        println!("  material.shininess_texture = {}", &mat.shininess_texture);
+        println!("  material.emission_texture = {}", mat_emission_texture);
+        println!("  material.ambient_texture = {}", &mat.ambient_texture);
 
         if !scene.materials.contains_key(&mat.name) {
             scene.materials.insert(mat.name.clone(), Material {
+                id: scene.materials.len() as u32,
                 ambient: Vec3::new(mat.ambient[0], mat.ambient[1], mat.ambient[2]),
                 diffuse: Vec3::new(mat.diffuse[0], mat.diffuse[1], mat.diffuse[2]),
                 specular: Vec3::new(mat.specular[0], mat.specular[1], mat.specular[2]),
                 shininess: mat.shininess,
-                emission: Vec3::new(mat_emission[0], mat_emission[1], mat_emission[2]),
-                diffuse_texture: load_texture(file_name, &mat.diffuse_texture, TextureType::Diffuse),
-                alpha_texture: load_texture(file_name, &mat.dissolve_texture, TextureType::Alpha),
+                emission: mat_emission,
+                surface: mat_surface,
+                // tobj reads a missing Ni as 1, which leaves the principled BSDF without specular reflection
+                ior: match mat_surface {
+                    Surface::Principled if mat.optical_density <= 1.0 => PRINCIPLED_IOR,
+                    _ if mat.optical_density > 0.0 => mat.optical_density,
+                    _ => 1.0,
+                },
+                dissolve: mat.dissolve.clamp(0.0, 1.0),
+                alpha_cutoff: 0.0,
+                transmission_filter: mat_transmission_filter,
+                roughness: mtl_scalar(mat, "Pr", 1.0),
+                metallic: mtl_scalar(mat, "Pm", 0.0),
+                subsurface: mtl_scalar(mat, "subsurface", 0.0),
+                specular_tint: mtl_scalar(mat, "specular_tint", 0.0),
+                anisotropic: mtl_scalar(mat, "aniso", 0.0),
+                anisotropic_rotation: mtl_scalar(mat, "anisor", 0.0),
+                sheen: mtl_scalar(mat, "Ps", 0.0),
+                sheen_tint: mtl_scalar(mat, "sheen_tint", 0.5),
+                clearcoat: mtl_scalar(mat, "Pc", 0.0),
+                clearcoat_roughness: mtl_scalar(mat, "Pcr", 0.03),
+                transmission: mtl_scalar(mat, "transmission", 0.0),
+                diffuse_texture: load_texture(&mut scene.textures, file_name, &mat.diffuse_texture, TextureType::Diffuse),
+                alpha_texture: load_texture(&mut scene.textures, file_name, &mat.dissolve_texture, TextureType::Alpha),
+                roughness_texture: load_texture(&mut scene.textures, file_name, mat_roughness_texture, TextureType::Roughness),
+                metallic_texture: load_texture(&mut scene.textures, file_name, mat_metallic_texture, TextureType::Metallic),
+                normal_texture: load_texture(&mut scene.textures, file_name, mat_normal_texture, mat_normal_type),
+                specular_texture: load_texture(&mut scene.textures, file_name, &mat.specular_texture, TextureType::Specular),
+                shininess_texture: load_texture(&mut scene.textures, file_name, &mat.shininess_texture, TextureType::Shininess),
+                emission_texture: load_texture(&mut scene.textures, file_name, mat_emission_texture, TextureType::Emission),
+                ambient_texture: load_texture(&mut scene.textures, file_name, &mat.ambient_texture, TextureType::Ambient),
+                graph: None,
             });
         }
 
+        let obj_id = mesh.objects.len() as u32;
+        mesh.objects.push(m.name.clone());
+
         let mut vertices: Vec<Vertex> = Vec::new();
         for i in 0..m.mesh.indices.len() {
             let p_offset = (m.mesh.indices[i] * 3) as usize;
             let pos = Vec3::new(m.mesh.positions[p_offset + 0], m.mesh.positions[p_offset + 1], m.mesh.positions[p_offset + 2]);
 
             let mut nrm = Vec3::new(0.0, 0.0, 0.0);
-            if m.mesh.normal_indices.len() > 0 {
+            if !m.mesh.normal_indices.is_empty() {
                 let n_offset = (m.mesh.normal_indices[i] * 3) as usize;
                 nrm = Vec3::new(m.mesh.normals[n_offset + 0], m.mesh.normals[n_offset + 1], m.mesh.normals[n_offset + 2]);
             }
 
             let mut tex = Vec2::new(0.0, 0.0);
-            if m.mesh.texcoord_indices.len() > 0 {
+            if !m.mesh.texcoord_indices.is_empty() {
                 let t_offset = (m.mesh.texcoord_indices[i] * 2) as usize;
                 tex = Vec2::new(m.mesh.texcoords[t_offset + 0], m.mesh.texcoords[t_offset + 1]);
             }
 
-            vertices.push(Vertex {
-                pos,
-                nrm,
-                tex,
-            });
+            vertices.push(Vertex::new(pos, nrm, tex));
+        }
+
+        add_triangles(&mut mesh, vertices, &mat.name, obj_id);
+    }
+
+    mesh.build();
+    return mesh;
+}
+
+/**
+ * Image of a glTF file, a file next to it or encoded image data from a buffer or a data URI,
+ * named after the file and index to be shared in the texture cache
+ */
+enum GltfImage {
+    File(PathBuf),
+    Embedded(String, Arc<[u8]>),
+}
+
+fn gltf_images(document: &gltf::Document, file_name: &str, buffers: &[gltf::buffer::Data]) -> Vec<GltfImage> {
+    let base = Path::new(file_name).parent().unwrap();
+    let mut images = Vec::new();
+    for image in document.images() {
+        let name = format!("{}#image{}", file_name, image.index());
+        match image.source() {
+            gltf::image::Source::View { view, .. } => {
+                let buffer = &buffers[view.buffer().index()];
+                images.push(GltfImage::Embedded(name, Arc::from(&buffer[view.offset()..view.offset() + view.length()])));
+            },
+            gltf::image::Source::Uri { uri, .. } => match uri.strip_prefix("data:") {
+                Some(data_uri) => {
+                    let data = data_uri.split_once(";base64,").map_or("", |(_, data)| data);
+                    let data = base64::decode(data)
+                        .unwrap_or_else(|err| panic!("failed to decode data URI of {}: {}", name, err));
+                    images.push(GltfImage::Embedded(name, Arc::from(data)));
+                },
+                None => {
+                    let path = urlencoding::decode(uri).map_or_else(|_| base.join(uri), |path| base.join(path.as_ref()));
+                    images.push(GltfImage::File(path));
+                },
+            },
         }
-        
-        for v in vertices.chunks_exact_mut(3) {
-            // calculate normals if not set
-            if v[0].nrm.length() == 0.0 && v[1].nrm.length() == 0.0 && v[2].nrm.length() == 0.0 {
-                let edge_a = v[0].pos - v[1].pos;
-                let edge_b = v[0].pos - v[2].pos;
-                let nrm = edge_a.cross(edge_b).normalize();
-                v[0].nrm = nrm;
-                v[1].nrm = nrm;
-                v[2].nrm = nrm;
+    }
+
+    return images;
+}
+
+/**
+ * Texture map of a glTF texture reference, wrapping as its sampler does. Samplers wrapping u and
+ * v differently repeat the texture, only the first texture coordinate set is read
+ */
+fn load_gltf_texture(textures: &mut TextureCache, images: &[GltfImage], info: (gltf::Texture, u32), channels: Channels, color_space: ColorSpace, kind: fn(CachedTexture) -> Texture) -> TextureMap {
+    let (texture, tex_coord) = info;
+    if tex_coord != 0 {
+        println!("warning: texture {} reads texture coordinate set {}, the first set is used", texture.index(), tex_coord);
+    }
+
+    let wrap_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => WrapMode::Clamp,
+        WrappingMode::MirroredRepeat => WrapMode::Mirror,
+        WrappingMode::Repeat => WrapMode::Repeat,
+    };
+    let sampler = texture.sampler();
+    let wrap = match (sampler.wrap_s(), sampler.wrap_t()) {
+        (wrap_s, wrap_t) if wrap_s == wrap_t => wrap_mode(wrap_s),
+        _ => {
+            println!("warning: texture {} wraps u and v differently, it is repeated", texture.index());
+            WrapMode::Repeat
+        },
+    };
+
+    let cached = match images[texture.source().index()] {
+        GltfImage::File(ref path) => textures.get(path, channels, color_space),
+        GltfImage::Embedded(ref name, ref data) => textures.get_embedded(name, data.clone(), channels, color_space),
+    };
+    return TextureMap {
+        texture: kind(cached),
+        options: TextureOptions { wrap, ..TextureOptions::default() },
+    };
+}
+
+fn no_texture() -> TextureMap {
+    return TextureMap { texture: Texture::None, options: TextureOptions::default() };
+}
+
+/**
+ * Metallic-roughness material of a glTF material. Texture maps are scaled by their factors: the
+ * blue channel of the packed metallic-roughness texture holds the metalness and green the
+ * roughness, the red channel of the occlusion texture is blended in by its strength. Blended
+ * materials are partially transparent by the base color alpha, masked ones cut away texels
+ * whose alpha scaled by it is below the cutoff
+ * Reference: https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#materials
+ */
+fn load_gltf_material(textures: &mut TextureCache, images: &[GltfImage], material: &gltf::Material, id: u32) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let base_color = pbr.base_color_factor();
+    let alpha_mode = material.alpha_mode();
+
+    // opaque materials ignore the alpha of the base color texture
+    let base_channels = if alpha_mode == AlphaMode::Opaque { Channels::Rgb } else { Channels::Rgba };
+    let diffuse_texture = pbr.base_color_texture()
+        .map_or_else(no_texture, |info| load_gltf_texture(textures, images, (info.texture(), info.tex_coord()), base_channels, ColorSpace::Srgb, Texture::Color));
+
+    // the alpha test reads the texture alone, the cutoff is divided by the alpha factor instead
+    let cutoff = material.alpha_cutoff().unwrap_or(0.5);
+    let alpha_cutoff = if alpha_mode == AlphaMode::Mask { cutoff / base_color[3].max(f32::MIN_POSITIVE) } else { 0.0 };
+
+    let (mut metallic_texture, mut roughness_texture) = match pbr.metallic_roughness_texture() {
+        Some(info) => {
+            let map = |textures: &mut TextureCache| load_gltf_texture(textures, images, (info.texture(), info.tex_coord()), Channels::Rgb, ColorSpace::Linear, Texture::Scalar);
+            (map(textures), map(textures))
+        },
+        None => (no_texture(), no_texture()),
+    };
+    metallic_texture.options.channel = Some(2);
+    metallic_texture.options.gain = pbr.metallic_factor();
+    roughness_texture.options.channel = Some(1);
+    roughness_texture.options.gain = pbr.roughness_factor();
+
+    let normal_texture = material.normal_texture()
+        .map_or_else(no_texture, |normal| {
+            let mut map = load_gltf_texture(textures, images, (normal.texture(), normal.tex_coord()), Channels::Rgb, ColorSpace::Linear, Texture::Normal);
+            map.options.bump_multiplier = normal.scale();
+            map
+        });
+
+    let ambient_texture = material.occlusion_texture()
+        .map_or_else(no_texture, |occlusion| {
+            let mut map = load_gltf_texture(textures, images, (occlusion.texture(), occlusion.tex_coord()), Channels::Rgb, ColorSpace::Linear, Texture::Color);
+            map.options.channel = Some(0);
+            map.options.base = 1.0 - occlusion.strength();
+            map.options.gain = occlusion.strength();
+            map
+        });
+
+    let emission_texture = material.emissive_texture()
+        .map_or_else(no_texture, |info| load_gltf_texture(textures, images, (info.texture(), info.tex_coord()), Channels::Rgb, ColorSpace::Srgb, Texture::Color));
+
+    let mat = Material {
+        id,
+        ambient: Vec3::ONE,
+        diffuse: Vec3::new(base_color[0], base_color[1], base_color[2]),
+        specular: Vec3::ZERO,
+        shininess: 0.0,
+        emission: Vec3::from(material.emissive_factor()),
+        surface: Surface::MetallicRoughness,
+        ior: PRINCIPLED_IOR,
+        dissolve: match alpha_mode {
+            AlphaMode::Blend => base_color[3],
+            AlphaMode::Mask if base_color[3] < cutoff => 0.0,
+            _ => 1.0,
+        },
+        alpha_cutoff,
+        transmission_filter: Vec3::ONE,
+        roughness: pbr.roughness_factor(),
+        metallic: pbr.metallic_factor(),
+        subsurface: 0.0,
+        specular_tint: 0.0,
+        anisotropic: 0.0,
+        anisotropic_rotation: 0.0,
+        sheen: 0.0,
+        sheen_tint: 0.5,
+        clearcoat: 0.0,
+        clearcoat_roughness: 0.03,
+        transmission: 0.0,
+        diffuse_texture,
+        alpha_texture: no_texture(),
+        roughness_texture,
+        metallic_texture,
+        normal_texture,
+        specular_texture: no_texture(),
+        shininess_texture: no_texture(),
+        emission_texture,
+        ambient_texture,
+        graph: None,
+    };
+    println!("  material.base_color = {} {} {} {}", base_color[0], base_color[1], base_color[2], base_color[3]);
+    println!("  material.metallic = {}, roughness = {}, alpha_mode = {:?}", mat.metallic, mat.roughness, alpha_mode);
+    println!("  material.emission = {} {} {}", mat.emission.x, mat.emission.y, mat.emission.z);
+    return mat;
+}
+
+/**
+ * Loads the meshes of the default scene of a glTF or GLB file, or of its first scene, with the
+ * node transforms applied. The mesh of each node becomes an object, primitives other than
+ * triangle lists are skipped. Texture coordinates are flipped to have v point up as in OBJ files
+ * Reference: https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html
+ */
+fn load_gltf(file_name: &str, scene: &mut Scene) -> Mesh {
+    println!("loading glTF models and materials...");
+    let mut mesh = Mesh::new(file_name.to_string());
+    let gltf = gltf::Gltf::open(file_name)
+        .expect("  failed to load target glTF file");
+    let buffers = gltf::import_buffers(&gltf.document, Path::new(file_name).parent(), gltf.blob.clone())
+        .expect("  failed to load glTF buffers");
+    let images = gltf_images(&gltf.document, file_name, &buffers);
+
+    let nodes: Vec<gltf::Node> = match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+        Some(gltf_scene) => gltf_scene.nodes().collect(),
+        None => {
+            println!("warning: {} contains no scene", file_name);
+            Vec::new()
+        },
+    };
+
+    // children are placed relative to their parent node
+    let mut stack: Vec<(gltf::Node, Mat4)> = nodes.into_iter().map(|node| (node, Mat4::IDENTITY)).collect();
+    while let Some((node, parent)) = stack.pop() {
+        let obj_to_world = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+        stack.extend(node.children().map(|child| (child, obj_to_world)));
+        let gltf_mesh = match node.mesh() {
+            Some(gltf_mesh) => gltf_mesh,
+            None => continue,
+        };
+
+        // mirroring transforms turn the triangles inside out, their corners are swapped back
+        let normal_matrix = Mat3::from_mat4(obj_to_world).inverse().transpose();
+        let mirrored = obj_to_world.determinant() < 0.0;
+
+        let name = node.name().or(gltf_mesh.name()).map_or_else(|| format!("node_{}", node.index()), String::from);
+        println!("  model.name = \"{}\"", name);
+        let obj_id = mesh.objects.len() as u32;
+        mesh.objects.push(name);
+
+        for primitive in gltf_mesh.primitives() {
+            if primitive.mode() != Mode::Triangles {
+                println!("warning: {} primitive {} of mesh {} is not a triangle list and skipped", file_name, primitive.index(), gltf_mesh.index());
+                continue;
+            }
+
+            let material = primitive.material();
+            let mat_name = match (material.name(), material.index()) {
+                (Some(name), _) => name.to_string(),
+                (None, Some(index)) => format!("material_{}", index),
+                (None, None) => "default_material".to_string(),
+            };
+            if !scene.materials.contains_key(&mat_name) {
+                println!("  material.name = {}", mat_name);
+                let mat = load_gltf_material(&mut scene.textures, &images, &material, scene.materials.len() as u32);
+                scene.materials.insert(mat_name.clone(), mat);
             }
 
-            let t = Triangle {
-                vrt: [
-                    v[0],
-                    v[1],
-                    v[2],
-                ],
-                mat: mat.name.clone(),
-                node_idx: 0,
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions: Vec<Vec3> = match reader.read_positions() {
+                Some(positions) => positions.map(|p| obj_to_world.transform_point3(Vec3::from(p))).collect(),
+                None => continue,
             };
+            let normals: Option<Vec<Vec3>> = reader.read_normals()
+                .map(|normals| normals.map(|n| (normal_matrix * Vec3::from(n)).normalize_or_zero()).collect());
+            let tex_coords: Option<Vec<Vec2>> = reader.read_tex_coords(0)
+                .map(|tex_coords| tex_coords.into_f32().map(|t| Vec2::new(t[0], 1.0 - t[1])).collect());
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            println!("  model.primitive = {}, face_count = {}", primitive.index(), indices.len() / 3);
 
-            // validate triangle, discard invalid triangles
-            if Bounded::aabb(&t).surface_area() > 0.0 {
-                scene.shapes.push(t);
+            let mut vertices: Vec<Vertex> = Vec::with_capacity(indices.len());
+            for tri in indices.chunks_exact(3) {
+                let corners = if mirrored { [tri[0], tri[2], tri[1]] } else { [tri[0], tri[1], tri[2]] };
+                for i in corners.map(|i| i as usize) {
+                    let nrm = normals.as_ref().map_or(Vec3::ZERO, |normals| normals[i]);
+                    let tex = tex_coords.as_ref().map_or(Vec2::ZERO, |tex_coords| tex_coords[i]);
+                    vertices.push(Vertex::new(positions[i], nrm, tex));
+                }
             }
+
+            add_triangles(&mut mesh, vertices, &mat_name, obj_id);
         }
     }
+
+    mesh.build();
+    return mesh;
+}
+
+fn load_scene_desc(file_name: &str, strict: bool) -> SceneDesc {
+    match SceneDesc::from_file(file_name, strict) {
+        Ok(scene_desc) => return scene_desc,
+        Err(problems) => {
+            for problem in &problems {
+                eprintln!("error: {problem}");
+            }
+            process::exit(1);
+        },
+    }
+}
+
+fn load_lights(scene: &mut Scene, lights: &[LightDesc]) {
+    scene.lights.clear();
+    for light in lights {
+        match *light {
+            LightDesc::AmbientLight { emission } => {
+                println!("loading light of type \"AmbientLight\"");
+                scene.ambient = emission;
+            },
+            LightDesc::DirLight { direction, emission } => {
+                println!("loading light of type \"DirLight\"");
+                scene.lights.push(Box::new(DirLight {
+                    direction,
+                    emission,
+                }));
+            },
+            LightDesc::PointLight { position, emission, c, l, q } => {
+                println!("loading light of type \"PointLight\"");
+                scene.lights.push(Box::new(PointLight {
+                    position,
+                    emission,
+                    c,
+                    l,
+                    q,
+                }));
+            },
+        }
+    }
+}
+
+fn autofocus(scene: &mut Scene, pixel: [u32; 2]) {
+    let mut pinhole = scene.camera.clone();
+    pinhole.lens_radius = 0.0;
+    pinhole.stereo = None;
+
+    let time = pinhole.shutter_open;
+    let x = pixel[0].min(pinhole.viewport_w as u32 - 1) as f32 + 0.5;
+    let y = pixel[1].min(pinhole.viewport_h as u32 - 1) as f32 + 0.5;
+    let sample = match pinhole.calc_ray(x, y, Vec2::ZERO, time) {
+        Some(ray) => AovSample::eval(scene, &ray, time),
+        None => AovSample::default(),
+    };
+    if sample.hit {
+        scene.camera.focus_dist = sample.depth;
+    }
+    println!("autofocus: pixel ({}, {}), focus_dist: {}", pixel[0], pixel[1], scene.camera.focus_dist);
+}
+
+/**
+ * Camera orbit around the scene bounds for turntable renders, starting from the azimuth of
+ * the scene camera at a fixed elevation and far enough away to fit the bounding sphere
+ */
+struct Orbit {
+    center: Vec3,
+    radius: f32,
+    distance: f32,
+    azimuth: f32,
+}
+
+impl Orbit {
+    const ELEVATION: f32 = 20.0 * std::f32::consts::PI / 180.0;
+
+    fn new(scene: &Scene) -> Orbit {
+        let bounds = scene.bounds();
+        let center = bounds.center();
+        let radius = bounds.size().length() * 0.5;
+
+        let camera = &scene.camera;
+        let half_fov = (camera.viewport_w * 0.5 / camera.fx).atan().min((camera.viewport_h * 0.5 / camera.fy).atan());
+        let distance = match camera.projection {
+            Projection::Perspective => radius / half_fov.sin(),
+            _ => radius * 2.0,
+        };
+        let offset = camera.trf.pos - center;
+
+        Orbit {
+            center,
+            radius,
+            distance,
+            azimuth: offset.x.atan2(offset.z),
+        }
+    }
+
+    /**
+     * Places the camera at the given fraction of a full revolution
+     */
+    fn place(&self, camera: &mut Camera, t: f32) {
+        let azimuth = self.azimuth + t * 2.0 * std::f32::consts::PI;
+        let dir = Vec3::new(
+            azimuth.sin() * Orbit::ELEVATION.cos(),
+            Orbit::ELEVATION.sin(),
+            azimuth.cos() * Orbit::ELEVATION.cos(),
+        );
+
+        camera.trf = Transform::from_lookat(self.center + dir * self.distance, self.center, Vec3::Y);
+        camera.motion = None;
+        camera.focus_dist = self.distance;
+        if camera.projection == Projection::Orthographic {
+            camera.ortho_width = self.radius * 2.0 * (camera.viewport_w / camera.viewport_h).max(1.0);
+        }
+    }
+}
+
+/**
+ * Renders the scene into the film, split into tiles across all cores
+ */
+fn render(scene: &Scene, renderer: &Renderer, film: &mut Film, spp: u32) {
+    // determine multithreading params
+    let view_w = scene.camera.viewport_w as usize;
+    let view_h = scene.camera.viewport_h as usize;
+    let cpu_count = thread::available_parallelism()
+        .unwrap()
+        .get();
+    let task_w = view_w.div_ceil(cpu_count);
+    let task_h = view_h.div_ceil(cpu_count);
+    println!("cpu_count: {}, task_w: {}, task_h: {}, spp: {}", cpu_count, task_w, task_h, spp);
+
+    // execute rendering as split tasks across multiple threads
+    thread::scope(|s| {
+        let film_w = film.width as usize;
+        let with_aovs = !film.aovs.is_empty();
+
+        // divide each view into rectangles as individual rendering tasks, all views share the scene
+        let mut threads: Vec<ScopedJoinHandle<FilmTile>> = Vec::new();
+        for (view_x, view_y) in scene.camera.views() {
+            for j in 0..cpu_count {
+                let y = view_y as usize + j * task_h;
+                let h = (y + task_h).min(view_y as usize + view_h);
+
+                for i in 0..cpu_count {
+                    let x = view_x as usize + i * task_w;
+                    let w = (x + task_w).min(view_x as usize + view_w);
+                    if x >= w || y >= h {
+                        continue;
+                    }
+
+                    let mut tile = film.tile(x as u32, y as u32, (w - x) as u32, (h - y) as u32);
+                    threads.push(s.spawn(move || {
+                        for yy in y..h {
+                            for xx in x..w {
+                                let mut sampler = Sampler::new(spp, (yy * film_w + xx) as u64);
+                                // the cone barely changes across a pixel, all its samples share the one of the center
+                                let cone = scene.camera.ray_cone(xx as f32 + 0.5, yy as f32 + 0.5);
+
+                                for n in 0..spp {
+                                    let p = Vec2::new(xx as f32, yy as f32) + sampler.get_pixel_sample(n);
+                                    let lens = sampler.get_2d();
+                                    let time = scene.camera.sample_time(sampler.get_1d());
+
+                                    // film positions outside a fisheye image circle stay black
+                                    let ray = match scene.camera.calc_ray(p.x, p.y, lens, time) {
+                                        Some(ray) => ray,
+                                        None => {
+                                            tile.add_sample(p, Vec3::ZERO);
+                                            if with_aovs {
+                                                tile.add_aov_sample(p, &AovSample::default());
+                                            }
+                                            continue;
+                                        },
+                                    };
+                                    let col = renderer.trace(scene, &ray, &cone, time, &mut sampler.rng);
+                                    tile.add_sample(p, col);
+
+                                    if with_aovs {
+                                        tile.add_aov_sample(p, &AovSample::eval(scene, &ray, time));
+                                    }
+                                }
+                            }
+                        }
+
+                        return tile;
+                    }));
+                }
+            }
+        }
+
+        // wait for rendering tasks to complete
+        for handle in threads {
+            let tile = handle.join().unwrap();
+            film.merge_tile(&tile);
+        }
+    });
+}
+
+/**
+ * Filter radius in pixels, a radius of zero covers no sample and leaves the image black
+ */
+fn parse_filter_radius(arg: &str) -> Result<f32, String> {
+    let radius: f32 = arg.parse().map_err(|err| format!("{}", err))?;
+    if !(radius > 0.0 && radius.is_finite()) {
+        return Err(String::from("the radius must be positive"));
+    }
+    return Ok(radius);
 }
 
 fn main() {
@@ -212,194 +828,284 @@ fn main() {
                 .default_value("./res/wirokit.json")
                 .value_parser(clap::value_parser!(String))
         )
+        .arg(
+            arg!(--strict "Treat unknown fields in the scene file as errors")
+                .required(false)
+                .global(true)
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("Reports all problems in a scene file without rendering")
+                .arg(
+                    arg!(<SCENE>)
+                        .value_parser(clap::value_parser!(String))
+                )
+        )
+        .arg(
+            arg!(--renderer <RENDERER>)
+                .required(false)
+                .value_parser(["raytracer", "pathtracer"])
+        )
+        .arg(
+            arg!(--spp <SPP>)
+                .required(false)
+                .default_value("1")
+                .value_parser(clap::value_parser!(u32).range(1..))
+        )
+        .arg(
+            arg!(--filter <FILTER>)
+                .required(false)
+                .default_value("box")
+                .value_parser(["box", "tent", "gaussian", "mitchell", "blackman-harris"])
+        )
+        .arg(
+            arg!(--"filter-radius" <RADIUS>)
+                .required(false)
+                .value_parser(parse_filter_radius)
+        )
+        .arg(
+            arg!(--"texture-filter" <TEXFILTER>)
+                .required(false)
+                .default_value("trilinear")
+                .value_parser(["nearest", "bilinear", "trilinear", "ewa"])
+        )
+        .arg(
+            arg!(--"texture-memory" <MIB> "Memory budget for decoded textures, least recently used mip levels are evicted beyond it")
+                .required(false)
+                .value_parser(clap::value_parser!(u32).range(1..))
+        )
+        .arg(
+            arg!(--tonemap <TONEMAP>)
+                .required(false)
+                .default_value("clamp")
+                .value_parser(["clamp", "reinhard", "reinhard-extended", "hable", "aces"])
+        )
+        .arg(
+            arg!(--"white-point" <WHITE>)
+                .required(false)
+                .default_value("4.0")
+                .value_parser(clap::value_parser!(f32))
+        )
+        .arg(
+            arg!(--exposure <STOPS>)
+                .required(false)
+                .default_value("0.0")
+                .allow_negative_numbers(true)
+                .value_parser(clap::value_parser!(f32))
+        )
+        .arg(
+            arg!(--output <OUTPUT> "Output path, extension selects png, jpg, exr, pfm or hdr")
+                .required(false)
+                .default_value("./render.png")
+                .value_parser(clap::value_parser!(String))
+        )
+        .arg(
+            arg!(--half "Write OpenEXR output as 16-bit half floats")
+                .required(false)
+        )
+        .arg(
+            arg!(--aov <AOV> "Additional passes to write alongside the beauty image")
+                .required(false)
+                .value_delimiter(',')
+                .value_parser(Aov::NAMES)
+        )
+        .arg(
+            arg!(--denoise "Denoise the rendered image guided by albedo, normal and depth passes")
+                .required(false)
+        )
+        .arg(
+            arg!(--turntable <FRAMES> "Render numbered frames orbiting the camera around the scene")
+                .required(false)
+                .value_parser(clap::value_parser!(u32).range(1..))
+        )
         .get_matches();
     let arg_width = args.get_one::<u32>("width").unwrap();
     let arg_height = args.get_one::<u32>("height").unwrap();
     let arg_scene = args.get_one::<String>("scene").unwrap();
+    let arg_strict = args.get_flag("strict");
+    let arg_renderer = args.get_one::<String>("renderer");
+    let arg_spp = args.get_one::<u32>("spp").unwrap();
+    let arg_filter = args.get_one::<String>("filter").unwrap();
+    let arg_filter_radius = args.get_one::<f32>("filter-radius");
+    let arg_texture_filter = args.get_one::<String>("texture-filter").unwrap();
+    let arg_texture_memory = args.get_one::<u32>("texture-memory");
+    let arg_tonemap = args.get_one::<String>("tonemap").unwrap();
+    let arg_white_point = args.get_one::<f32>("white-point").unwrap();
+    let arg_exposure = args.get_one::<f32>("exposure").unwrap();
+    let arg_output = args.get_one::<String>("output").unwrap();
+    let arg_half = args.get_flag("half");
+    let arg_aovs: Vec<Aov> = args.get_many::<String>("aov")
+        .map_or(Vec::new(), |names| names.map(|name| Aov::from_name(name).unwrap()).collect());
+    let arg_denoise = args.get_flag("denoise");
+    let arg_turntable = args.get_one::<u32>("turntable");
+
+    // validate scene file only
+    if let Some(validate_args) = args.subcommand_matches("validate") {
+        let file_name = validate_args.get_one::<String>("SCENE").unwrap();
+        match SceneDesc::from_file(file_name, validate_args.get_flag("strict")) {
+            Ok(_) => println!("{file_name}: scene is valid"),
+            Err(problems) => {
+                for problem in &problems {
+                    println!("{problem}");
+                }
+                println!("{file_name}: {} problem(s) found", problems.len());
+                process::exit(1);
+            },
+        }
+        return;
+    }
 
-    // final render buffer
-    let mut render_buf: RgbImage = ImageBuffer::new(*arg_width, *arg_height);
+    // validate output before spending time on rendering
+    let output = Output {
+        path: arg_output.clone(),
+        format: OutputFormat::from_path(arg_output)
+            .expect("output extension must be one of png, jpg, jpeg, exr, pfm or hdr"),
+        half: arg_half,
+        tone_mapper: ToneMapper::from_name(arg_tonemap, *arg_white_point).unwrap(),
+        exposure: *arg_exposure,
+    };
 
     // load scene file
-    let scene_json_file = fs::File::open(arg_scene)
-        .expect("Failed to load target scene JSON file");
-    let scene_json: serde_json::Value = serde_json::from_reader(scene_json_file)
-        .expect("Failed to parse target scene JSON file");
-
-    // load camera params
-    let camera_json = scene_json.get("camera")
-        .expect("camera is a mandatory field for a scene JSON file");
-    let camera_pos: Vec<f32> = camera_json.get("position")
-        .unwrap()
-        .as_array()
-        .unwrap()
-        .iter()
-        .map(|x| x.as_f64().unwrap() as f32)
-        .collect();
-    let camera_axis: Vec<f32> = camera_json.get("rot_axis")
-        .unwrap()
-        .as_array()
-        .unwrap()
-        .iter()
-        .map(|x| x.as_f64().unwrap() as f32)
-        .collect();
-    let camera_angle = camera_json.get("rot_angle")
-        .unwrap()
-        .as_f64()
-        .unwrap();
-
-    // init scene
-    let mut scene = Scene::new(Camera::from_axis_angle(
-        Vec3 { x: camera_pos[0] , y: camera_pos[1], z: camera_pos[2] },
-        Vec3 { x: camera_axis[0], y: camera_axis[1], z: camera_axis[2] },
-        std::f32::consts::PI / 180.0 * camera_angle as f32,
-        *arg_width as f32,
-        *arg_height as f32
-    ));
-
-    // load models and materials
-    for model in scene_json["models"].as_array().unwrap() {
-        load_model(model.as_str().unwrap(), &mut scene);
+    let scene_desc = load_scene_desc(arg_scene, arg_strict);
+
+    // init scene, width and height give the size of a single view
+    let mut scene = Scene::new(scene_desc.camera.to_camera(*arg_width as f32, *arg_height as f32));
+    scene.texture_filter = TextureFilter::from_name(arg_texture_filter).unwrap();
+    scene.textures = TextureCache::new(arg_texture_memory.map(|mib| *mib as usize * 1024 * 1024));
+
+    // film settings, each frame renders into a new film holding both eyes for stereo cameras
+    let filter = Filter::from_name(arg_filter, arg_filter_radius.copied()).unwrap();
+    let mut film_aovs = arg_aovs.clone();
+    if arg_denoise {
+        film_aovs.extend(DENOISE_AOVS.iter().filter(|aov| !arg_aovs.contains(aov)));
     }
 
-    // load lights
-    for light in scene_json["lights"].as_array().unwrap() {
-        let light_type = light.get("type")
-            .unwrap()
-            .as_str()
-            .unwrap();
-
-        println!("loading light of type \"{light_type}\"");
-        match light_type {
-            "AmbientLight" => {
-                let light_emission: Vec<f32> = light.get("emission")
-                    .unwrap()
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .map(|x| x.as_f64().unwrap() as f32)
-                    .collect();
-
-                scene.ambient = Vec3::new(light_emission[0], light_emission[1], light_emission[2]);
+    // select renderer, command line takes precedence over the scene file
+    let renderer_name = match arg_renderer {
+        Some(name) => name.as_str(),
+        None => scene_desc.renderer.as_deref().unwrap_or("raytracer"),
+    };
+    let renderer = Renderer::from_name(renderer_name).unwrap();
+    println!("using renderer \"{renderer_name}\"");
+
+    // load models and materials, files referenced more than once share the same mesh
+    let mut mesh_indices: HashMap<&str, usize> = HashMap::new();
+    for model in &scene_desc.models {
+        let mesh_idx = match mesh_indices.get(model.file.as_str()) {
+            Some(mesh_idx) => *mesh_idx,
+            None => {
+                let mesh = match Path::new(&model.file).extension().and_then(|ext| ext.to_str()) {
+                    Some("gltf") | Some("glb") => load_gltf(&model.file, &mut scene),
+                    _ => load_model(&model.file, &mut scene),
+                };
+                scene.meshes.push(mesh);
+                mesh_indices.insert(&model.file, scene.meshes.len() - 1);
+                scene.meshes.len() - 1
             },
-            "DirLight" => {
-                let light_direction: Vec<f32> = light.get("direction")
-                    .unwrap()
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .map(|x| x.as_f64().unwrap() as f32)
-                    .collect();
-                let light_emission: Vec<f32> = light.get("emission")
-                    .unwrap()
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .map(|x| x.as_f64().unwrap() as f32)
-                    .collect();
+        };
 
-                scene.lights.push(Box::new(DirLight {
-                    direction: Vec3::new(light_direction[0], light_direction[1], light_direction[2]),
-                    emission: Vec3::new(light_emission[0], light_emission[1], light_emission[2]),
-                }));
+        match model.motion() {
+            Some(motion) => {
+                for offset in model.instance_transforms() {
+                    scene.add_animated_instance(mesh_idx, motion.clone(), offset);
+                }
+            },
+            None => {
+                for obj_to_world in model.transforms() {
+                    scene.add_instance(mesh_idx, obj_to_world);
+                }
             },
-            "PointLight" => {
-                let light_position: Vec<f32> = light.get("position")
-                    .unwrap()
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .map(|x| x.as_f64().unwrap() as f32)
-                    .collect();
-                let light_emission: Vec<f32> = light.get("emission")
-                    .unwrap()
-                    .as_array()
-                    .unwrap()
-                    .iter()
-                    .map(|x| x.as_f64().unwrap() as f32)
-                    .collect();
-                let light_c = light.get("c")
-                    .unwrap()
-                    .as_f64()
-                    .unwrap();
-                let light_l = light.get("l")
-                    .unwrap()
-                    .as_f64()
-                    .unwrap();
-                let light_q = light.get("q")
-                    .unwrap()
-                    .as_f64()
-                    .unwrap();
+        }
+    }
 
-                scene.lights.push(Box::new(PointLight {
-                    position: Vec3::new(light_position[0], light_position[1], light_position[2]),
-                    emission: Vec3::new(light_emission[0], light_emission[1], light_emission[2]),
-                    c: light_c as f32,
-                    l: light_l as f32,
-                    q: light_q as f32,
-                }));
+    // override materials by name
+    for (name, material_desc) in &scene_desc.materials {
+        match scene.materials.get_mut(name) {
+            Some(material) => {
+                match material_desc.output {
+                    Some(ref output) => println!("overriding material \"{name}\" with node graph \"{output}\""),
+                    None => println!("overriding material \"{name}\""),
+                }
+                material_desc.apply(material, &scene_desc.textures, &mut scene.textures);
             },
-            _ => ()
+            None => println!("warning: materials.{name}: no material with this name is loaded"),
         }
     }
 
-    // construct scene
-    println!("constructing scene, shape_count: {} ...", scene.shapes.len());
-    scene.bvh = Some(BVH::build(&mut scene.shapes));
+    // load lights
+    load_lights(&mut scene, &scene_desc.lights);
 
-    // determine multithreading params
-    let cpu_count = thread::available_parallelism()
-        .unwrap()
-        .get();
-    let task_w = scene.camera.viewport_w as usize / cpu_count;
-    let task_h = scene.camera.viewport_h as usize / cpu_count;
-    println!("cpu_count: {}, task_w: {}, task_h: {}", cpu_count, task_w, task_h);
+    // construct scene
+    scene.build();
 
-    // execute rendering as split tasks across multiple threads
-    thread::scope(|s| {
-        let scn = &scene;
-
-        // divide screen into rectangles as individual rendering tasks
-        let mut threads: Vec<ScopedJoinHandle<Vec<(u32, u32, Rgb<u8>)>>> = Vec::new();
-        for j in 0..cpu_count {
-            let y = j * task_h;
-            let h = y + task_h;
-
-            for i in 0..cpu_count {
-                let x = i * task_w;
-                let w = x + task_w;
-
-                threads.push(s.spawn(move || {
-                    let mut buf: Vec<(u32, u32, Rgb<u8>)> = Vec::new();
-
-                    for yy in y..h {
-                        for xx in x..w {
-                            let ray = &scn.camera.calc_ray(xx as f32, yy as f32);
-                            let col = Raytracer::trace(scn, &ray, 0) * 255.0;
-                            let pix = image::Rgb([
-                                col.x.max(1.0) as u8,
-                                col.y.max(1.0) as u8,
-                                col.z.max(1.0) as u8
-                            ]);
-                            buf.push((xx as u32, yy as u32, pix));
-                        }
-                    }
+    // frames of an animation or turntable, a still image otherwise
+    let frames: Vec<Option<u32>> = match (arg_turntable, &scene_desc.animation) {
+        (Some(n), _) => (1..=*n).map(Some).collect(),
+        (None, Some(animation)) => (animation.frame_start..=animation.frame_end).map(Some).collect(),
+        (None, None) => vec![None],
+    };
+    if let (None, Some(animation)) = (arg_turntable, &scene_desc.animation) {
+        if !animation.camera.is_empty() {
+            scene.camera.motion = Some(scene_desc.camera.motion_track(&animation.camera, animation.interpolation, scene.camera.trf.ori));
+        }
+    }
+    let orbit = arg_turntable.map(|_| Orbit::new(&scene));
 
-                    return buf;
-                }));
-            }
+    for frame in frames {
+        // animations are keyed in frames, turntables orbit a static scene
+        let time = match (frame, orbit.is_some()) {
+            (Some(frame), false) => frame as f32,
+            _ => 0.0,
+        };
+        if let Some(frame) = frame {
+            println!("rendering frame {frame} ...");
         }
 
-        // wait for rendering tasks to complete
-        for handle in threads {
-            let buf = handle.join().unwrap();
+        if let (Some(frame), Some(ref orbit), Some(n)) = (frame, &orbit, arg_turntable) {
+            orbit.place(&mut scene.camera, (frame - 1) as f32 / *n as f32);
+        }
+        if let (Some(animation), None) = (&scene_desc.animation, &orbit) {
+            let lights: Vec<LightDesc> = scene_desc.lights.iter()
+                .enumerate()
+                .map(|(i, light)| animation.light_at(i, light, time))
+                .collect();
+            load_lights(&mut scene, &lights);
+        }
+        scene.set_shutter(time + scene_desc.camera.shutter_open, time + scene_desc.camera.shutter_close);
 
-            for pix in buf {
-                render_buf.put_pixel(pix.0, pix.1, pix.2);
-            }
+        // focus on the first opaque surface seen through the center of the autofocus pixel
+        if let Some(pixel) = scene_desc.camera.autofocus {
+            autofocus(&mut scene, pixel);
         }
-    });
 
-    // export render buffer
-    render_buf.save_with_format("./render.png", ImageFormat::Png).unwrap();
+        let (film_w, film_h) = scene.camera.film_size();
+        let mut film = Film::new(film_w, film_h, filter, film_aovs.clone());
+        film.view_w = *arg_width;
+        film.view_h = *arg_height;
+        scene.textures.begin_frame();
+        render(&scene, &renderer, &mut film, *arg_spp);
+        scene.textures.report();
+
+        // export render buffer
+        let mut render_buf = match arg_denoise {
+            true => {
+                println!("denoising ...");
+                Denoiser::default().denoise(&film)
+            },
+            false => film.to_rgb32f_image(),
+        };
+        if let Some(sensor) = scene_desc.camera.sensor {
+            println!("simulating sensor ...");
+            sensor.apply(&mut render_buf, &scene.camera);
+        }
+        let aov_bufs: Vec<(Aov, _)> = arg_aovs.iter()
+            .map(|aov| (*aov, film.to_aov_image(*aov)))
+            .collect();
+        let frame_output = match frame {
+            Some(frame) => output.frame(frame),
+            None => output.clone(),
+        };
+        frame_output.save(&render_buf, &aov_bufs).expect("Failed to write output image");
+        frame_output.save_camera(&scene.camera).expect("Failed to write camera calibration");
+    }
 }
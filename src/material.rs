@@ -1,28 +1,258 @@
-use glam::Vec3;
-use image::{RgbaImage, GrayAlphaImage};
+use glam::{Vec2, Vec3, Vec4};
+
+use crate::procedural::ProceduralTexture;
+use crate::shader_graph::ShaderGraph;
+use crate::texture::{ColorSpace, Footprint, WrapMode};
+use crate::texture_cache::CachedTexture;
 
 #[derive(Debug, Clone)]
 
 pub enum TextureType {
     Diffuse,
     Alpha,
+    Roughness,
+    Metallic,
+    Normal,
+    Bump,
+    Specular,
+    Shininess,
+    Emission,
+    Ambient,
     None
 }
 
 pub enum Texture {
-    Diffuse(RgbaImage),
-    Alpha(GrayAlphaImage),
+    Diffuse(CachedTexture),
+    Alpha(CachedTexture),
+    Scalar(CachedTexture),
+    Normal(CachedTexture),
+    Bump(CachedTexture),
+    Color(CachedTexture),
+    Procedural(ProceduralTexture),
     None,
 }
 
+/**
+ * Options of an MTL texture statement. Texture coordinates are scaled by -s and shifted by -o
+ * before the lookup, -clamp on keeps them inside the texture instead of repeating it, -mm remaps
+ * looked up values to base + gain * value and -bm scales the slopes of bump and normal maps.
+ * The extensions -wrap repeat|clamp|mirror|border and -border r g b [a] select other wrap modes,
+ * -colorspace srgb|linear overrides the color space that is assumed for the kind of texture and
+ * -normalmap on|off reads the texture as a normal map or a height map whatever the statement.
+ * -imfchan r|g|b|m reads scalar and bump textures from a single channel, m being the alpha
+ * channel, instead of their luminance l. Textures packing several maps into their channels, as
+ * glTF does, read a single channel the same way
+ * Reference: https://paulbourke.net/dataformats/mtl/
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureOptions {
+    pub offset: Vec2,
+    pub scale: Vec2,
+    pub wrap: WrapMode,
+    pub bump_multiplier: f32,
+    pub base: f32,
+    pub gain: f32,
+    pub color_space: Option<ColorSpace>,
+    pub normal_map: Option<bool>,
+    pub channel: Option<usize>,
+}
+
+impl Default for TextureOptions {
+    fn default() -> TextureOptions {
+        TextureOptions {
+            offset: Vec2::ZERO,
+            scale: Vec2::ONE,
+            wrap: WrapMode::Repeat,
+            bump_multiplier: 1.0,
+            base: 0.0,
+            gain: 1.0,
+            color_space: None,
+            normal_map: None,
+            channel: None,
+        }
+    }
+}
+
+impl TextureOptions {
+    /**
+     * Splits the options off a texture statement, returns them with the file name that follows.
+     * Unsupported options are skipped together with their arguments
+     */
+    pub fn parse(statement: &str) -> (TextureOptions, String) {
+        let mut options = TextureOptions::default();
+        let mut words = statement.split_whitespace().peekable();
+
+        while let Some(option) = words.next_if(|w| w.starts_with('-')) {
+            // numeric arguments are taken as long as they parse, switches and names take one word
+            let mut args: Vec<f32> = Vec::new();
+            let max_args = match option {
+                "-border" => 4,
+                "-o" | "-s" | "-t" => 3,
+                "-mm" => 2,
+                "-bm" | "-boost" | "-texres" => 1,
+                _ => 0,
+            };
+            while args.len() < max_args {
+                match words.peek().and_then(|w| w.parse::<f32>().ok()) {
+                    Some(arg) => {
+                        args.push(arg);
+                        words.next();
+                    },
+                    None => break,
+                }
+            }
+
+            match (option, args.as_slice()) {
+                ("-o", [u, rest @ ..]) => options.offset = Vec2::new(*u, rest.first().copied().unwrap_or(0.0)),
+                ("-s", [u, rest @ ..]) => options.scale = Vec2::new(*u, rest.first().copied().unwrap_or(1.0)),
+                ("-mm", [base, rest @ ..]) => {
+                    options.base = *base;
+                    options.gain = rest.first().copied().unwrap_or(1.0);
+                },
+                ("-bm", [multiplier]) => options.bump_multiplier = *multiplier,
+                ("-clamp", _) => {
+                    options.wrap = if words.next() == Some("on") { WrapMode::Clamp } else { WrapMode::Repeat };
+                },
+                ("-wrap", _) => {
+                    let name = words.next().unwrap_or_default();
+                    match WrapMode::from_name(name) {
+                        Some(WrapMode::Border(_)) if matches!(options.wrap, WrapMode::Border(_)) => {},
+                        Some(wrap) => options.wrap = wrap,
+                        None => println!("warning: texture wrap mode {} is unknown", name),
+                    }
+                },
+                ("-colorspace", _) => {
+                    let name = words.next().unwrap_or_default();
+                    match ColorSpace::from_name(name) {
+                        Some(color_space) => options.color_space = Some(color_space),
+                        None => println!("warning: texture color space {} is unknown", name),
+                    }
+                },
+                ("-normalmap", _) => {
+                    match words.next() {
+                        Some("on") => options.normal_map = Some(true),
+                        Some("off") => options.normal_map = Some(false),
+                        name => println!("warning: texture option -normalmap {} is unknown", name.unwrap_or_default()),
+                    }
+                },
+                ("-border", [r, g, b, rest @ ..]) => {
+                    options.wrap = WrapMode::Border(Vec4::new(*r, *g, *b, rest.first().copied().unwrap_or(1.0)));
+                },
+                ("-imfchan", _) => {
+                    match words.next() {
+                        Some("r") => options.channel = Some(0),
+                        Some("g") => options.channel = Some(1),
+                        Some("b") => options.channel = Some(2),
+                        Some("m") => options.channel = Some(3),
+                        Some("l") => options.channel = None,
+                        name => println!("warning: texture option -imfchan {} is not supported", name.unwrap_or_default()),
+                    }
+                },
+                ("-blendu" | "-blendv" | "-cc" | "-type", _) => {
+                    println!("warning: texture option {} is not supported", option);
+                    words.next();
+                },
+                _ => println!("warning: texture option {} is not supported", option),
+            }
+        }
+
+        return (options, words.collect::<Vec<_>>().join(" "));
+    }
+
+    /**
+     * Texture coordinate to look up, in the flipped v convention of the intersections
+     */
+    pub fn coords(&self, tex: &Vec2) -> Vec2 {
+        let uv = Vec2::new(tex.x, 1.0 - tex.y) * self.scale + self.offset;
+        return Vec2::new(uv.x, 1.0 - uv.y);
+    }
+
+    /**
+     * Footprint to look up, scaled along with the texture coordinates
+     */
+    pub fn footprint(&self, fp: &Footprint) -> Footprint {
+        return Footprint {
+            tex: self.coords(&fp.tex),
+            axis_a: fp.axis_a * self.scale,
+            axis_b: fp.axis_b * self.scale,
+            ..*fp
+        };
+    }
+
+    pub fn remap(&self, value: f32) -> f32 {
+        return self.base + self.gain * value;
+    }
+}
+
+pub struct TextureMap {
+    pub texture: Texture,
+    pub options: TextureOptions,
+}
+
+/**
+ * Surface type selected by the MTL illumination model, reflection with ray tracing (3, 5, 8)
+ * gives mirrors and transparency or refraction (4, 6, 7, 9) gives glass. Other materials with
+ * PBR extensions (Pr, Pm, map_Pr, map_Pm) use the metallic-roughness BRDF. Materials with any
+ * principled parameters or overridden by the scene file use the principled BSDF
+ * Reference: https://paulbourke.net/dataformats/mtl/
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Surface {
+    Diffuse,
+    Mirror,
+    Glass,
+    MetallicRoughness,
+    Principled,
+}
+
+impl Surface {
+    pub fn from_mtl(illum: Option<u8>, pbr: bool, principled: bool) -> Surface {
+        match illum {
+            _ if principled => return Surface::Principled,
+            Some(3) | Some(5) | Some(8) => return Surface::Mirror,
+            Some(4) | Some(6) | Some(7) | Some(9) => return Surface::Glass,
+            _ if pbr => return Surface::MetallicRoughness,
+            _ => return Surface::Diffuse,
+        }
+    }
+}
+
+// index of refraction of principled materials without one, that of common dielectrics
+pub const PRINCIPLED_IOR: f32 = 1.5;
+
 pub struct Material {
+    pub id: u32,
     pub ambient: Vec3,
     pub diffuse: Vec3,
     pub specular: Vec3,
     pub shininess: f32,
     pub emission: Vec3,
-    pub diffuse_texture: Texture,
-    pub alpha_texture: Texture,
+    pub surface: Surface,
+    pub ior: f32,
+    pub dissolve: f32,
+    pub alpha_cutoff: f32,
+    pub transmission_filter: Vec3,
+    pub roughness: f32,
+    pub metallic: f32,
+    pub subsurface: f32,
+    pub specular_tint: f32,
+    pub anisotropic: f32,
+    pub anisotropic_rotation: f32,
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub transmission: f32,
+    pub diffuse_texture: TextureMap,
+    pub alpha_texture: TextureMap,
+    pub roughness_texture: TextureMap,
+    pub metallic_texture: TextureMap,
+    pub normal_texture: TextureMap,
+    pub specular_texture: TextureMap,
+    pub shininess_texture: TextureMap,
+    pub emission_texture: TextureMap,
+    pub ambient_texture: TextureMap,
+    pub graph: Option<ShaderGraph>,
 }
 
 impl Material {
@@ -30,11 +260,146 @@ impl Material {
         return normal.dot(*light);
     }
 
-    pub fn brdf_phong(&self, reflect: &Vec3, view: &Vec3) -> f32 {
-        return reflect.dot(*view).powf(self.shininess);
+    pub fn brdf_phong(&self, reflect: &Vec3, view: &Vec3, shininess: f32) -> f32 {
+        return reflect.dot(*view).max(0.0).powf(shininess);
+    }
+
+    /**
+     * Fraction of light reflected at the dielectric boundary, with the normal facing the view
+     * vector. Leaving the medium uses the transmitted angle and reflects everything beyond
+     * the critical angle
+     * Reference: https://www.pbr-book.org/3ed-2018/Reflection_Models/Specular_Reflection_and_Transmission
+     */
+    pub fn fresnel_schlick(&self, normal: &Vec3, view: &Vec3, entering: bool) -> f32 {
+        let r0 = ((self.ior - 1.0) / (self.ior + 1.0)).powi(2);
+        let mut cos_theta = normal.dot(*view).clamp(0.0, 1.0);
+        if !entering {
+            let sin2_t = self.ior * self.ior * (1.0 - cos_theta * cos_theta);
+            if sin2_t >= 1.0 {
+                return 1.0;
+            }
+            cos_theta = (1.0 - sin2_t).sqrt();
+        }
+
+        return r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5);
+    }
+
+    /**
+     * Ratio of the indices of refraction on the incident and the transmitted side
+     */
+    pub fn eta(&self, entering: bool) -> f32 {
+        match entering {
+            true => return 1.0 / self.ior,
+            false => return self.ior,
+        }
+    }
+
+    /**
+     * Beer-Lambert attenuation over the distance travelled inside the medium, the transmission
+     * filter color is the fraction of light left after one scene unit
+     * Reference: https://en.wikipedia.org/wiki/Beer%E2%80%93Lambert_law
+     */
+    pub fn absorption(&self, dist: f32) -> Vec3 {
+        return self.transmission_filter.powf(dist);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(statement: &str) -> (TextureOptions, String) {
+        return TextureOptions::parse(statement);
+    }
+
+    #[test]
+    fn file_name_without_options() {
+        assert_eq!(parse("bricks.png"), (TextureOptions::default(), "bricks.png".to_string()));
+        assert_eq!(parse(""), (TextureOptions::default(), String::new()));
+    }
+
+    #[test]
+    fn offset_and_scale_take_one_to_three_arguments() {
+        let (options, name) = parse("-o 0.5 bricks.png");
+        assert_eq!((options.offset, name.as_str()), (Vec2::new(0.5, 0.0), "bricks.png"));
+        let (options, name) = parse("-o 0.5 0.25 bricks.png");
+        assert_eq!((options.offset, name.as_str()), (Vec2::new(0.5, 0.25), "bricks.png"));
+        let (options, name) = parse("-o 0.5 0.25 1 bricks.png");
+        assert_eq!((options.offset, name.as_str()), (Vec2::new(0.5, 0.25), "bricks.png"));
+
+        let (options, name) = parse("-s 2 bricks.png");
+        assert_eq!((options.scale, name.as_str()), (Vec2::new(2.0, 1.0), "bricks.png"));
+        let (options, name) = parse("-s 2 3 bricks.png");
+        assert_eq!((options.scale, name.as_str()), (Vec2::new(2.0, 3.0), "bricks.png"));
+        let (options, name) = parse("-s 2 3 4 -o -1 -2 bricks.png");
+        assert_eq!((options.scale, options.offset, name.as_str()), (Vec2::new(2.0, 3.0), Vec2::new(-1.0, -2.0), "bricks.png"));
+    }
+
+    #[test]
+    fn value_range() {
+        let (options, _) = parse("-mm 0.2 0.5 rough.png");
+        assert_eq!((options.base, options.gain), (0.2, 0.5));
+        let (options, _) = parse("-mm 0.2 rough.png");
+        assert_eq!((options.base, options.gain), (0.2, 1.0));
+        assert!((options.remap(0.5) - 0.7).abs() < 1e-6);
+    }
+
+    #[test]
+    fn bump_multiplier() {
+        let (options, name) = parse("-bm 0.5 height.png");
+        assert_eq!((options.bump_multiplier, name.as_str()), (0.5, "height.png"));
+        let (options, name) = parse("-bm -2 -s 4 4 height.png");
+        assert_eq!((options.bump_multiplier, options.scale, name.as_str()), (-2.0, Vec2::splat(4.0), "height.png"));
+    }
+
+    #[test]
+    fn clamp_switch() {
+        assert_eq!(parse("-clamp on sign.png").0.wrap, WrapMode::Clamp);
+        assert_eq!(parse("-clamp off sign.png"), (TextureOptions::default(), "sign.png".to_string()));
+        assert_eq!(parse("-clamp on -clamp off sign.png").0.wrap, WrapMode::Repeat);
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(parse("-wrap mirror tiles.png").0.wrap, WrapMode::Mirror);
+        assert_eq!(parse("-border 1 0 0 tiles.png").0.wrap, WrapMode::Border(Vec4::new(1.0, 0.0, 0.0, 1.0)));
+        assert_eq!(parse("-border 1 0 0 0.5 -wrap border tiles.png").0.wrap, WrapMode::Border(Vec4::new(1.0, 0.0, 0.0, 0.5)));
+        assert_eq!(parse("-colorspace linear albedo.exr").0.color_space, Some(ColorSpace::Linear));
+        assert_eq!(parse("-normalmap on normal.png").0.normal_map, Some(true));
+        assert_eq!(parse("-normalmap off height.png").0.normal_map, Some(false));
+    }
+
+    #[test]
+    fn channel_selection() {
+        assert_eq!(parse("-imfchan r rough.png"), (TextureOptions { channel: Some(0), ..TextureOptions::default() }, "rough.png".to_string()));
+        assert_eq!(parse("-imfchan g rough.png").0.channel, Some(1));
+        assert_eq!(parse("-imfchan b -mm 0 2 rough.png").0.channel, Some(2));
+        assert_eq!(parse("-imfchan m cutout.png").0.channel, Some(3));
+        assert_eq!(parse("-imfchan l rough.png"), (TextureOptions::default(), "rough.png".to_string()));
+        assert_eq!(parse("-imfchan z depth.png"), (TextureOptions::default(), "depth.png".to_string()));
+    }
+
+    #[test]
+    fn unsupported_options_skip_their_arguments() {
+        let statements = [
+            "-blendu off bricks.png",
+            "-blendv on -cc off bricks.png",
+            "-type sphere bricks.png",
+            "-boost 2.5 bricks.png",
+            "-texres 512 bricks.png",
+            "-t 0.1 0.2 0.3 bricks.png",
+            "-t 0.1 -texres 256 -blendu on bricks.png",
+        ];
+        for statement in statements {
+            assert_eq!(parse(statement), (TextureOptions::default(), "bricks.png".to_string()), "{}", statement);
+        }
     }
 
-    pub fn fresnel_schlick(&self, normal: &Vec3, view: &Vec3) -> f32 {
-        return (1.0 - normal.dot(*view)).clamp(0.0, 1.0).powf(5.0);
+    #[test]
+    fn file_names_with_spaces() {
+        assert_eq!(parse("old bricks.png").1, "old bricks.png");
+        let (options, name) = parse("-s 2 2 -clamp on my old bricks.png");
+        assert_eq!((options.scale, options.wrap, name.as_str()), (Vec2::splat(2.0), WrapMode::Clamp, "my old bricks.png"));
+        assert_eq!(parse("-bm 1 textures/wall height.png").1, "textures/wall height.png");
     }
 }
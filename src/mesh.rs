@@ -0,0 +1,34 @@
+use bvh::aabb::{AABB, Bounded};
+use bvh::bvh::BVH;
+
+use crate::triangle::Triangle;
+
+/**
+ * Triangle data of a single loaded model in object space, with its own BVH.
+ * Shared by every instance placing the model in the scene
+ */
+pub struct Mesh {
+    pub name: String,
+    pub shapes: Vec<Triangle>,
+    pub objects: Vec<String>,
+    pub bvh: Option<BVH>,
+    pub aabb: AABB,
+}
+
+impl Mesh {
+    pub fn new(name: String) -> Mesh {
+        Mesh {
+            name,
+            shapes: Vec::new(),
+            objects: Vec::new(),
+            bvh: None,
+            aabb: AABB::empty(),
+        }
+    }
+
+    pub fn build(&mut self) {
+        println!("constructing mesh \"{}\", shape_count: {} ...", self.name, self.shapes.len());
+        self.aabb = self.shapes.iter().fold(AABB::empty(), |aabb, t| aabb.join(&t.aabb()));
+        self.bvh = Some(BVH::build(&mut self.shapes));
+    }
+}
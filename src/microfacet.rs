@@ -0,0 +1,219 @@
+use glam::{Vec2, Vec3};
+
+use crate::utils::{luminance, onb, sample_cosine_hemisphere};
+
+// lower bound of the GGX alpha, keeps smooth surfaces from degenerating into a delta distribution
+pub const MIN_ALPHA: f32 = 1e-3;
+
+/**
+ * Metallic-roughness BRDF, a GGX/Trowbridge-Reitz specular lobe with height-correlated Smith
+ * masking-shadowing over a lambertian base. Metals tint the specular reflection by the base
+ * color and have no diffuse term, dielectrics reflect 4% at normal incidence and only diffusely
+ * scatter the light not reflected by the Fresnel term. All directions point away from the surface
+ * References: https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#appendix-b-brdf-implementation,
+ *             https://jcgt.org/published/0003/02/03/
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct MetallicRoughness {
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+impl MetallicRoughness {
+    fn alpha(&self) -> f32 {
+        return (self.roughness * self.roughness).max(MIN_ALPHA);
+    }
+
+    fn f0(&self) -> Vec3 {
+        return Vec3::splat(0.04).lerp(self.base_color, self.metallic);
+    }
+
+    /**
+     * Probability of sampling the specular lobe, by its share of the expected reflectance
+     */
+    fn specular_probability(&self, n_dot_v: f32) -> f32 {
+        let s = luminance(&fresnel_schlick(self.f0(), n_dot_v));
+        let d = luminance(&(self.base_color * (1.0 - self.metallic)));
+        return (s / (s + d).max(f32::EPSILON)).clamp(0.1, 1.0);
+    }
+
+    /**
+     * BRDF times the cosine of the light direction
+     */
+    pub fn eval(&self, n: &Vec3, v: &Vec3, l: &Vec3) -> Vec3 {
+        let n_dot_v = n.dot(*v);
+        let n_dot_l = n.dot(*l);
+        if n_dot_v <= 0.0 || n_dot_l <= 0.0 {
+            return Vec3::ZERO;
+        }
+
+        let alpha = self.alpha();
+        let h = (*v + *l).normalize();
+        let f = fresnel_schlick(self.f0(), v.dot(h));
+        let specular = f * distribution_ggx(n.dot(h), alpha) * visibility_smith(n_dot_v, n_dot_l, alpha);
+        // the diffuse base is lit by what the coat does not reflect towards the viewer, weighting it
+        // by the fresnel term of each half vector instead gains energy at grazing angles
+        let diffuse = (Vec3::ONE - fresnel_schlick(self.f0(), n_dot_v)) * (1.0 - self.metallic) * self.base_color / std::f32::consts::PI;
+
+        return (diffuse + specular) * n_dot_l;
+    }
+
+    /**
+     * Density of sample() choosing the light direction, with respect to solid angle
+     */
+    pub fn pdf(&self, n: &Vec3, v: &Vec3, l: &Vec3) -> f32 {
+        let n_dot_v = n.dot(*v);
+        let n_dot_l = n.dot(*l);
+        if n_dot_v <= 0.0 || n_dot_l <= 0.0 {
+            return 0.0;
+        }
+
+        // visible normals are reflected about the half vector, the jacobian is 1 / (4 v.h)
+        let alpha = self.alpha();
+        let h = (*v + *l).normalize();
+        let pdf_specular = masking_smith(n_dot_v, alpha) * distribution_ggx(n.dot(h), alpha) / (4.0 * n_dot_v);
+        let pdf_diffuse = n_dot_l / std::f32::consts::PI;
+
+        let p = self.specular_probability(n_dot_v);
+        return p * pdf_specular + (1.0 - p) * pdf_diffuse;
+    }
+
+    /**
+     * Importance samples a light direction, from the distribution of normals visible from the
+     * view direction or cosine-weighted for the diffuse lobe. Returns the direction and the
+     * sample weight eval() / pdf(), None if the sample points below the surface
+     * Reference: https://jcgt.org/published/0007/04/01/
+     */
+    pub fn sample(&self, n: &Vec3, v: &Vec3, u_lobe: f32, u: Vec2) -> Option<(Vec3, Vec3)> {
+        let n_dot_v = n.dot(*v);
+        if n_dot_v <= 0.0 {
+            return None;
+        }
+
+        let l = match u_lobe < self.specular_probability(n_dot_v) {
+            true => {
+                let (t, s) = onb(n);
+                let v_local = Vec3::new(v.dot(t), v.dot(s), n_dot_v);
+                let h_local = sample_ggx_vndf(&v_local, self.alpha(), self.alpha(), u);
+                let h = t * h_local.x + s * h_local.y + *n * h_local.z;
+                (2.0 * v.dot(h) * h - *v).normalize()
+            },
+            false => sample_cosine_hemisphere(n, u.x, u.y),
+        };
+
+        let pdf = self.pdf(n, v, &l);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        return Some((l, self.eval(n, v, &l) / pdf));
+    }
+}
+
+/**
+ * Schlick's approximation of the Fresnel reflectance
+ */
+pub fn fresnel_schlick(f0: Vec3, cos_theta: f32) -> Vec3 {
+    return f0 + (Vec3::ONE - f0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+}
+
+/**
+ * GGX/Trowbridge-Reitz distribution of microfacet normals
+ */
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (std::f32::consts::PI * d * d);
+}
+
+/**
+ * Smith masking of microfacets seen from a single direction
+ */
+fn masking_smith(n_dot_v: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    return 2.0 * n_dot_v / (n_dot_v + (a2 + (1.0 - a2) * n_dot_v * n_dot_v).sqrt());
+}
+
+/**
+ * Height-correlated Smith masking-shadowing, divided by the 4 n.v n.l denominator of the BRDF
+ */
+fn visibility_smith(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let ggx_v = n_dot_l * (n_dot_v * n_dot_v * (1.0 - a2) + a2).sqrt();
+    let ggx_l = n_dot_v * (n_dot_l * n_dot_l * (1.0 - a2) + a2).sqrt();
+    return 0.5 / (ggx_v + ggx_l);
+}
+
+/**
+ * Samples a microfacet normal visible from the local view direction, with the normal along +z
+ * and the roughness of the tangent and bitangent directions along +x and +y
+ */
+pub fn sample_ggx_vndf(v: &Vec3, alpha_x: f32, alpha_y: f32, u: Vec2) -> Vec3 {
+    // stretch the view direction onto the hemisphere configuration
+    let vh = Vec3::new(alpha_x * v.x, alpha_y * v.y, v.z).normalize();
+    let len_sq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = match len_sq > 0.0 {
+        true => Vec3::new(-vh.y, vh.x, 0.0) / len_sq.sqrt(),
+        false => Vec3::X,
+    };
+    let t2 = vh.cross(t1);
+
+    // sample the projected area of the visible hemisphere
+    let r = u.x.sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u.y;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+    // unstretch back to the ellipsoid configuration
+    return Vec3::new(alpha_x * nh.x, alpha_y * nh.y, nh.z.max(0.0)).normalize();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdf_test::{check_pdf, check_sample_weight, check_white_furnace, TestBsdf};
+
+    impl TestBsdf for MetallicRoughness {
+        fn sample(&self, v: &Vec3, u_lobe: f32, u: Vec2) -> Option<(Vec3, Vec3)> {
+            return self.sample(&Vec3::Z, v, u_lobe, u);
+        }
+
+        fn eval(&self, v: &Vec3, l: &Vec3) -> Vec3 {
+            return self.eval(&Vec3::Z, v, l);
+        }
+
+        fn pdf(&self, v: &Vec3, l: &Vec3) -> f32 {
+            return self.pdf(&Vec3::Z, v, l);
+        }
+    }
+
+    fn params() -> [MetallicRoughness; 6] {
+        return [
+            MetallicRoughness { base_color: Vec3::ONE, metallic: 0.0, roughness: 0.5 },
+            MetallicRoughness { base_color: Vec3::ONE, metallic: 0.0, roughness: 0.05 },
+            MetallicRoughness { base_color: Vec3::ONE, metallic: 0.0, roughness: 1.0 },
+            MetallicRoughness { base_color: Vec3::ONE, metallic: 1.0, roughness: 0.3 },
+            MetallicRoughness { base_color: Vec3::ONE, metallic: 0.5, roughness: 0.8 },
+            MetallicRoughness { base_color: Vec3::ONE, metallic: 1.0, roughness: 1.0 },
+        ];
+    }
+
+    #[test]
+    fn sample_weight_is_eval_over_pdf() {
+        check_sample_weight(params());
+    }
+
+    #[test]
+    fn pdf_matches_sampled_directions() {
+        // uniform sampling misses the narrow highlights of smooth surfaces
+        check_pdf(params().into_iter().filter(|bsdf| bsdf.roughness >= 0.3), 0.03);
+    }
+
+    #[test]
+    fn white_furnace() {
+        check_white_furnace(params());
+    }
+}
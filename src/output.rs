@@ -0,0 +1,224 @@
+use exr::prelude::{AnyChannel, AnyChannels, FlatSamples, Image, SmallVec, WritableImage, f16};
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageFormat, Rgb32FImage};
+use std::error::Error;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::aov::Aov;
+use crate::camera::{Camera, Eye};
+use crate::tonemap::{ToneMapper, encode_srgb};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Exr,
+    Pfm,
+    Hdr,
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &str) -> Option<OutputFormat> {
+        let ext = Path::new(path)
+            .extension()?
+            .to_str()?
+            .to_lowercase();
+
+        match ext.as_str() {
+            "png" => return Some(OutputFormat::Png),
+            "jpg" | "jpeg" => return Some(OutputFormat::Jpeg),
+            "exr" => return Some(OutputFormat::Exr),
+            "pfm" => return Some(OutputFormat::Pfm),
+            "hdr" => return Some(OutputFormat::Hdr),
+            _ => return None,
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        return matches!(self, OutputFormat::Exr | OutputFormat::Pfm | OutputFormat::Hdr);
+    }
+}
+
+/**
+ * Output settings for the final image, display formats are tone mapped while
+ * HDR formats receive the unclamped linear radiance of the film
+ */
+#[derive(Clone)]
+pub struct Output {
+    pub path: String,
+    pub format: OutputFormat,
+    pub half: bool,
+    pub tone_mapper: ToneMapper,
+    pub exposure: f32,
+}
+
+impl Output {
+    /**
+     * Writes the beauty image and any AOV passes, AOVs become extra channel layers of
+     * an OpenEXR output or separate OpenEXR files next to any other output format
+     */
+    pub fn save(&self, hdr: &Rgb32FImage, aovs: &[(Aov, Rgb32FImage)]) -> Result<(), Box<dyn Error>> {
+        println!("writing {:?} output to \"{}\" ...", self.format, self.path);
+
+        match self.format {
+            OutputFormat::Png => {
+                encode_srgb(hdr, &self.tone_mapper, self.exposure).save_with_format(&self.path, ImageFormat::Png)?;
+            },
+            OutputFormat::Jpeg => {
+                encode_srgb(hdr, &self.tone_mapper, self.exposure).save_with_format(&self.path, ImageFormat::Jpeg)?;
+            },
+            OutputFormat::Exr => {
+                let mut channels = image_channels("", &["R", "G", "B"], hdr);
+                for (aov, img) in aovs {
+                    channels.extend(image_channels(aov.name(), aov.channels(), img));
+                }
+                return write_exr(&self.path, hdr.width(), hdr.height(), channels, self.half);
+            },
+            OutputFormat::Pfm => write_pfm(&self.path, hdr)?,
+            OutputFormat::Hdr => write_hdr(&self.path, hdr)?,
+        }
+
+        for (aov, img) in aovs {
+            let path = self.sibling_path(aov.name(), "exr");
+            println!("writing {} pass to \"{}\" ...", aov.name(), path);
+            write_exr(&path, img.width(), img.height(), image_channels("", aov.channels(), img), self.half)?;
+        }
+
+        return Ok(());
+    }
+
+    /**
+     * Writes the camera calibration next to the image as "<stem>.camera.json", matrices
+     * are row-major and follow OpenCV conventions (x right, y down, z forward). Only planar
+     * projections have an intrinsic matrix, the others record their projection and pose
+     */
+    pub fn save_camera(&self, camera: &Camera) -> Result<(), Box<dyn Error>> {
+        let path = self.sibling_path("camera", "json");
+        println!("writing camera calibration to \"{}\" ...", path);
+
+        let calibration = |eye: Option<Eye>| {
+            let world_to_camera = camera.extrinsics(eye);
+            let mut json = serde_json::json!({
+                "world_to_camera": world_to_camera.transpose().to_cols_array_2d(),
+                "camera_to_world": world_to_camera.inverse().transpose().to_cols_array_2d(),
+            });
+            if camera.projection.is_planar() {
+                let k = camera.intrinsics(eye);
+                json["fx"] = k.x_axis.x.into();
+                json["fy"] = k.y_axis.y.into();
+                json["cx"] = k.z_axis.x.into();
+                json["cy"] = k.z_axis.y.into();
+                json["K"] = serde_json::json!(k.transpose().to_cols_array_2d());
+            }
+            return json;
+        };
+
+        let mut json = calibration(None);
+        json["width"] = (camera.viewport_w as u32).into();
+        json["height"] = (camera.viewport_h as u32).into();
+        json["projection"] = camera.projection.name().into();
+        json["distortion"] = serde_json::json!({
+            "model": camera.distortion.name(),
+            "coefficients": camera.distortion.coefficients(),
+        });
+        // stereo eyes are listed in the order of their views on the film
+        if let Some(stereo) = camera.stereo {
+            json["ipd"] = stereo.ipd.into();
+            json["left"] = calibration(Some(Eye::Left));
+            json["right"] = calibration(Some(Eye::Right));
+        }
+
+        fs::write(&path, serde_json::to_string_pretty(&json)?)?;
+        return Ok(());
+    }
+
+    /**
+     * Output for a frame of a sequence, numbered like "render_0001.png"
+     */
+    pub fn frame(&self, frame: u32) -> Output {
+        let path = Path::new(&self.path);
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        let ext = path.extension().unwrap().to_str().unwrap();
+
+        let mut output = self.clone();
+        output.path = path.with_file_name(format!("{stem}_{frame:04}.{ext}")).to_str().unwrap().to_string();
+        return output;
+    }
+
+    /**
+     * Derives the path of an auxiliary file, e.g. "render.png" => "render.depth.exr"
+     */
+    pub fn sibling_path(&self, suffix: &str, ext: &str) -> String {
+        return Path::new(&self.path)
+            .with_extension(format!("{suffix}.{ext}"))
+            .to_str()
+            .unwrap()
+            .to_string();
+    }
+}
+
+/**
+ * Splits the leading components of an image into named channels, prefixed by the layer name if any
+ */
+fn image_channels(layer: &str, names: &[&str], img: &Rgb32FImage) -> Vec<(String, Vec<f32>)> {
+    return names.iter()
+        .enumerate()
+        .map(|(c, name)| {
+            let name = match layer.is_empty() {
+                true => name.to_string(),
+                false => format!("{layer}.{name}"),
+            };
+            (name, img.pixels().map(|p| p[c]).collect())
+        })
+        .collect();
+}
+
+fn write_exr(path: &str, width: u32, height: u32, channels: Vec<(String, Vec<f32>)>, half: bool) -> Result<(), Box<dyn Error>> {
+    let channels: SmallVec<_> = channels.into_iter()
+        .map(|(name, samples)| {
+            let samples = match half {
+                true => FlatSamples::F16(samples.into_iter().map(f16::from_f32).collect()),
+                false => FlatSamples::F32(samples),
+            };
+            AnyChannel::new(name.as_str(), samples)
+        })
+        .collect();
+
+    Image::from_channels((width as usize, height as usize), AnyChannels::sort(channels))
+        .write()
+        .to_file(path)?;
+
+    return Ok(());
+}
+
+/**
+ * Portable float map, little-endian scanlines stored from bottom to top
+ * Reference: http://www.pauldebevec.com/Research/HDR/PFM/
+ */
+fn write_pfm(path: &str, hdr: &Rgb32FImage) -> Result<(), Box<dyn Error>> {
+    let mut file = BufWriter::new(fs::File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", hdr.width(), hdr.height())?;
+
+    for y in (0..hdr.height()).rev() {
+        for x in 0..hdr.width() {
+            for c in hdr.get_pixel(x, y).0 {
+                file.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+
+    // dropping the writer would flush it but swallow a failed write
+    file.flush()?;
+    return Ok(());
+}
+
+fn write_hdr(path: &str, hdr: &Rgb32FImage) -> Result<(), Box<dyn Error>> {
+    let mut file = BufWriter::new(fs::File::create(path)?);
+    let pixels: Vec<_> = hdr.pixels().copied().collect();
+    HdrEncoder::new(&mut file).encode(&pixels, hdr.width() as usize, hdr.height() as usize)?;
+
+    file.flush()?;
+    return Ok(());
+}
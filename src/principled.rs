@@ -0,0 +1,411 @@
+use glam::{Vec2, Vec3};
+
+use crate::microfacet::{MIN_ALPHA, fresnel_schlick, sample_ggx_vndf};
+use crate::utils::{luminance, refract, sample_cosine_hemisphere};
+
+/**
+ * Disney principled BSDF. A Burley diffuse lobe blends into a subsurface approximation and
+ * gains a grazing sheen, an anisotropic GGX lobe reflects the specular color, tinted towards the
+ * base color by specular_tint and metallic, a GTR1 clearcoat layer adds a second highlight and
+ * rough dielectric transmission refracts through the surface. Directions point away from the
+ * surface and the frame vectors are orthonormal, with the normal facing the viewer
+ * References: https://media.disneyanimation.com/uploads/production/publication_asset/48/asset/s2012_pbs_disney_brdf_notes_v3.pdf,
+ *             https://blog.selfshadow.com/publications/s2015-shading-course/burley/s2015_pbs_disney_bsdf_notes.pdf,
+ *             https://www.pbr-book.org/3ed-2018/Reflection_Models/Microfacet_Models
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Principled {
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub subsurface: f32,
+    pub specular_tint: f32,
+    pub anisotropic: f32,
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub transmission: f32,
+    pub ior: f32,
+    pub entering: bool,
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub normal: Vec3,
+}
+
+impl Principled {
+    fn local(&self, w: &Vec3) -> Vec3 {
+        return Vec3::new(w.dot(self.tangent), w.dot(self.bitangent), w.dot(self.normal));
+    }
+
+    fn world(&self, w: &Vec3) -> Vec3 {
+        return self.tangent * w.x + self.bitangent * w.y + self.normal * w.z;
+    }
+
+    /**
+     * Roughness along the tangent and the bitangent
+     */
+    fn alphas(&self) -> (f32, f32) {
+        let aspect = (1.0 - 0.9 * self.anisotropic).sqrt();
+        let alpha = self.roughness * self.roughness;
+        return ((alpha / aspect).max(MIN_ALPHA), (alpha * aspect).max(MIN_ALPHA));
+    }
+
+    fn clearcoat_alpha(&self) -> f32 {
+        return 0.001 + (0.1 - 0.001) * self.clearcoat_roughness;
+    }
+
+    /**
+     * Ratio of the indices of refraction on the transmitted and the incident side
+     */
+    fn eta(&self) -> f32 {
+        match self.entering {
+            true => return self.ior,
+            false => return 1.0 / self.ior,
+        }
+    }
+
+    fn tint(&self) -> Vec3 {
+        let lum = luminance(&self.base_color);
+        match lum > 0.0 {
+            true => return self.base_color / lum,
+            false => return Vec3::ONE,
+        }
+    }
+
+    /**
+     * Reflectance at normal incidence, dielectrics derive it from the index of refraction
+     */
+    fn specular_color(&self) -> Vec3 {
+        let f0 = ((self.ior - 1.0) / (self.ior + 1.0)).powi(2);
+        let dielectric = Vec3::splat(f0).lerp(self.tint() * f0, self.specular_tint);
+        return dielectric.lerp(self.base_color, self.metallic);
+    }
+
+    /**
+     * Share of the surface behaving as a transmissive dielectric
+     */
+    fn dielectric_weight(&self) -> f32 {
+        return (1.0 - self.metallic) * self.transmission;
+    }
+
+    /**
+     * Probabilities of sampling the diffuse, specular, clearcoat and dielectric lobes, by their
+     * expected share of the scattered light. The dielectric lobe reflects or refracts by the
+     * Fresnel term of the sampled microfacet, so internal reflections are not lost
+     */
+    fn lobe_probabilities(&self, wo: &Vec3) -> [f32; 4] {
+        let f = luminance(&fresnel_schlick(self.specular_color(), wo.z));
+        let weights = [
+            (1.0 - self.metallic) * (1.0 - self.transmission) * (1.0 - f),
+            (1.0 - self.dielectric_weight()) * f,
+            0.25 * self.clearcoat,
+            self.dielectric_weight(),
+        ];
+
+        let sum: f32 = weights.iter().sum();
+        if sum <= 0.0 {
+            return [0.0; 4];
+        }
+        return weights.map(|w| w / sum);
+    }
+
+    /**
+     * BSDF times the absolute cosine of the light direction
+     */
+    pub fn eval(&self, v: &Vec3, l: &Vec3) -> Vec3 {
+        let wo = self.local(v);
+        let wi = self.local(l);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return Vec3::ZERO;
+        }
+
+        let (alpha_x, alpha_y) = self.alphas();
+        if wi.z < 0.0 {
+            // rough dielectric transmission through the generalized half vector
+            let eta = self.eta();
+            let h = match self.transmission_half_vector(&wo, &wi) {
+                Some(h) => h,
+                None => return Vec3::ZERO,
+            };
+            let denom = wo.dot(h) + eta * wi.dot(h);
+            let f = fresnel_dielectric(wo.dot(h), eta);
+            let d = distribution_gtr2(&h, alpha_x, alpha_y);
+            let g = masking_smith(&wo, alpha_x, alpha_y) * masking_smith(&wi, alpha_x, alpha_y);
+            let t = d * g * (1.0 - f) * wi.dot(h).abs() * wo.dot(h) / (wo.z * denom * denom);
+
+            return self.base_color * (1.0 - self.metallic) * self.transmission * t;
+        }
+
+        let h = (wo + wi).normalize();
+        let cos_d = wi.dot(h);
+        let fl = schlick_weight(wi.z);
+        let fv = schlick_weight(wo.z);
+        let fh = schlick_weight(cos_d);
+
+        // burley diffuse with retro-reflection, blended with the hanrahan-krueger subsurface approximation
+        let fd90 = 0.5 + 2.0 * cos_d * cos_d * self.roughness;
+        let fd = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+        let fss90 = cos_d * cos_d * self.roughness;
+        let fss = (1.0 + (fss90 - 1.0) * fl) * (1.0 + (fss90 - 1.0) * fv);
+        let ss = 1.25 * (fss * (1.0 / (wi.z + wo.z) - 0.5) + 0.5);
+        let sheen = Vec3::ONE.lerp(self.tint(), self.sheen_tint) * self.sheen * fh;
+        let diffuse = (self.base_color / std::f32::consts::PI * (fd + (ss - fd) * self.subsurface) + sheen)
+            * (1.0 - self.metallic) * (1.0 - self.transmission) * (1.0 - fresnel_schlick(self.specular_color(), wo.z));
+
+        // anisotropic specular reflection, transmissive dielectrics reflect by the exact fresnel term
+        let f = fresnel_schlick(self.specular_color(), cos_d)
+            .lerp(Vec3::splat(fresnel_dielectric(cos_d, self.eta())), self.dielectric_weight());
+        let d = distribution_gtr2(&h, alpha_x, alpha_y);
+        let g = masking_smith(&wo, alpha_x, alpha_y) * masking_smith(&wi, alpha_x, alpha_y);
+        let specular = f * d * g / (4.0 * wo.z * wi.z);
+
+        // clearcoat with a fixed index of refraction of 1.5 and masking roughness of 0.25
+        let fc = 0.04 + 0.96 * fh;
+        let dc = distribution_gtr1(h.z, self.clearcoat_alpha());
+        let gc = masking_smith(&wo, 0.25, 0.25) * masking_smith(&wi, 0.25, 0.25);
+        let clearcoat = 0.25 * self.clearcoat * fc * dc * gc / (4.0 * wo.z * wi.z);
+
+        // the base layer is lit by what the clearcoat does not reflect towards the viewer
+        let coat = 1.0 - 0.25 * self.clearcoat * (0.04 + 0.96 * fv);
+
+        return ((diffuse + specular) * coat + Vec3::splat(clearcoat)) * wi.z;
+    }
+
+    /**
+     * Density of sample() choosing the light direction, with respect to solid angle
+     */
+    pub fn pdf(&self, v: &Vec3, l: &Vec3) -> f32 {
+        let wo = self.local(v);
+        let wi = self.local(l);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+
+        let [p_diffuse, p_specular, p_clearcoat, p_transmission] = self.lobe_probabilities(&wo);
+        let (alpha_x, alpha_y) = self.alphas();
+        if wi.z < 0.0 {
+            let eta = self.eta();
+            let h = match self.transmission_half_vector(&wo, &wi) {
+                Some(h) => h,
+                None => return 0.0,
+            };
+            let denom = wo.dot(h) + eta * wi.dot(h);
+            let pdf_h = masking_smith(&wo, alpha_x, alpha_y) * wo.dot(h) * distribution_gtr2(&h, alpha_x, alpha_y) / wo.z;
+
+            let p_refraction = p_transmission * (1.0 - fresnel_dielectric(wo.dot(h), eta));
+
+            return p_refraction * pdf_h * eta * eta * wi.dot(h).abs() / (denom * denom);
+        }
+
+        // reflected normals map to directions with the jacobian 1 / (4 v.h)
+        let h = (wo + wi).normalize();
+        let pdf_diffuse = wi.z / std::f32::consts::PI;
+        let pdf_specular = masking_smith(&wo, alpha_x, alpha_y) * distribution_gtr2(&h, alpha_x, alpha_y) / (4.0 * wo.z);
+        let pdf_clearcoat = distribution_gtr1(h.z, self.clearcoat_alpha()) * h.z / (4.0 * wo.dot(h));
+        let p_reflection = p_specular + p_transmission * fresnel_dielectric(wo.dot(h), self.eta());
+
+        return p_diffuse * pdf_diffuse + p_reflection * pdf_specular + p_clearcoat * pdf_clearcoat;
+    }
+
+    /**
+     * Importance samples a light direction from one of the lobes, chosen by u_lobe. Returns the
+     * direction and the sample weight eval() / pdf(), None if the sample is invalid
+     */
+    pub fn sample(&self, v: &Vec3, u_lobe: f32, u: Vec2) -> Option<(Vec3, Vec3)> {
+        let wo = self.local(v);
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let [p_diffuse, p_specular, p_clearcoat, p_transmission] = self.lobe_probabilities(&wo);
+        let (alpha_x, alpha_y) = self.alphas();
+        let mut refracted = false;
+        let wi = if u_lobe < p_diffuse {
+            sample_cosine_hemisphere(&Vec3::Z, u.x, u.y)
+        } else if u_lobe < p_diffuse + p_specular {
+            let h = sample_ggx_vndf(&wo, alpha_x, alpha_y, u);
+            2.0 * wo.dot(h) * h - wo
+        } else if u_lobe < p_diffuse + p_specular + p_clearcoat {
+            let h = sample_gtr1(self.clearcoat_alpha(), u);
+            2.0 * wo.dot(h) * h - wo
+        } else {
+            // reuse the remainder of the lobe sample to choose between reflection and refraction
+            let h = sample_ggx_vndf(&wo, alpha_x, alpha_y, u);
+            let u_fresnel = (u_lobe - (p_diffuse + p_specular + p_clearcoat)) / p_transmission;
+            match refract(&-wo, &h, 1.0 / self.eta()) {
+                Some(wi) if u_fresnel >= fresnel_dielectric(wo.dot(h), self.eta()) => {
+                    refracted = true;
+                    wi
+                },
+                _ => 2.0 * wo.dot(h) * h - wo,
+            }
+        };
+
+        // reflections must stay above and refractions below the surface
+        if (wi.z < 0.0) != refracted || wi.z == 0.0 {
+            return None;
+        }
+
+        let l = self.world(&wi).normalize();
+        let pdf = self.pdf(v, &l);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        return Some((l, self.eval(v, &l) / pdf));
+    }
+
+    /**
+     * Microfacet normal refracting wo into wi, None if the directions lie on the wrong sides of it
+     */
+    fn transmission_half_vector(&self, wo: &Vec3, wi: &Vec3) -> Option<Vec3> {
+        let mut h = (*wo + *wi * self.eta()).normalize();
+        if h.z < 0.0 {
+            h = -h;
+        }
+        if !h.is_finite() || wo.dot(h) <= 0.0 || wi.dot(h) >= 0.0 {
+            return None;
+        }
+
+        return Some(h);
+    }
+}
+
+fn schlick_weight(cos_theta: f32) -> f32 {
+    return (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+}
+
+/**
+ * Unpolarized Fresnel reflectance of a dielectric boundary, eta is the ratio of the indices of
+ * refraction on the transmitted and the incident side
+ * Reference: https://www.pbr-book.org/3ed-2018/Reflection_Models/Specular_Reflection_and_Transmission
+ */
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+
+    return (r_parl * r_parl + r_perp * r_perp) * 0.5;
+}
+
+/**
+ * Anisotropic GGX distribution of local microfacet normals
+ */
+fn distribution_gtr2(h: &Vec3, alpha_x: f32, alpha_y: f32) -> f32 {
+    let d = (h.x / alpha_x).powi(2) + (h.y / alpha_y).powi(2) + h.z * h.z;
+    return 1.0 / (std::f32::consts::PI * alpha_x * alpha_y * d * d);
+}
+
+/**
+ * Smith masking of the anisotropic GGX distribution seen from a local direction
+ */
+fn masking_smith(w: &Vec3, alpha_x: f32, alpha_y: f32) -> f32 {
+    let tan2 = ((alpha_x * w.x).powi(2) + (alpha_y * w.y).powi(2)) / (w.z * w.z);
+    let lambda = (-1.0 + (1.0 + tan2).sqrt()) * 0.5;
+    return 1.0 / (1.0 + lambda);
+}
+
+/**
+ * Generalized Trowbridge-Reitz distribution with gamma 1 of the clearcoat layer
+ */
+fn distribution_gtr1(cos_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    return (a2 - 1.0) / (std::f32::consts::PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h));
+}
+
+/**
+ * Samples a local microfacet normal proportional to the GTR1 distribution times its cosine
+ */
+fn sample_gtr1(alpha: f32, u: Vec2) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos_h = ((1.0 - a2.powf(1.0 - u.x)) / (1.0 - a2)).max(0.0).sqrt();
+    let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u.y;
+
+    return Vec3::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdf_test::{check_pdf, check_sample_weight, check_white_furnace, TestBsdf};
+
+    impl TestBsdf for Principled {
+        fn sample(&self, v: &Vec3, u_lobe: f32, u: Vec2) -> Option<(Vec3, Vec3)> {
+            return self.sample(v, u_lobe, u);
+        }
+
+        fn eval(&self, v: &Vec3, l: &Vec3) -> Vec3 {
+            return self.eval(v, l);
+        }
+
+        fn pdf(&self, v: &Vec3, l: &Vec3) -> f32 {
+            return self.pdf(v, l);
+        }
+
+        fn transmits(&self) -> bool {
+            return true;
+        }
+
+        fn energy_scale(&self, l: &Vec3) -> f32 {
+            // refraction scales radiance by the squared ratio of the indices of refraction
+            match l.z < 0.0 {
+                true => return self.eta() * self.eta(),
+                false => return 1.0,
+            }
+        }
+    }
+
+    fn bsdf(base_color: Vec3, metallic: f32, roughness: f32) -> Principled {
+        return Principled {
+            base_color,
+            metallic,
+            roughness,
+            subsurface: 0.0,
+            specular_tint: 0.0,
+            anisotropic: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            transmission: 0.0,
+            ior: 1.5,
+            entering: true,
+            tangent: Vec3::X,
+            bitangent: Vec3::Y,
+            normal: Vec3::Z,
+        };
+    }
+
+    fn params() -> [Principled; 7] {
+        return [
+            bsdf(Vec3::ONE, 0.0, 0.5),
+            Principled { anisotropic: 0.8, ..bsdf(Vec3::ONE, 1.0, 0.4) },
+            Principled { clearcoat: 1.0, clearcoat_roughness: 0.3, sheen: 0.5, ..bsdf(Vec3::ONE, 0.0, 0.6) },
+            Principled { subsurface: 1.0, specular_tint: 0.5, ..bsdf(Vec3::new(0.8, 0.5, 0.3), 0.3, 0.7) },
+            Principled { transmission: 1.0, ..bsdf(Vec3::ONE, 0.0, 0.5) },
+            bsdf(Vec3::ONE, 0.0, 1.0),
+            Principled { transmission: 1.0, entering: false, ..bsdf(Vec3::ONE, 0.0, 0.5) },
+        ];
+    }
+
+    #[test]
+    fn sample_weight_is_eval_over_pdf() {
+        check_sample_weight(params());
+    }
+
+    #[test]
+    fn pdf_matches_sampled_directions() {
+        check_pdf(params(), 0.04);
+    }
+
+    #[test]
+    fn white_furnace() {
+        check_white_furnace(params());
+    }
+}
@@ -0,0 +1,362 @@
+use glam::{IVec3, Vec3, Vec4};
+use serde::Deserialize;
+
+use crate::texture::Footprint;
+
+// Ken Perlin's permutation of 0..255
+const PERMUTATION: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69,
+    142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219,
+    203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175,
+    74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230,
+    220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76,
+    132, 187, 208, 89, 18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173,
+    186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206,
+    59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163,
+    70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232,
+    178, 185, 112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162,
+    241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204,
+    176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141,
+    128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+// smallest step of the finite differences for bump mapping, in pattern units
+const MIN_BUMP_STEP: f32 = 1e-3;
+
+/**
+ * Pattern of a procedural texture, evaluated to a value in [0, 1] that selects a color of the
+ * ramp. Checkers alternate between the first and last color in 2D (uv space) or 3D, noise,
+ * fbm and turbulence are Perlin noise and its fractal sums, voronoi is the distance to the
+ * nearest of randomly placed feature points and cells a random value per nearest point.
+ * Ramps run along x, radial gradients away from the z axis. Marble perturbs stripes along y
+ * and wood rings around the z axis with fbm scaled by the variation
+ * References: https://mrl.cs.nyu.edu/~perlin/noise/, https://www.pbr-book.org/3ed-2018/Texture/Noise
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+    Checker,
+    Noise,
+    Fbm,
+    Turbulence,
+    Voronoi,
+    Cells,
+    Ramp,
+    Radial,
+    Marble,
+    Wood,
+}
+
+/**
+ * Coordinates a procedural texture is evaluated at, the texture coordinate with w = 0 or the
+ * world space position
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextureSpace {
+    #[default]
+    Uv,
+    World,
+}
+
+/**
+ * Texture computed from a pattern instead of looked up in an image. Coordinates are scaled
+ * and offset into pattern units, where checkers and noise features are one unit wide. The
+ * pattern is filtered over the footprint: checkers are box filtered, the fractal sums drop
+ * octaves finer than the footprint
+ */
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ProceduralTexture {
+    pub pattern: Pattern,
+    #[serde(default)]
+    pub space: TextureSpace,
+    #[serde(default = "default_scale")]
+    pub scale: Vec3,
+    #[serde(default)]
+    pub offset: Vec3,
+    #[serde(default = "default_colors")]
+    pub colors: Vec<Vec3>,
+    #[serde(default = "default_octaves")]
+    pub octaves: u32,
+    #[serde(default = "default_roughness")]
+    pub roughness: f32,
+    #[serde(default = "default_variation")]
+    pub variation: f32,
+}
+
+fn default_scale() -> Vec3 {
+    return Vec3::ONE;
+}
+
+fn default_colors() -> Vec<Vec3> {
+    return vec![Vec3::ZERO, Vec3::ONE];
+}
+
+fn default_octaves() -> u32 {
+    return 8;
+}
+
+fn default_roughness() -> f32 {
+    return 0.5;
+}
+
+fn default_variation() -> f32 {
+    return 1.0;
+}
+
+impl ProceduralTexture {
+    /**
+     * Point in pattern units and the width of the footprint around it along each axis
+     */
+    fn point(&self, fp: &Footprint) -> (Vec3, Vec3) {
+        match self.space {
+            TextureSpace::Uv => {
+                let width = fp.axis_a.length().max(fp.axis_b.length());
+                let p = fp.tex.extend(0.0) * self.scale + self.offset;
+                return (p, Vec3::new(width, width, 0.0) * self.scale.abs());
+            },
+            TextureSpace::World => {
+                return (fp.pos * self.scale + self.offset, Vec3::splat(fp.width) * self.scale.abs());
+            },
+        }
+    }
+
+    /**
+     * Pattern value in [0, 1] at a point in pattern units, filtered over the given width
+     */
+    fn value(&self, p: Vec3, width: Vec3) -> f32 {
+        let w = width.max_element();
+        match self.pattern {
+            Pattern::Checker => {
+                let (a, b, c) = (odd_fraction(p.x, width.x), odd_fraction(p.y, width.y), odd_fraction(p.z, width.z));
+                let ab = a + b - 2.0 * a * b;
+                return ab + c - 2.0 * ab * c;
+            },
+            Pattern::Noise => return (0.5 + 0.5 * fbm(p, w, 0.5, 1)).clamp(0.0, 1.0),
+            Pattern::Fbm => return (0.5 + 0.5 * fbm(p, w, self.roughness, self.octaves)).clamp(0.0, 1.0),
+            Pattern::Turbulence => return turbulence(p, w, self.roughness, self.octaves).clamp(0.0, 1.0),
+            Pattern::Voronoi => return voronoi(p).0.clamp(0.0, 1.0),
+            Pattern::Cells => return voronoi(p).1,
+            Pattern::Ramp => return p.x.clamp(0.0, 1.0),
+            Pattern::Radial => return p.truncate().length().clamp(0.0, 1.0),
+            Pattern::Marble => {
+                let marble = p.y + self.variation * fbm(p, w, self.roughness, self.octaves);
+                return 0.5 + 0.5 * marble.sin();
+            },
+            Pattern::Wood => {
+                // rings narrower than the footprint fade to their average
+                let r = p.truncate().length() + self.variation * 0.1 * fbm(p, w, self.roughness, self.octaves);
+                return r.fract() + smooth_step(0.5, 1.0, w) * (0.5 - r.fract());
+            },
+        }
+    }
+
+    /**
+     * Color of the ramp at a pattern value, the colors are spread evenly over [0, 1]
+     */
+    fn color(&self, value: f32) -> Vec3 {
+        match self.colors.len() {
+            0 => return Vec3::splat(value),
+            1 => return self.colors[0],
+            n => {
+                let x = value * (n - 1) as f32;
+                let i = (x.floor() as usize).min(n - 2);
+                return self.colors[i].lerp(self.colors[i + 1], x - i as f32);
+            },
+        }
+    }
+
+    /**
+     * Filtered color over the footprint, alpha is always one
+     */
+    pub fn lookup(&self, fp: &Footprint) -> Vec4 {
+        let (p, width) = self.point(fp);
+        return self.color(self.value(p, width)).extend(1.0);
+    }
+
+    /**
+     * Slopes of the pattern value as a height field, along the texture coordinates u and v in
+     * uv space and along the given tangent and bitangent in world space (per world unit).
+     * Central differences step by half the footprint width
+     */
+    pub fn slopes(&self, fp: &Footprint, tangent: Vec3, bitangent: Vec3) -> (f32, f32) {
+        let (p, width) = self.point(fp);
+        let step = (0.5 * width.max_element()).max(MIN_BUMP_STEP);
+        let height = |offset: Vec3| self.value(p + offset, width);
+
+        match self.space {
+            TextureSpace::Uv => {
+                // the stored v coordinate is flipped
+                let dh_du = (height(Vec3::X * step) - height(-Vec3::X * step)) / (2.0 * step) * self.scale.x;
+                let dh_dv = (height(-Vec3::Y * step) - height(Vec3::Y * step)) / (2.0 * step) * self.scale.y;
+                return (dh_du, dh_dv);
+            },
+            TextureSpace::World => {
+                let dist = step / self.scale.abs().max_element();
+                let dh_dt = (height(tangent * self.scale * dist) - height(-tangent * self.scale * dist)) / (2.0 * dist);
+                let dh_db = (height(bitangent * self.scale * dist) - height(-bitangent * self.scale * dist)) / (2.0 * dist);
+                return (dh_dt, dh_db);
+            },
+        }
+    }
+}
+
+/**
+ * Fraction of odd unit cells within width around x, integrating the square wave in closed form
+ * Reference: https://www.pbr-book.org/3ed-2018/Texture/Solid_and_Procedural_Texturing#Closed-FormBoxFiltering
+ */
+fn odd_fraction(x: f32, width: f32) -> f32 {
+    if width <= 0.0 {
+        return x.floor().rem_euclid(2.0);
+    }
+
+    let integral = |x: f32| (x / 2.0).floor() + 2.0 * (x / 2.0 - (x / 2.0).floor() - 0.5).max(0.0);
+    let (x0, x1) = (x - 0.5 * width, x + 0.5 * width);
+    return (integral(x1) - integral(x0)) / width;
+}
+
+fn perm(i: i32) -> i32 {
+    return PERMUTATION[(i & 255) as usize] as i32;
+}
+
+fn fade(t: f32) -> f32 {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+/**
+ * Dot product of the position within the cell with one of 12 gradients chosen by the hash
+ */
+fn grad(hash: i32, p: Vec3) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { p.x } else { p.y };
+    let v = match h {
+        0..=3 => p.y,
+        12 | 14 => p.x,
+        _ => p.z,
+    };
+    return (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v });
+}
+
+/**
+ * Improved Perlin noise, zero at integer lattice points and within [-1, 1]
+ * Reference: https://mrl.cs.nyu.edu/~perlin/noise/
+ */
+pub fn noise(p: Vec3) -> f32 {
+    let cell = p.floor();
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let f = p - cell;
+    let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+
+    let a = perm(x) + y;
+    let (aa, ab) = (perm(a) + z, perm(a + 1) + z);
+    let b = perm(x + 1) + y;
+    let (ba, bb) = (perm(b) + z, perm(b + 1) + z);
+
+    let lerp = |t: f32, a: f32, b: f32| a + t * (b - a);
+    return lerp(w,
+        lerp(v,
+            lerp(u, grad(perm(aa), f), grad(perm(ba), f - Vec3::X)),
+            lerp(u, grad(perm(ab), f - Vec3::Y), grad(perm(bb), f - Vec3::new(1.0, 1.0, 0.0)))),
+        lerp(v,
+            lerp(u, grad(perm(aa + 1), f - Vec3::Z), grad(perm(ba + 1), f - Vec3::new(1.0, 0.0, 1.0))),
+            lerp(u, grad(perm(ab + 1), f - Vec3::new(0.0, 1.0, 1.0)), grad(perm(bb + 1), f - Vec3::ONE))));
+}
+
+fn smooth_step(min: f32, max: f32, x: f32) -> f32 {
+    let t = ((x - min) / (max - min)).clamp(0.0, 1.0);
+    return t * t * (3.0 - 2.0 * t);
+}
+
+/**
+ * Number of octaves of the fractal sums that are not finer than the footprint width, the
+ * fractional part fades in the last octave
+ */
+fn octave_count(width: f32, max_octaves: u32) -> f32 {
+    let len2 = (width * width).max(1e-12);
+    return (-1.0 - 0.5 * len2.log2()).clamp(0.0, max_octaves as f32);
+}
+
+/**
+ * Fractional brownian motion, octaves of noise at doubling frequencies with amplitudes
+ * falling by the roughness
+ * Reference: https://www.pbr-book.org/3ed-2018/Texture/Noise#FractionalBrownianMotion
+ */
+pub fn fbm(p: Vec3, width: f32, roughness: f32, max_octaves: u32) -> f32 {
+    let n = octave_count(width, max_octaves);
+    let n_int = n.floor() as u32;
+
+    let (mut sum, mut lambda, mut o) = (0.0, 1.0, 1.0);
+    for _ in 0..n_int {
+        sum += o * noise(lambda * p);
+        lambda *= 1.99;
+        o *= roughness;
+    }
+    return sum + o * smooth_step(0.3, 0.7, n - n_int as f32) * noise(lambda * p);
+}
+
+/**
+ * Fractal sum of the absolute noise, octaves below the footprint take its average value
+ * Reference: https://www.pbr-book.org/3ed-2018/Texture/Noise#Turbulence
+ */
+pub fn turbulence(p: Vec3, width: f32, roughness: f32, max_octaves: u32) -> f32 {
+    let n = octave_count(width, max_octaves);
+    let n_int = n.floor() as u32;
+
+    let (mut sum, mut lambda, mut o) = (0.0, 1.0, 1.0);
+    for _ in 0..n_int {
+        sum += o * noise(lambda * p).abs();
+        lambda *= 1.99;
+        o *= roughness;
+    }
+
+    // octaves finer than the footprint take the average of the absolute noise
+    if n_int < max_octaves {
+        let partial = smooth_step(0.3, 0.7, n - n_int as f32);
+        sum += o * (0.2 + partial * (noise(lambda * p).abs() - 0.2));
+        for _ in n_int + 1..max_octaves {
+            o *= roughness;
+            sum += o * 0.2;
+        }
+    }
+    return sum;
+}
+
+/**
+ * Random value in [0, 1) for a lattice cell, seeds give independent values
+ */
+fn cell_random(cell: IVec3, seed: u32) -> f32 {
+    let mut h = (cell.x as u32).wrapping_mul(0x8da6b343)
+        ^ (cell.y as u32).wrapping_mul(0xd8163841)
+        ^ (cell.z as u32).wrapping_mul(0xcb1ab31f)
+        ^ seed.wrapping_mul(0x165667b1);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a2d39);
+    h ^= h >> 15;
+    return (h >> 8) as f32 / (1 << 24) as f32;
+}
+
+/**
+ * Worley noise with one random feature point per lattice cell, returns the distance to the
+ * nearest feature point and a random value of that point
+ * Reference: https://dl.acm.org/doi/10.1145/237170.237267
+ */
+pub fn voronoi(p: Vec3) -> (f32, f32) {
+    let base = p.floor().as_ivec3();
+    let mut nearest = (f32::MAX, 0.0);
+    for z in -1..=1 {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let cell = base + IVec3::new(x, y, z);
+                let feature = cell.as_vec3() + Vec3::new(cell_random(cell, 0), cell_random(cell, 1), cell_random(cell, 2));
+                let dist = feature.distance(p);
+                if dist < nearest.0 {
+                    nearest = (dist, cell_random(cell, 3));
+                }
+            }
+        }
+    }
+
+    return nearest;
+}
@@ -1,14 +1,23 @@
 use bvh::{ray::Ray};
-use glam::{Vec3, Vec2};
-use image::{GenericImageView, Pixel};
+use glam::{Vec2, Vec3, Vec4};
+use rand::Rng;
 
 use crate::{
     intersection::Intersection,
-    utils::{EPSILON, reflect},
-    scene::Scene, material::Texture,
+    utils::{EPSILON, luminance, onb, reflect, refract, sample_cosine_hemisphere},
+    scene::Scene, material::{Material, Surface, Texture, TextureMap},
+    microfacet::MetallicRoughness,
+    principled::Principled,
+    procedural::TextureSpace,
+    shader_graph::{ShaderGraph, Socket, SocketValues},
+    texture::{Footprint, RayCone, TextureFilter},
 };
 
 const RESULT_NULL: Vec3 = Vec3::new(0.0, 0.0, 0.0);
+// height of a white texel in a bump map, relative to the size of one texture repeat
+const BUMP_DEPTH: f32 = 1.0 / 64.0;
+// smallest step of the finite differences for shader graph bump heights
+const MIN_BUMP_STEP: f32 = 1e-4;
 
 pub struct Raytracer;
 pub struct Pathtracer;
@@ -17,160 +26,598 @@ pub enum Renderer {
     PATHTRACER(Pathtracer),
 }
 
-fn sample_texture<P: Pixel>(img: &dyn GenericImageView<Pixel = P>, tex: &Vec2) -> (f32, f32, f32, u8, u8) where P: Pixel<Subpixel = u8> {
-    // get pixel sample at texture coordinate, use wrapping  sampling mode
-    let img_w = img.width() - 1;
-    let img_h = img.height() - 1;
-    let pix_x = (tex.x.rem_euclid(1.0) * img_w as f32) as u32;
-    let pix_y = (tex.y.rem_euclid(1.0) * img_h as f32) as u32;
-    let pix_c = img.get_pixel(pix_x, pix_y);
+/**
+ * Looks up a texture map over the footprint, with its coordinate transform, wrap mode, channel
+ * and value range. Procedural textures take the place of any kind of image texture
+ */
+fn sample_map(map: &TextureMap, fp: &Footprint) -> Vec4 {
+    let o = &map.options;
+    let c = match map.texture {
+        Texture::Diffuse(ref texture) | Texture::Alpha(ref texture) | Texture::Scalar(ref texture)
+            | Texture::Normal(ref texture) | Texture::Bump(ref texture) | Texture::Color(ref texture) => texture.lookup(&o.footprint(fp), o.wrap),
+        Texture::Procedural(ref procedural) => procedural.lookup(&o.footprint(fp)),
+        Texture::None => return Vec4::ONE,
+    };
+    let c = match o.channel {
+        Some(channel) => Vec4::new(c[channel], c[channel], c[channel], c.w),
+        None => c,
+    };
+    return Vec4::new(o.remap(c.x), o.remap(c.y), o.remap(c.z), c.w);
+}
+
+/**
+ * Shader graph sockets over the footprint of a hit, evaluated once and passed to the shading
+ * functions below. None of them is connected for materials without a graph
+ */
+pub fn graph_values(mat: &Material, fp: &Footprint) -> SocketValues {
+    return mat.graph.as_ref().map_or_else(SocketValues::default, |graph| graph.eval(fp));
+}
+
+fn graph_scalar(graph: &SocketValues, socket: Socket, value: f32) -> f32 {
+    return graph.get(socket).map_or(value, |v| v.x);
+}
 
-    // return results based on pixel channel count
-    match pix_c.channels().len() {
-        2 => {
-            let p = pix_c.to_luma_alpha();
-            return (0.0, 0.0, 0.0, p[0], p[1]);
+/**
+ * Alpha testing looks up single texels, filtering would shrink or grow the cutouts. Texels with
+ * an alpha up to the cutoff of the material are cut away, MTL materials only cut away zero alpha
+ */
+pub fn is_transparent(mat: &Material, isect: &Intersection) -> bool {
+    let tex = &isect.tex;
+
+    // transparency via the alpha of a shader graph
+    if let Some(ref graph) = mat.graph {
+        return graph.eval_socket(Socket::Alpha, &Footprint::point(isect, TextureFilter::Nearest)).is_some_and(|alpha| alpha.x < 0.5);
+    }
+
+    // transparency via alpha texture
+    let alpha = match mat.alpha_texture.texture {
+        Texture::Alpha(ref alpha_texture) => {
+            let o = &mat.alpha_texture.options;
+            alpha_texture.nearest(&o.coords(tex), o.wrap)[o.channel.unwrap_or(0)]
         },
-        3 => {
-            let p = pix_c.to_rgb();
-            return (p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0, 255, 255);
+        Texture::Procedural(_) => sample_map(&mat.alpha_texture, &Footprint::point(isect, TextureFilter::Nearest)).x,
+        _ => 1.0,
+    };
+    if alpha <= mat.alpha_cutoff {
+        return true;
+    }
+    if let Texture::Diffuse(ref diffuse_texture) | Texture::Color(ref diffuse_texture) = mat.diffuse_texture.texture {
+        // transparency via diffuse texture, files without alpha are opaque and need not read their full resolution
+        if !diffuse_texture.has_alpha() {
+            return false;
+        }
+        let o = &mat.diffuse_texture.options;
+        if diffuse_texture.nearest(&o.coords(tex), o.wrap).w <= mat.alpha_cutoff {
+            return true;
+        }
+    }
+
+    return false;
+}
+
+/**
+ * Diffuse or base color, MTL diffuse maps replace the material color while color maps, such as
+ * glTF base color textures, are scaled by it
+ */
+pub fn diffuse_color(mat: &Material, fp: &Footprint, graph: &SocketValues) -> Vec3 {
+    if let Some(c) = graph.get(Socket::BaseColor) {
+        return c.truncate();
+    }
+
+    match mat.diffuse_texture.texture {
+        Texture::Diffuse(_) | Texture::Procedural(_) => return sample_map(&mat.diffuse_texture, fp).truncate(),
+        Texture::Color(_) => return color_value(&mat.diffuse_texture, fp, mat.diffuse),
+        _ => return mat.diffuse,
+    }
+}
+
+/**
+ * Color of a texture map scaled by the material color, as specified for the MTL color maps
+ */
+fn color_value(map: &TextureMap, fp: &Footprint, color: Vec3) -> Vec3 {
+    match map.texture {
+        Texture::Color(_) | Texture::Procedural(_) => return color * sample_map(map, fp).truncate(),
+        _ => return color,
+    }
+}
+
+pub fn specular_color(mat: &Material, fp: &Footprint, graph: &SocketValues) -> Vec3 {
+    if let Some(c) = graph.get(Socket::Specular) {
+        return c.truncate();
+    }
+
+    return color_value(&mat.specular_texture, fp, mat.specular);
+}
+
+pub fn emission_color(mat: &Material, fp: &Footprint, graph: &SocketValues) -> Vec3 {
+    if let Some(c) = graph.get(Socket::Emission) {
+        return c.truncate();
+    }
+
+    return color_value(&mat.emission_texture, fp, mat.emission);
+}
+
+/**
+ * Ambient occlusion from the ambient texture, the ambient color itself is not used for shading
+ */
+pub fn ambient_occlusion(mat: &Material, fp: &Footprint, graph: &SocketValues) -> Vec3 {
+    if let Some(c) = graph.get(Socket::Occlusion) {
+        return c.truncate();
+    }
+
+    return color_value(&mat.ambient_texture, fp, Vec3::ONE);
+}
+
+pub fn shininess(mat: &Material, fp: &Footprint, graph: &SocketValues) -> f32 {
+    if let Some(s) = graph.get(Socket::Shininess) {
+        return s.x;
+    }
+
+    match mat.shininess_texture.texture {
+        Texture::Scalar(_) | Texture::Procedural(_) => return mat.shininess * sample_map(&mat.shininess_texture, fp).x,
+        _ => return mat.shininess,
+    }
+}
+
+/**
+ * Shading normal at the intersection, perturbed by a tangent space normal map with green along
+ * +v or by the slopes of a bump map or a procedural texture. Bump heights span BUMP_DEPTH of one
+ * texture repeat, the slopes are central differences over one texel of the filtered heights.
+ * Both are scaled by the bump multiplier. Shader graphs give their own normal or heights
+ * References: http://www.mikktspace.com/, https://www.microsoft.com/en-us/research/publication/simulation-of-wrinkled-surfaces/
+ */
+pub fn shading_normal(mat: &Material, isect: &Intersection, fp: &Footprint, values: &SocketValues) -> Vec3 {
+    if let Some(ref graph) = mat.graph {
+        return graph_normal(graph, values, isect, fp);
+    }
+    let map = &mat.normal_texture;
+
+    // no tangent frame without texture coordinates, patterns in world space take any frame
+    let (tangent, bitangent) = match isect.tan.try_normalize() {
+        Some(tangent) => (tangent, isect.bitan.normalize()),
+        None => match map.texture {
+            Texture::Procedural(ref procedural) if procedural.space == TextureSpace::World => onb(&isect.nrm),
+            _ => return isect.nrm,
+        },
+    };
+
+    let bump_multiplier = map.options.bump_multiplier;
+    match map.texture {
+        Texture::Normal(_) => {
+            let m = sample_map(map, fp).truncate() * 2.0 - Vec3::ONE;
+            let n = (isect.tan * m.x + isect.bitan * m.y) * bump_multiplier + isect.nrm * m.z;
+            return n.try_normalize().unwrap_or(isect.nrm);
+        },
+        Texture::Bump(ref bump_texture) => {
+            // offsets by one texel, before the texture scale is applied
+            let du = Vec2::new(1.0 / bump_texture.width() as f32, 0.0) / map.options.scale.x;
+            let dv = Vec2::new(0.0, 1.0 / bump_texture.height() as f32) / map.options.scale.y;
+            let height = |tex: Vec2| sample_map(map, &Footprint { tex, ..*fp }).x;
+
+            // texture rows run against v, the stored coordinate is flipped
+            let slope = BUMP_DEPTH * bump_multiplier;
+            let dh_du = (height(fp.tex + du) - height(fp.tex - du)) / (2.0 * du.x) * slope;
+            let dh_dv = (height(fp.tex - dv) - height(fp.tex + dv)) / (2.0 * dv.y) * slope;
+            let n = isect.nrm - tangent * dh_du - bitangent * dh_dv;
+            return n.try_normalize().unwrap_or(isect.nrm);
         },
-        4 => {
-            let p = pix_c.to_rgba();
-            return (p[0] as f32 / 255.0, p[1] as f32 / 255.0, p[2] as f32 / 255.0, 255, p[3]);
+        Texture::Procedural(ref procedural) => {
+            let slope = BUMP_DEPTH * bump_multiplier;
+            let (dh_du, dh_dv) = procedural.slopes(&map.options.footprint(fp), tangent, bitangent);
+            let n = isect.nrm - tangent * dh_du * slope - bitangent * dh_dv * slope;
+            return n.try_normalize().unwrap_or(isect.nrm);
         },
-        _ => return (0.0, 0.0, 0.0, 255, 255)
+        _ => return isect.nrm,
+    }
+}
+
+/**
+ * Shading normal of a shader graph, from its tangent space normal or the slopes of its bump
+ * heights. The heights are evaluated at offsets of half the footprint along the texture
+ * coordinates, moving the position along with them, or along any tangent frame in world units
+ * without texture coordinates. Heights span BUMP_DEPTH of one unit like bump maps
+ */
+fn graph_normal(graph: &ShaderGraph, values: &SocketValues, isect: &Intersection, fp: &Footprint) -> Vec3 {
+    let tangent_frame = isect.tan.try_normalize().map(|tangent| (tangent, isect.bitan.normalize()));
+    let (tangent, bitangent) = tangent_frame.unwrap_or_else(|| onb(&isect.nrm));
+
+    if let Some(m) = values.get(Socket::Normal) {
+        let m = m.truncate() * 2.0 - Vec3::ONE;
+        let n = tangent * m.x + bitangent * m.y + isect.nrm * m.z;
+        return n.try_normalize().unwrap_or(isect.nrm);
+    }
+    if !graph.is_connected(Socket::Bump) {
+        return isect.nrm;
+    }
+
+    let height = |tex: Vec2, pos: Vec3| graph.eval_socket(Socket::Bump, &Footprint { tex, pos, ..*fp }).unwrap().x;
+    let (dh_du, dh_dv) = match tangent_frame {
+        Some(_) => {
+            // the stored v coordinate is flipped
+            let step = (0.5 * fp.axis_a.length().max(fp.axis_b.length())).max(MIN_BUMP_STEP);
+            let (du, dv) = (Vec2::new(step, 0.0), Vec2::new(0.0, step));
+            let (dpdu, dpdv) = (isect.dpdu * step, isect.dpdv * step);
+            let dh_du = (height(fp.tex + du, fp.pos + dpdu) - height(fp.tex - du, fp.pos - dpdu)) / (2.0 * step);
+            let dh_dv = (height(fp.tex - dv, fp.pos + dpdv) - height(fp.tex + dv, fp.pos - dpdv)) / (2.0 * step);
+            (dh_du, dh_dv)
+        },
+        None => {
+            let step = (0.5 * fp.width).max(MIN_BUMP_STEP);
+            let (dt, db) = (tangent * step, bitangent * step);
+            let dh_dt = (height(fp.tex, fp.pos + dt) - height(fp.tex, fp.pos - dt)) / (2.0 * step);
+            let dh_db = (height(fp.tex, fp.pos + db) - height(fp.tex, fp.pos - db)) / (2.0 * step);
+            (dh_dt, dh_db)
+        },
+    };
+
+    let n = isect.nrm - (tangent * dh_du + bitangent * dh_dv) * BUMP_DEPTH;
+    return n.try_normalize().unwrap_or(isect.nrm);
+}
+
+fn scalar_value(graph: &SocketValues, socket: Socket, map: &TextureMap, fp: &Footprint, value: f32) -> f32 {
+    if let Some(v) = graph.get(socket) {
+        return v.x;
+    }
+
+    match map.texture {
+        Texture::Scalar(_) | Texture::Procedural(_) => return sample_map(map, fp).x,
+        _ => return value,
+    }
+}
+
+pub fn metallic_roughness(mat: &Material, fp: &Footprint, graph: &SocketValues, base_color: Vec3) -> MetallicRoughness {
+    return MetallicRoughness {
+        base_color,
+        metallic: scalar_value(graph, Socket::Metallic, &mat.metallic_texture, fp, mat.metallic),
+        roughness: scalar_value(graph, Socket::Roughness, &mat.roughness_texture, fp, mat.roughness),
+    };
+}
+
+/**
+ * Principled BSDF at the intersection, with the normal facing the incoming ray and the tangent
+ * following the texture coordinates rotated by the anisotropic rotation
+ */
+pub fn principled(mat: &Material, isect: &Intersection, fp: &Footprint, graph: &SocketValues, base_color: Vec3, entering: bool) -> Principled {
+    let normal = isect.nrm;
+    let tangent = match (isect.tan - normal * normal.dot(isect.tan)).try_normalize() {
+        Some(tangent) => tangent,
+        None => onb(&normal).0,
+    };
+    let anisotropic_rotation = graph_scalar(graph, Socket::AnisotropicRotation, mat.anisotropic_rotation);
+    let (sin, cos) = (anisotropic_rotation * 2.0 * std::f32::consts::PI).sin_cos();
+    let tangent = tangent * cos + normal.cross(tangent) * sin;
+
+    return Principled {
+        base_color,
+        metallic: scalar_value(graph, Socket::Metallic, &mat.metallic_texture, fp, mat.metallic),
+        roughness: scalar_value(graph, Socket::Roughness, &mat.roughness_texture, fp, mat.roughness),
+        subsurface: graph_scalar(graph, Socket::Subsurface, mat.subsurface),
+        specular_tint: graph_scalar(graph, Socket::SpecularTint, mat.specular_tint),
+        anisotropic: graph_scalar(graph, Socket::Anisotropic, mat.anisotropic),
+        sheen: graph_scalar(graph, Socket::Sheen, mat.sheen),
+        sheen_tint: graph_scalar(graph, Socket::SheenTint, mat.sheen_tint),
+        clearcoat: graph_scalar(graph, Socket::Clearcoat, mat.clearcoat),
+        clearcoat_roughness: graph_scalar(graph, Socket::ClearcoatRoughness, mat.clearcoat_roughness),
+        transmission: graph_scalar(graph, Socket::Transmission, mat.transmission),
+        ior: mat.ior,
+        entering,
+        tangent,
+        bitangent: normal.cross(tangent),
+        normal,
+    };
+}
+
+fn in_shadow(scene: &Scene, isect: &Intersection, dir: &Vec3, max_t: f32, time: f32) -> bool {
+    let l_ray = Ray::new(isect.pos + isect.nrm * EPSILON, *dir);
+
+    match scene.intersect(&l_ray, max_t, time) {
+        // in shadow unless the occluder is transparent at the hit point
+        Some(l_hit_result) => {
+            let l_hit_mat = scene.materials.get(l_hit_result.mat).unwrap();
+            return !is_transparent(l_hit_mat, &l_hit_result);
+        },
+        None => return false,
+    }
+}
+
+impl Renderer {
+    pub fn from_name(name: &str) -> Option<Renderer> {
+        match name {
+            "raytracer" => return Some(Renderer::RAYTRACER(Raytracer)),
+            "pathtracer" => return Some(Renderer::PATHTRACER(Pathtracer)),
+            _ => return None,
+        }
+    }
+
+    /**
+     * Radiance arriving along the camera ray, the scene is evaluated at the ray time
+     */
+    pub fn trace<R: Rng>(&self, scene: &Scene, ray: &Ray, cone: &RayCone, time: f32, rng: &mut R) -> Vec3 {
+        match self {
+            Renderer::RAYTRACER(_) => return Raytracer::trace(scene, ray, cone, time, 0),
+            Renderer::PATHTRACER(_) => return Pathtracer::trace(scene, ray, cone, time, rng),
+        }
     }
 }
 
 impl Raytracer {
-    pub fn trace(scene: &Scene, ray: &Ray, n: u8) -> Vec3 {
+    const MAX_DEPTH: u8 = 15;
+    const SPLIT_DEPTH: u8 = 6;
+
+    pub fn trace(scene: &Scene, ray: &Ray, cone: &RayCone, time: f32, n: u8) -> Vec3 {
         // limit recursion
-        if n > 15 {
+        if n > Raytracer::MAX_DEPTH {
             return RESULT_NULL;
         }
 
-        // get ref to BVH
-        let bvh = scene.bvh.as_ref().unwrap();
-
         // find closest intersection
-        let hits = bvh.traverse(&ray, &scene.shapes);
-        let mut hit_dist = f32::MAX;
-        let mut hit_isect: Option<Intersection> = None;
-        for hit in hits {
-            match hit.intersect(&ray) {
-                Some(hit_result) => {
-                    if hit_result.t < hit_dist {
-                        hit_dist = hit_result.t;
-                        hit_isect = Some(hit_result);
-                    }
-                },
-                None => (),
-            }
-        }
+        let hit_isect = scene.intersect(ray, f32::MAX, time);
 
         // calculate shading
         let mut result = RESULT_NULL;
         match hit_isect {
-            Some(hit_result) => {
+            Some(mut hit_result) => {
                 // get reference to material
                 let hit_mat = scene.materials.get(hit_result.mat).unwrap();
 
-                // transparency via alpha texture
-                if let Texture::Alpha(ref alpha_texture) = hit_mat.alpha_texture {
-                    let c = sample_texture(alpha_texture, &hit_result.tex);
-                    if c.3 == 0 {
-                        let n_ray = Ray::new(hit_result.pos, ray.direction);
-                        return result + Raytracer::trace(scene, &n_ray, n + 1);
-                    }
+                // transparency via alpha or diffuse texture
+                if is_transparent(hit_mat, &hit_result) {
+                    let n_ray = Ray::new(hit_result.pos, ray.direction);
+                    return result + Raytracer::trace(scene, &n_ray, &cone.propagate(hit_result.t), time, n + 1);
                 }
-                
-                // transparency via diffuse texture
-                let mut d_color = hit_mat.diffuse;
-                if let Texture::Diffuse(ref diffuse_texture) = hit_mat.diffuse_texture {
-                    let c = sample_texture(diffuse_texture, &hit_result.tex);
-                    if c.4 == 0 {
-                        let n_ray = Ray::new(hit_result.pos, ray.direction);
-                        return result + Raytracer::trace(scene, &n_ray, n + 1);
-                    }
-                    d_color = Vec3::new(c.0, c.1, c.2);
-                }
-                
-                // calculate shading by each light source
-                for light in &scene.lights {
-                    let we = light.eval_we(&hit_result.pos);
-                    let we_normalized = we.normalize();
-                    let le = light.eval_le(&we);
-
-                    // check if in shadow
-                    let l_ray = Ray::new(hit_result.pos + hit_result.nrm * EPSILON, -we_normalized);
-                    let l_maxt = we.length();
-                    let l_hits = bvh.traverse(&l_ray, &scene.shapes);
-                    let mut l_hit_dist = l_maxt;
-                    let mut l_hit_isect: Option<Intersection> = None;
-                    for l_hit in l_hits {
-                        match l_hit.intersect(&l_ray) {
-                            Some(l_hit_result) => {
-                                if l_hit_result.t < l_hit_dist {
-                                    l_hit_dist = l_hit_result.t;
-                                    l_hit_isect = Some(l_hit_result);
-                                }
-                            },
-                            None => (),
-                        }
-                    }
 
-                    let mut l_shadow = false;
-                    match l_hit_isect {
-                        Some(l_hit_result) => {
-                            // in shadow by default
-                            l_shadow = true;
-
-                            // check for transparency
-                            let l_hit_mat = scene.materials.get(l_hit_result.mat).unwrap();
-                            if let Texture::Alpha(ref alpha_texture) = l_hit_mat.alpha_texture {
-                                // transparency via alpha texture
-                                let c = sample_texture(alpha_texture, &l_hit_result.tex);
-                                if c.3 == 0 {
-                                    l_shadow = false;
-                                }
-                            } else if let Texture::Diffuse(ref diffuse_texture) = l_hit_mat.diffuse_texture  {
-                                // transparency via diffuse texture
-                                let c = sample_texture(diffuse_texture, &l_hit_result.tex);
-                                if c.4 == 0 {
-                                    l_shadow = false;
-                                }
-                            }
-                        },
-                        None => (),
+                // texture footprint of the ray cone, secondary rays continue the cone from the hit point
+                let cone = cone.propagate(hit_result.t);
+                let fp = Footprint::new(&hit_result, &ray.direction, cone.width, scene.texture_filter);
+                let graph = graph_values(hit_mat, &fp);
+                let d_color = diffuse_color(hit_mat, &fp, &graph);
+                let s_color = specular_color(hit_mat, &fp, &graph);
+
+                // rays hitting a back face travelled inside the object, reflect off the side facing the ray
+                let entering = hit_result.nrm.dot(ray.direction) < 0.0;
+                hit_result.nrm = shading_normal(hit_mat, &hit_result, &fp, &graph);
+                let nrm = if entering { hit_result.nrm } else { -hit_result.nrm };
+
+                if hit_mat.surface == Surface::Glass {
+                    result += Raytracer::trace_dielectric(scene, ray, &cone, &hit_result.pos, &nrm, entering, hit_mat, time, n);
+                } else {
+                    let mut isect = hit_result;
+                    isect.nrm = nrm;
+                    let brdf = metallic_roughness(hit_mat, &fp, &graph, d_color);
+                    let bsdf = principled(hit_mat, &isect, &fp, &graph, d_color, entering);
+
+                    // calculate shading by each light source
+                    for light in &scene.lights {
+                        let we = light.eval_we(&hit_result.pos);
+                        let we_normalized = we.normalize();
+                        let le = light.eval_le(&we);
+
+                        // check if in shadow
+                        let l_shadow = in_shadow(scene, &hit_result, &-we_normalized, light.eval_dist(&we), time);
+
+                        // pre-calc stuff
+                        let reflection = reflect(&we_normalized, &hit_result.nrm).normalize();
+
+                        // apply shading, light emission is what a white lambertian surface reflects, hence the factor of pi
+                        if !l_shadow && hit_mat.surface == Surface::MetallicRoughness {
+                            result += le * brdf.eval(&nrm, &-ray.direction, &-we_normalized) * std::f32::consts::PI;
+                        } else if !l_shadow && hit_mat.surface == Surface::Principled {
+                            result += le * bsdf.eval(&-ray.direction, &-we_normalized) * std::f32::consts::PI;
+                        } else if !l_shadow {
+                            // diffuse
+                            let brdf_d = hit_mat.brdf_lambertian(&hit_result.nrm, &-we_normalized);
+
+                            // specular
+                            let brdf_s = hit_mat.brdf_phong(&reflection, &-ray.direction, shininess(hit_mat, &fp, &graph));
+
+                            result += le * (d_color * brdf_d + s_color * brdf_s);
+                        }
                     }
 
-                    // pre-calc stuff
-                    let reflection = reflect(&we_normalized, &hit_result.nrm).normalize();
-                    
-                    // apply shading
-                    if !l_shadow {
-                        // diffuse
-                        let brdf_d = hit_mat.brdf_lambertian(&hit_result.nrm, &-we_normalized);
+                    // ambient light, darkened by the ambient occlusion texture
+                    result += scene.ambient * d_color * ambient_occlusion(hit_mat, &fp, &graph);
 
-                        // specular
-                        let brdf_s = hit_mat.brdf_phong(&reflection, &-ray.direction);
+                    // principled transmission along the ideal refraction and reflection directions
+                    if hit_mat.surface == Surface::Principled && bsdf.transmission > 0.0 {
+                        let weight = d_color * (1.0 - bsdf.metallic) * bsdf.transmission;
+                        result += weight * Raytracer::trace_dielectric(scene, ray, &cone, &hit_result.pos, &nrm, entering, hit_mat, time, n);
+                    }
 
-                        result += le * (d_color * brdf_d + d_color * brdf_s);
+                    // mirror reflection tinted by the specular color
+                    if hit_mat.surface == Surface::Mirror {
+                        let r_ray = Ray::new(hit_result.pos + nrm * EPSILON, reflect(&ray.direction, &nrm));
+                        result += s_color * Raytracer::trace(scene, &r_ray, &cone, time, n + 1);
                     }
                 }
 
-                // ambient light
-                result += scene.ambient * d_color;
-
                 // emissive light
-                result += hit_mat.emission;
+                result += emission_color(hit_mat, &fp, &graph);
+
+                // partial transparency via the dissolve factor
+                if hit_mat.dissolve < 1.0 {
+                    let n_ray = Ray::new(hit_result.pos, ray.direction);
+                    let behind = Raytracer::trace(scene, &n_ray, &cone, time, n + 1);
+                    result = result * hit_mat.dissolve + behind * (1.0 - hit_mat.dissolve);
+                }
+
+                // absorption along the path through coloured glass
+                if !entering && hit_mat.surface == Surface::Glass {
+                    result *= hit_mat.absorption(hit_result.t);
+                }
             },
             None => {
                 result += scene.ambient;
             },
         }
-        
+
+        return result;
+    }
+
+    /**
+     * Fresnel-weighted sum of the reflected and refracted radiance, deep paths only follow the
+     * dominant direction to avoid an exponential number of rays
+     */
+    #[allow(clippy::too_many_arguments)]
+    fn trace_dielectric(scene: &Scene, ray: &Ray, cone: &RayCone, pos: &Vec3, nrm: &Vec3, entering: bool, mat: &Material, time: f32, n: u8) -> Vec3 {
+        let r_ray = Ray::new(*pos + *nrm * EPSILON, reflect(&ray.direction, nrm));
+        let t_dir = match refract(&ray.direction, nrm, mat.eta(entering)) {
+            Some(t_dir) => t_dir,
+            None => return Raytracer::trace(scene, &r_ray, cone, time, n + 1),
+        };
+        let t_ray = Ray::new(*pos - *nrm * EPSILON, t_dir);
+
+        let f = mat.fresnel_schlick(nrm, &-ray.direction, entering);
+        if n >= Raytracer::SPLIT_DEPTH {
+            match f > 0.5 {
+                true => return Raytracer::trace(scene, &r_ray, cone, time, n + 1),
+                false => return Raytracer::trace(scene, &t_ray, cone, time, n + 1),
+            }
+        }
+
+        return Raytracer::trace(scene, &r_ray, cone, time, n + 1) * f + Raytracer::trace(scene, &t_ray, cone, time, n + 1) * (1.0 - f);
+    }
+}
+
+impl Pathtracer {
+    const MAX_DEPTH: u8 = 16;
+    const RR_DEPTH: u8 = 3;
+
+    /**
+     * Unidirectional path tracing with next-event estimation towards the scene lights
+     * Reference: https://www.pbr-book.org
+     */
+    pub fn trace<R: Rng>(scene: &Scene, ray: &Ray, cone: &RayCone, time: f32, rng: &mut R) -> Vec3 {
+        let mut result = RESULT_NULL;
+        let mut throughput = Vec3::ONE;
+        let mut ray = Ray::new(ray.origin, ray.direction);
+        let mut cone = *cone;
+
+        for n in 0..Pathtracer::MAX_DEPTH {
+            // find closest intersection, escaped paths pick up the ambient term as environment
+            let hit_result = match scene.intersect(&ray, f32::MAX, time) {
+                Some(hit_result) => hit_result,
+                None => {
+                    result += throughput * scene.ambient;
+                    break;
+                },
+            };
+            let hit_mat = scene.materials.get(hit_result.mat).unwrap();
+            cone = cone.propagate(hit_result.t);
+
+            // absorption along the path through coloured glass
+            let entering = hit_result.nrm.dot(ray.direction) < 0.0;
+            if !entering && hit_mat.surface == Surface::Glass {
+                throughput *= hit_mat.absorption(hit_result.t);
+            }
+
+            // transparency via alpha or diffuse texture or the dissolve factor, continue the path unchanged
+            if is_transparent(hit_mat, &hit_result) || (hit_mat.dissolve < 1.0 && rng.gen::<f32>() >= hit_mat.dissolve) {
+                ray = Ray::new(hit_result.pos, ray.direction);
+                continue;
+            }
+
+            // emissive light, textures are filtered over the footprint of the ray cone
+            let fp = Footprint::new(&hit_result, &ray.direction, cone.width, scene.texture_filter);
+            let graph = graph_values(hit_mat, &fp);
+            result += throughput * emission_color(hit_mat, &fp, &graph);
+
+            // shade with the normal facing the incoming ray
+            let mut isect = hit_result;
+            isect.nrm = shading_normal(hit_mat, &isect, &fp, &graph);
+            if !entering {
+                isect.nrm = -isect.nrm;
+            }
+            let d_color = diffuse_color(hit_mat, &fp, &graph);
+            let s_color = specular_color(hit_mat, &fp, &graph);
+            let r_ray = Ray::new(isect.pos + isect.nrm * EPSILON, reflect(&ray.direction, &isect.nrm));
+
+            // specular surfaces continue along a single direction, light sampling cannot hit it
+            match hit_mat.surface {
+                Surface::Glass => {
+                    // choose between reflection and refraction by the fresnel term
+                    let f = hit_mat.fresnel_schlick(&isect.nrm, &-ray.direction, entering);
+                    ray = match refract(&ray.direction, &isect.nrm, hit_mat.eta(entering)) {
+                        Some(t_dir) if rng.gen::<f32>() >= f => Ray::new(isect.pos - isect.nrm * EPSILON, t_dir),
+                        _ => r_ray,
+                    };
+                    continue;
+                },
+                Surface::Mirror => {
+                    // choose the mirror reflection by its share of the reflectance, the rest is diffuse
+                    let s = luminance(&s_color);
+                    let p = s / (s + luminance(&d_color)).max(EPSILON);
+                    if rng.gen::<f32>() < p {
+                        throughput *= s_color / p;
+                        ray = r_ray;
+                        continue;
+                    }
+                    throughput /= 1.0 - p;
+                },
+                Surface::Diffuse | Surface::MetallicRoughness | Surface::Principled => {},
+            }
+
+            // next-event estimation, direct light from each light source
+            let brdf = metallic_roughness(hit_mat, &fp, &graph, d_color);
+            let bsdf = principled(hit_mat, &isect, &fp, &graph, d_color, entering);
+            for light in &scene.lights {
+                let we = light.eval_we(&isect.pos);
+                let wi = -we.normalize();
+                let cos_theta = isect.nrm.dot(wi);
+                if cos_theta <= 0.0 {
+                    continue;
+                }
+
+                if !in_shadow(scene, &isect, &wi, light.eval_dist(&we), time) {
+                    // light emission is what a white lambertian surface reflects, hence the factor of pi
+                    let f = match hit_mat.surface {
+                        Surface::MetallicRoughness => brdf.eval(&isect.nrm, &-ray.direction, &wi) * std::f32::consts::PI,
+                        Surface::Principled => bsdf.eval(&-ray.direction, &wi) * std::f32::consts::PI,
+                        _ => d_color * cos_theta,
+                    };
+                    result += throughput * f * light.eval_le(&we);
+                }
+            }
+
+            // russian roulette, terminate low contribution paths
+            if n >= Pathtracer::RR_DEPTH {
+                let p = throughput.max_element().min(0.95);
+                if rng.gen::<f32>() >= p {
+                    break;
+                }
+                throughput /= p;
+            }
+
+            match hit_mat.surface {
+                // importance sampled microfacet bounce, the weight holds the brdf, cosine and pdf terms
+                Surface::MetallicRoughness => {
+                    match brdf.sample(&isect.nrm, &-ray.direction, rng.gen(), Vec2::new(rng.gen(), rng.gen())) {
+                        Some((dir, weight)) => {
+                            throughput *= weight;
+                            ray = Ray::new(isect.pos + isect.nrm * EPSILON, dir);
+                        },
+                        None => break,
+                    }
+                },
+                // principled lobes, transmitted directions continue below the surface
+                Surface::Principled => {
+                    match bsdf.sample(&-ray.direction, rng.gen(), Vec2::new(rng.gen(), rng.gen())) {
+                        Some((dir, weight)) => {
+                            let offset = if dir.dot(isect.nrm) < 0.0 { -EPSILON } else { EPSILON };
+                            throughput *= weight;
+                            ray = Ray::new(isect.pos + isect.nrm * offset, dir);
+                        },
+                        None => break,
+                    }
+                },
+                // cosine-weighted diffuse bounce, the pdf cancels the cosine and 1/pi terms of the lambertian brdf
+                _ => {
+                    let dir = sample_cosine_hemisphere(&isect.nrm, rng.gen(), rng.gen());
+                    throughput *= d_color;
+                    ray = Ray::new(isect.pos + isect.nrm * EPSILON, dir);
+                },
+            }
+        }
+
         return result;
     }
 }
@@ -0,0 +1,44 @@
+use glam::Vec2;
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+
+/**
+ * Per-pixel sample generator, jitters sub-pixel positions over a stratified grid
+ */
+pub struct Sampler {
+    pub spp: u32,
+    pub rng: SmallRng,
+    strata: u32,
+}
+
+impl Sampler {
+    pub fn new(spp: u32, seed: u64) -> Sampler {
+        Sampler {
+            spp,
+            rng: SmallRng::seed_from_u64(seed),
+            strata: (spp as f32).sqrt() as u32,
+        }
+    }
+
+    /**
+     * Returns the sub-pixel offset in [0, 1)^2 for the i-th sample of the pixel, samples
+     * that do not fit the square grid are placed uniformly at random
+     */
+    pub fn get_pixel_sample(&mut self, i: u32) -> Vec2 {
+        let jitter = self.get_2d();
+        if i >= self.strata * self.strata {
+            return jitter;
+        }
+
+        let cell = Vec2::new((i % self.strata) as f32, (i / self.strata) as f32);
+        return (cell + jitter) / self.strata as f32;
+    }
+
+    pub fn get_1d(&mut self) -> f32 {
+        return self.rng.gen();
+    }
+
+    pub fn get_2d(&mut self) -> Vec2 {
+        return Vec2::new(self.rng.gen(), self.rng.gen());
+    }
+}
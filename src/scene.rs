@@ -1,28 +1,128 @@
+use bvh::aabb::AABB;
 use bvh::bvh::BVH;
-use glam::Vec3;
+use bvh::ray::Ray;
+use glam::{Vec3, Mat4};
 
-use crate::{triangle::Triangle, material::Material, camera::Camera, light::Light};
+use crate::{
+    material::Material, camera::Camera, light::Light, intersection::Intersection,
+    instance::Instance, mesh::Mesh, transform::AnimatedTransform, texture::TextureFilter,
+    texture_cache::TextureCache,
+};
 
 use std::collections::HashMap;
 
 pub struct Scene {
-    pub shapes: Vec<Triangle>,
+    pub meshes: Vec<Mesh>,
+    pub instances: Vec<Instance>,
     pub materials: HashMap<String, Material>,
+    pub objects: Vec<String>,
     pub ambient: Vec3,
     pub lights: Vec<Box<dyn Light + Sync>>,
     pub bvh: Option<BVH>,
     pub camera: Camera,
+    pub texture_filter: TextureFilter,
+    pub textures: TextureCache,
 }
 
 impl Scene {
     pub fn new(camera: Camera) -> Scene {
         Scene {
-            shapes: Vec::new(),
+            meshes: Vec::new(),
+            instances: Vec::new(),
             materials: HashMap::new(),
+            objects: Vec::new(),
             ambient: Vec3::new(0.0, 0.0, 0.0),
             lights: Vec::new(),
             bvh: None,
             camera,
+            texture_filter: TextureFilter::Trilinear,
+            textures: TextureCache::new(None),
         }
     }
+
+    /**
+     * Places a mesh in the scene, object ids of its instances are kept unique
+     */
+    pub fn add_instance(&mut self, mesh_idx: usize, obj_to_world: Mat4) {
+        let mesh = &self.meshes[mesh_idx];
+        let obj_offset = self.objects.len() as u32;
+        let instance_idx = self.instances.len();
+        for name in &mesh.objects {
+            self.objects.push(format!("{}#{}/{}", mesh.name, instance_idx, name));
+        }
+
+        self.instances.push(Instance::new(mesh_idx, mesh, obj_to_world, obj_offset));
+    }
+
+    /**
+     * Places a mesh moving along a keyframed transform, offset is applied before the motion
+     */
+    pub fn add_animated_instance(&mut self, mesh_idx: usize, motion: AnimatedTransform, offset: Mat4) {
+        let mesh = &self.meshes[mesh_idx];
+        let obj_offset = self.objects.len() as u32;
+        let instance_idx = self.instances.len();
+        for name in &mesh.objects {
+            self.objects.push(format!("{}#{}/{}", mesh.name, instance_idx, name));
+        }
+
+        let (open, close) = (self.camera.shutter_open, self.camera.shutter_close);
+        self.instances.push(Instance::animated(mesh_idx, mesh, motion, offset, obj_offset, open, close));
+    }
+
+    /**
+     * Builds the top level BVH over all instances, mesh BVHs must already be built
+     */
+    pub fn build(&mut self) {
+        println!("constructing scene, instance_count: {} ...", self.instances.len());
+        self.bvh = Some(BVH::build(&mut self.instances));
+    }
+
+    /**
+     * Moves the shutter interval of the camera, e.g. to the next frame of an animation. Animated
+     * instances are bounded over the new interval and the top level BVH is rebuilt for them
+     */
+    pub fn set_shutter(&mut self, open: f32, close: f32) {
+        if open == self.camera.shutter_open && close == self.camera.shutter_close {
+            return;
+        }
+        self.camera.shutter_open = open;
+        self.camera.shutter_close = close;
+        self.camera.trf = self.camera.transform_at(open);
+
+        let mut animated = false;
+        for instance in self.instances.iter_mut().filter(|i| i.motion.is_some()) {
+            instance.update_bounds(&self.meshes[instance.mesh], open, close);
+            animated = true;
+        }
+        if animated {
+            self.build();
+        }
+    }
+
+    /**
+     * Bounds of all instances, the scene must already be built
+     */
+    pub fn bounds(&self) -> AABB {
+        return self.instances.iter().fold(AABB::empty(), |aabb, i| aabb.join(&i.aabb));
+    }
+
+    /**
+     * Finds the closest intersection along the ray that lies nearer than max_t, with animated
+     * instances placed at the given time
+     */
+    pub fn intersect(&self, ray: &Ray, max_t: f32, time: f32) -> Option<Intersection<'_>> {
+        let bvh = self.bvh.as_ref().unwrap();
+        let hits = bvh.traverse(ray, &self.instances);
+
+        let mut hit_dist = max_t;
+        let mut hit_isect: Option<Intersection> = None;
+        for hit in hits {
+            if let Some(hit_result) = hit.intersect(ray, &self.meshes[hit.mesh], hit_dist, time) {
+                hit_dist = hit_result.t;
+                hit_isect = Some(hit_result);
+            }
+        }
+
+        return hit_isect;
+    }
 }
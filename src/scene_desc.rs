@@ -0,0 +1,1521 @@
+use glam::{Affine2, EulerRot, Mat4, Quat, Vec2, Vec3, Vec4};
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::camera::{Aperture, ApertureImage, Camera, Convergence, FovAxis, Projection, Stereo};
+use crate::distortion::Distortion;
+use crate::material::{Material, Surface, Texture, TextureMap, TextureOptions, PRINCIPLED_IOR};
+use crate::procedural::{Pattern, ProceduralTexture};
+use crate::renderer::Renderer;
+use crate::sensor::Sensor;
+use crate::shader_graph::{Input, Node, ShaderGraph, Socket};
+use crate::texture::{Channels, ColorSpace, WrapMode};
+use crate::texture_cache::TextureCache;
+use crate::animation::{Interpolation, Keyframe, Track};
+use crate::transform::{AnimatedTransform, Transform};
+
+/**
+ * Typed description of a scene JSON file
+ */
+#[derive(Debug, Deserialize)]
+pub struct SceneDesc {
+    #[serde(default)]
+    pub renderer: Option<String>,
+    pub camera: CameraDesc,
+    #[serde(default)]
+    pub models: Vec<ModelDesc>,
+    #[serde(default)]
+    pub lights: Vec<LightDesc>,
+    #[serde(default)]
+    pub animation: Option<AnimationDesc>,
+    #[serde(default)]
+    pub textures: BTreeMap<String, ProceduralTexture>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDesc>,
+}
+
+/**
+ * Principled BSDF parameters overriding the OBJ material of the same name, values that are
+ * not given keep the ones loaded from the MTL file. A base color replaces Kd and map_Kd.
+ * Texture fields name procedural textures of the scene that replace the maps of the material.
+ * Alternatively a graph of nodes replaces the material, the output names its BSDF node
+ */
+#[derive(Debug, Default, Deserialize)]
+pub struct MaterialDesc {
+    #[serde(default)]
+    pub base_color: Option<Vec3>,
+    #[serde(default)]
+    pub emission: Option<Vec3>,
+    #[serde(default)]
+    pub metallic: Option<f32>,
+    #[serde(default)]
+    pub roughness: Option<f32>,
+    #[serde(default)]
+    pub subsurface: Option<f32>,
+    #[serde(default)]
+    pub specular_tint: Option<f32>,
+    #[serde(default)]
+    pub anisotropic: Option<f32>,
+    #[serde(default)]
+    pub anisotropic_rotation: Option<f32>,
+    #[serde(default)]
+    pub sheen: Option<f32>,
+    #[serde(default)]
+    pub sheen_tint: Option<f32>,
+    #[serde(default)]
+    pub clearcoat: Option<f32>,
+    #[serde(default)]
+    pub clearcoat_roughness: Option<f32>,
+    #[serde(default)]
+    pub transmission: Option<f32>,
+    #[serde(default)]
+    pub ior: Option<f32>,
+    #[serde(default)]
+    pub base_color_texture: Option<String>,
+    #[serde(default)]
+    pub alpha_texture: Option<String>,
+    #[serde(default)]
+    pub metallic_texture: Option<String>,
+    #[serde(default)]
+    pub roughness_texture: Option<String>,
+    #[serde(default)]
+    pub emission_texture: Option<String>,
+    #[serde(default)]
+    pub bump_texture: Option<String>,
+    #[serde(default)]
+    pub bump_multiplier: Option<f32>,
+    #[serde(default)]
+    pub nodes: BTreeMap<String, NodeDesc>,
+    #[serde(default)]
+    pub output: Option<String>,
+}
+
+/**
+ * Node of a material graph. Image and triplanar nodes sample image files, texture nodes the
+ * procedural textures of the scene. Images are sRGB encoded unless they feed a socket of other
+ * data than colors, or the node gives its color space. Uv transform nodes scale, rotate (in degrees, about the
+ * origin) and offset the texture coordinates of the nodes naming them as uv, after their own
+ * uv. Math nodes mix, multiply, remap and clamp their inputs. The BSDF nodes principled,
+ * metallic_roughness and diffuse take the inputs of their surface type
+ */
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeDesc {
+    Image {
+        file: String,
+        #[serde(default)]
+        color_space: Option<ColorSpace>,
+        #[serde(default = "default_wrap")]
+        wrap: String,
+        #[serde(default)]
+        uv: Option<String>,
+    },
+    Texture {
+        texture: String,
+        #[serde(default)]
+        uv: Option<String>,
+    },
+    Triplanar {
+        file: String,
+        #[serde(default)]
+        color_space: Option<ColorSpace>,
+        #[serde(default = "default_wrap")]
+        wrap: String,
+        #[serde(default = "default_triplanar_scale")]
+        scale: f32,
+        #[serde(default = "default_sharpness")]
+        sharpness: f32,
+    },
+    UvTransform {
+        #[serde(default = "default_uv_scale")]
+        scale: Vec2,
+        #[serde(default)]
+        rotation: f32,
+        #[serde(default)]
+        offset: Vec2,
+        #[serde(default)]
+        uv: Option<String>,
+    },
+    Mix {
+        a: InputDesc,
+        b: InputDesc,
+        factor: InputDesc,
+    },
+    Multiply {
+        a: InputDesc,
+        b: InputDesc,
+    },
+    Remap {
+        input: InputDesc,
+        #[serde(default = "default_range")]
+        from: Vec2,
+        #[serde(default = "default_range")]
+        to: Vec2,
+    },
+    Clamp {
+        input: InputDesc,
+        #[serde(default = "default_clamp_min")]
+        min: InputDesc,
+        #[serde(default = "default_clamp_max")]
+        max: InputDesc,
+    },
+    Principled(BTreeMap<String, InputDesc>),
+    MetallicRoughness(BTreeMap<String, InputDesc>),
+    Diffuse(BTreeMap<String, InputDesc>),
+}
+
+/**
+ * Node input, a scalar for all channels, an RGB color with alpha one, an RGBA color or the
+ * name of a node. A suffix .r, .g, .b or .a to the name takes a single channel of the node
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum InputDesc {
+    Scalar(f32),
+    Color(Vec<f32>),
+    Link(String),
+}
+
+/**
+ * Frame range of an animated sequence with camera keyframes and light parameter tracks.
+ * Key times are given in frames, model and camera motion share the same time line
+ */
+#[derive(Debug, Deserialize)]
+pub struct AnimationDesc {
+    #[serde(default = "default_frame_start")]
+    pub frame_start: u32,
+    pub frame_end: u32,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    #[serde(default)]
+    pub camera: Vec<CameraKeyframeDesc>,
+    #[serde(default)]
+    pub lights: Vec<LightTrackDesc>,
+}
+
+/**
+ * Keyframes for the parameters of the light at the given index of the lights list
+ */
+#[derive(Debug, Deserialize)]
+pub struct LightTrackDesc {
+    pub light: usize,
+    pub keys: Vec<LightKeyframeDesc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LightKeyframeDesc {
+    pub time: f32,
+    #[serde(default)]
+    pub emission: Option<Vec3>,
+    #[serde(default)]
+    pub position: Option<Vec3>,
+    #[serde(default)]
+    pub direction: Option<Vec3>,
+}
+
+/**
+ * Camera placement and intrinsics, orientation comes from look_at and up if a target is
+ * given and from rot_axis and rot_angle otherwise. The focal length is given either as a
+ * field of view in degrees or in millimeters together with the sensor size. Depth of field
+ * is enabled by an aperture radius or f-stop, focused at focus_distance or at the surface
+ * seen through the autofocus pixel. The optional sensor is simulated on the final image
+ */
+#[derive(Debug, Deserialize)]
+pub struct CameraDesc {
+    pub position: Vec3,
+    #[serde(default = "default_rot_axis")]
+    pub rot_axis: Vec3,
+    #[serde(default)]
+    pub rot_angle: f32,
+    #[serde(default)]
+    pub look_at: Option<Vec3>,
+    #[serde(default = "default_up")]
+    pub up: Vec3,
+    #[serde(default)]
+    pub fov: Option<f32>,
+    #[serde(default)]
+    pub fov_axis: FovAxis,
+    #[serde(default)]
+    pub focal_length: Option<f32>,
+    #[serde(default = "default_sensor_width")]
+    pub sensor_width: f32,
+    #[serde(default)]
+    pub sensor_height: Option<f32>,
+    #[serde(default)]
+    pub principal_point: Vec2,
+    #[serde(default)]
+    pub projection: Projection,
+    #[serde(default)]
+    pub ortho_width: Option<f32>,
+    #[serde(default)]
+    pub aperture_radius: Option<f32>,
+    #[serde(default)]
+    pub f_stop: Option<f32>,
+    #[serde(default)]
+    pub focus_distance: Option<f32>,
+    #[serde(default)]
+    pub autofocus: Option<[u32; 2]>,
+    #[serde(default)]
+    pub bokeh: BokehDesc,
+    #[serde(default)]
+    pub stereo: Option<Stereo>,
+    #[serde(default)]
+    pub distortion: Distortion,
+    #[serde(default)]
+    pub sensor: Option<Sensor>,
+    #[serde(default)]
+    pub shutter_open: f32,
+    #[serde(default)]
+    pub shutter_close: f32,
+    #[serde(default)]
+    pub motion: Vec<CameraKeyframeDesc>,
+}
+
+/**
+ * Camera pose at a point in time, missing values are taken from the camera itself and
+ * keyframes without an orientation keep aiming at the camera look_at target if any.
+ * Handles offset the bezier control points from the key position
+ */
+#[derive(Debug, Deserialize)]
+pub struct CameraKeyframeDesc {
+    pub time: f32,
+    #[serde(default)]
+    pub position: Option<Vec3>,
+    #[serde(default)]
+    pub look_at: Option<Vec3>,
+    #[serde(default)]
+    pub rotation: Option<RotationDesc>,
+    #[serde(default)]
+    pub handle_in: Vec3,
+    #[serde(default)]
+    pub handle_out: Vec3,
+}
+
+/**
+ * Aperture shape, "circle", {"polygon": {"blades": n, "rotation": deg}} or {"image": path}
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BokehDesc {
+    #[default]
+    Circle,
+    Polygon {
+        blades: u32,
+        #[serde(default)]
+        rotation: f32,
+    },
+    Image(String),
+}
+
+/**
+ * A model entry, either a plain OBJ path or an object with a transform and optional instances
+ */
+#[derive(Debug, Deserialize)]
+#[serde(remote = "Self")]
+pub struct ModelDesc {
+    pub file: String,
+    #[serde(default)]
+    pub position: Vec3,
+    #[serde(default)]
+    pub rotation: Option<RotationDesc>,
+    #[serde(default = "default_scale")]
+    pub scale: Vec3,
+    #[serde(default)]
+    pub instances: Vec<TransformDesc>,
+    #[serde(default)]
+    pub motion: Vec<KeyframeDesc>,
+}
+
+/**
+ * Model transform at a point in time, missing values are taken from the model itself
+ */
+#[derive(Debug, Deserialize)]
+pub struct KeyframeDesc {
+    pub time: f32,
+    #[serde(default)]
+    pub position: Option<Vec3>,
+    #[serde(default)]
+    pub rotation: Option<RotationDesc>,
+    #[serde(default)]
+    pub scale: Option<Vec3>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransformDesc {
+    #[serde(default)]
+    pub position: Vec3,
+    #[serde(default)]
+    pub rotation: Option<RotationDesc>,
+    #[serde(default = "default_scale")]
+    pub scale: Vec3,
+}
+
+/**
+ * Rotation given as axis and angle, XYZ euler angles (both in degrees) or an xyzw quaternion
+ */
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationDesc {
+    AxisAngle { axis: Vec3, angle: f32 },
+    Euler(Vec3),
+    Quaternion(Vec4),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(tag = "type")]
+pub enum LightDesc {
+    AmbientLight {
+        #[serde(default = "default_emission")]
+        emission: Vec3,
+    },
+    DirLight {
+        direction: Vec3,
+        #[serde(default = "default_emission")]
+        emission: Vec3,
+    },
+    PointLight {
+        position: Vec3,
+        #[serde(default = "default_emission")]
+        emission: Vec3,
+        #[serde(default)]
+        c: f32,
+        #[serde(default)]
+        l: f32,
+        #[serde(default = "default_quadratic")]
+        q: f32,
+    },
+}
+
+impl LightDesc {
+    pub fn fields(light_type: &str) -> &'static [&'static str] {
+        match light_type {
+            "AmbientLight" => return &["emission"],
+            "DirLight" => return &["direction", "emission"],
+            "PointLight" => return &["position", "emission", "c", "l", "q"],
+            _ => return &[],
+        }
+    }
+}
+
+impl NodeDesc {
+    pub fn fields(node_type: &str) -> Vec<&'static str> {
+        let bsdf = |surface: Surface| Socket::inputs(surface).iter().map(|(name, _)| *name);
+        match node_type {
+            "image" => return vec!["file", "color_space", "wrap", "uv"],
+            "texture" => return vec!["texture", "uv"],
+            "triplanar" => return vec!["file", "color_space", "wrap", "scale", "sharpness"],
+            "uv_transform" => return vec!["scale", "rotation", "offset", "uv"],
+            "mix" => return vec!["a", "b", "factor"],
+            "multiply" => return vec!["a", "b"],
+            "remap" => return vec!["input", "from", "to"],
+            "clamp" => return vec!["input", "min", "max"],
+            "principled" => return bsdf(Surface::Principled).chain(["ior"]).collect(),
+            "metallic_roughness" => return bsdf(Surface::MetallicRoughness).collect(),
+            "diffuse" => return bsdf(Surface::Diffuse).collect(),
+            _ => return Vec::new(),
+        }
+    }
+
+    fn surface(&self) -> Option<(Surface, &BTreeMap<String, InputDesc>)> {
+        match self {
+            NodeDesc::Principled(inputs) => return Some((Surface::Principled, inputs)),
+            NodeDesc::MetallicRoughness(inputs) => return Some((Surface::MetallicRoughness, inputs)),
+            NodeDesc::Diffuse(inputs) => return Some((Surface::Diffuse, inputs)),
+            _ => return None,
+        }
+    }
+
+    /**
+     * Inputs by name, the known inputs of BSDF nodes
+     */
+    fn inputs(&self) -> Vec<(&str, &InputDesc)> {
+        match self {
+            NodeDesc::Mix { a, b, factor } => return vec![("a", a), ("b", b), ("factor", factor)],
+            NodeDesc::Multiply { a, b } => return vec![("a", a), ("b", b)],
+            NodeDesc::Remap { input, .. } => return vec![("input", input)],
+            NodeDesc::Clamp { input, min, max } => return vec![("input", input), ("min", min), ("max", max)],
+            _ => match self.surface() {
+                Some((surface, inputs)) => return Socket::inputs(surface).iter()
+                    .filter_map(|(name, _)| inputs.get_key_value(*name))
+                    .map(|(name, input)| (name.as_str(), input))
+                    .collect(),
+                None => return Vec::new(),
+            },
+        }
+    }
+
+    fn uv(&self) -> Option<&str> {
+        match self {
+            NodeDesc::Image { uv, .. } | NodeDesc::Texture { uv, .. } | NodeDesc::UvTransform { uv, .. } => return uv.as_deref(),
+            _ => return None,
+        }
+    }
+
+    /**
+     * Names of the nodes this node depends on
+     */
+    fn links(&self) -> Vec<&str> {
+        let inputs = self.inputs();
+        let links = inputs.iter().filter_map(|(_, input)| input.link()).map(|(name, _)| name);
+        return links.chain(self.uv()).collect();
+    }
+}
+
+impl InputDesc {
+    /**
+     * Node name and channel of a link
+     */
+    fn link(&self) -> Option<(&str, Option<usize>)> {
+        let name = match self {
+            InputDesc::Link(name) => name.as_str(),
+            _ => return None,
+        };
+        let channel = |suffix: &str| ["r", "g", "b", "a"].iter().position(|c| *c == suffix);
+        match name.rsplit_once('.') {
+            Some((node, suffix)) if channel(suffix).is_some() => return Some((node, channel(suffix))),
+            _ => return Some((name, None)),
+        }
+    }
+
+    fn constant(&self) -> Option<Vec4> {
+        match self {
+            InputDesc::Scalar(v) => return Some(Vec4::splat(*v)),
+            InputDesc::Color(c) if c.len() == 3 => return Some(Vec4::new(c[0], c[1], c[2], 1.0)),
+            InputDesc::Color(c) if c.len() == 4 => return Some(Vec4::new(c[0], c[1], c[2], c[3])),
+            _ => return None,
+        }
+    }
+
+    fn to_input(&self, index: &HashMap<&str, usize>) -> Input {
+        match self.link() {
+            Some((name, channel)) => return Input::Link { node: index[name], channel },
+            None => return Input::Constant(self.constant().unwrap()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ModelDesc {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ModelDesc, D::Error> {
+        struct ModelVisitor;
+
+        impl<'de> Visitor<'de> for ModelVisitor {
+            type Value = ModelDesc;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                return f.write_str("an OBJ file path or a model object");
+            }
+
+            fn visit_str<E: de::Error>(self, file: &str) -> Result<ModelDesc, E> {
+                return Ok(ModelDesc {
+                    file: file.to_string(),
+                    position: Vec3::ZERO,
+                    rotation: None,
+                    scale: default_scale(),
+                    instances: Vec::new(),
+                    motion: Vec::new(),
+                });
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, map: M) -> Result<ModelDesc, M::Error> {
+                return ModelDesc::deserialize(de::value::MapAccessDeserializer::new(map));
+            }
+        }
+
+        return deserializer.deserialize_any(ModelVisitor);
+    }
+}
+
+impl ModelDesc {
+    /**
+     * Object to world matrices of every instance, instance transforms are relative to the model transform
+     */
+    pub fn transforms(&self) -> Vec<Mat4> {
+        let base = to_mat4(&self.position, &self.rotation, &self.scale);
+        return self.instance_transforms().iter()
+            .map(|i| base * *i)
+            .collect();
+    }
+
+    /**
+     * Instance transforms relative to the model transform, a single identity without instances
+     */
+    pub fn instance_transforms(&self) -> Vec<Mat4> {
+        if self.instances.is_empty() {
+            return vec![Mat4::IDENTITY];
+        }
+
+        return self.instances.iter()
+            .map(|i| to_mat4(&i.position, &i.rotation, &i.scale))
+            .collect();
+    }
+
+    /**
+     * Keyframed model transform replacing the static one, None for models that do not move
+     */
+    pub fn motion(&self) -> Option<AnimatedTransform> {
+        if self.motion.is_empty() {
+            return None;
+        }
+
+        let keys = self.motion.iter()
+            .map(|k| {
+                let ori = k.rotation.or(self.rotation).map_or(Quat::IDENTITY, |r| r.to_quat());
+                let trf = Transform::new(k.position.unwrap_or(self.position), ori, k.scale.unwrap_or(self.scale));
+                Keyframe::new(k.time, trf)
+            })
+            .collect();
+        return Some(AnimatedTransform::new(keys, Interpolation::Linear));
+    }
+}
+
+impl AnimationDesc {
+    /**
+     * Light parameters at the given time, parameters without keys keep their static value
+     */
+    pub fn light_at(&self, i: usize, light: &LightDesc, time: f32) -> LightDesc {
+        let track = |get: fn(&LightKeyframeDesc) -> Option<Vec3>, value: Vec3| {
+            let keys: Vec<Keyframe<Vec3>> = self.lights.iter()
+                .filter(|t| t.light == i)
+                .flat_map(|t| t.keys.iter())
+                .filter_map(|k| get(k).map(|v| Keyframe::new(k.time, v)))
+                .collect();
+            match keys.is_empty() {
+                true => return value,
+                false => return Track::new(keys, self.interpolation).eval(time),
+            }
+        };
+
+        match *light {
+            LightDesc::AmbientLight { emission } => {
+                return LightDesc::AmbientLight {
+                    emission: track(|k| k.emission, emission),
+                };
+            },
+            LightDesc::DirLight { direction, emission } => {
+                return LightDesc::DirLight {
+                    direction: track(|k| k.direction, direction),
+                    emission: track(|k| k.emission, emission),
+                };
+            },
+            LightDesc::PointLight { position, emission, c, l, q } => {
+                return LightDesc::PointLight {
+                    position: track(|k| k.position, position),
+                    emission: track(|k| k.emission, emission),
+                    c,
+                    l,
+                    q,
+                };
+            },
+        }
+    }
+}
+
+impl RotationDesc {
+    pub fn to_quat(&self) -> Quat {
+        match *self {
+            RotationDesc::AxisAngle { axis, angle } => return Quat::from_axis_angle(axis.normalize(), angle.to_radians()),
+            RotationDesc::Euler(e) => {
+                return Quat::from_euler(EulerRot::XYZ, e.x.to_radians(), e.y.to_radians(), e.z.to_radians());
+            },
+            RotationDesc::Quaternion(q) => return Quat::from_vec4(q).normalize(),
+        }
+    }
+
+    fn validate(&self, problems: &mut Vec<String>, path: &str) {
+        match *self {
+            RotationDesc::AxisAngle { axis, .. } if axis.length_squared() == 0.0 => {
+                problems.push(format!("{path}.axis_angle.axis: must not be a zero vector"));
+            },
+            RotationDesc::Quaternion(q) if q.length_squared() == 0.0 => {
+                problems.push(format!("{path}.quaternion: must not be a zero quaternion"));
+            },
+            _ => (),
+        }
+    }
+}
+
+fn to_mat4(position: &Vec3, rotation: &Option<RotationDesc>, scale: &Vec3) -> Mat4 {
+    let ori = rotation.map_or(Quat::IDENTITY, |r| r.to_quat());
+    return Transform::new(*position, ori, *scale).to_mat4();
+}
+
+fn validate_transform(problems: &mut Vec<String>, path: &str, rotation: &Option<RotationDesc>, scale: &Vec3) {
+    if let Some(rotation) = rotation {
+        rotation.validate(problems, &format!("{path}.rotation"));
+    }
+    if scale.x == 0.0 || scale.y == 0.0 || scale.z == 0.0 {
+        problems.push(format!("{path}.scale: components must not be zero"));
+    }
+}
+
+fn default_scale() -> Vec3 {
+    return Vec3::ONE;
+}
+
+impl CameraDesc {
+    pub fn to_camera(&self, viewport_w: f32, viewport_h: f32) -> Camera {
+        let mut camera = match self.look_at {
+            Some(target) => Camera::from_lookat(self.position, target, self.up, viewport_w, viewport_h),
+            None => Camera::from_axis_angle(
+                self.position,
+                self.rot_axis.normalize(),
+                self.rot_angle.to_radians(),
+                viewport_w,
+                viewport_h,
+            ),
+        };
+
+        camera.set_projection(self.projection);
+        if let Some(ortho_width) = self.ortho_width {
+            camera.ortho_width = ortho_width;
+        }
+        if let Some(fov) = self.fov {
+            camera.set_fov(fov.to_radians(), self.fov_axis);
+        }
+        if let Some(focal_length) = self.focal_length {
+            camera.set_focal_length(focal_length, self.sensor_width, self.sensor_height);
+        }
+        camera.set_principal_point_offset(self.principal_point);
+
+        if let Some(radius) = self.aperture_radius {
+            camera.lens_radius = radius;
+        }
+        if let Some(f_stop) = self.f_stop {
+            camera.set_f_stop(f_stop, self.sensor_width);
+        }
+        // focus on the look_at target unless given otherwise, autofocus needs the built scene
+        let target_dist = self.look_at.map(|target| target.distance(self.position));
+        if let Some(dist) = self.focus_distance.or(target_dist) {
+            camera.focus_dist = dist;
+        }
+        camera.aperture = match self.bokeh {
+            BokehDesc::Circle => Aperture::Circle,
+            BokehDesc::Polygon { blades, rotation } => Aperture::Polygon { blades, rotation: rotation.to_radians() },
+            BokehDesc::Image(ref path) => Aperture::Image(ApertureImage::load(path).expect("Failed to load bokeh image")),
+        };
+        camera.stereo = self.stereo;
+        camera.distortion = self.distortion;
+
+        camera.shutter_open = self.shutter_open;
+        camera.shutter_close = self.shutter_close;
+        if !self.motion.is_empty() {
+            let motion = self.motion_track(&self.motion, Interpolation::Linear, camera.trf.ori);
+            camera.trf = motion.eval(self.shutter_open);
+            camera.motion = Some(motion);
+        }
+
+        return camera;
+    }
+
+    /**
+     * Camera to world transform along the given keyframes, ori is used for keys that neither
+     * rotate nor aim the camera
+     */
+    pub fn motion_track(&self, keys: &[CameraKeyframeDesc], interpolation: Interpolation, ori: Quat) -> AnimatedTransform {
+        let keys = keys.iter()
+            .map(|k| {
+                let pos = k.position.unwrap_or(self.position);
+                let ori = match (k.look_at.or(self.look_at), k.rotation) {
+                    (_, Some(rotation)) => rotation.to_quat(),
+                    (Some(target), None) => Transform::from_lookat(pos, target, self.up).ori,
+                    (None, None) => ori,
+                };
+                Keyframe {
+                    time: k.time,
+                    value: Transform::new(pos, ori, Vec3::ONE),
+                    handle_in: Transform::new(pos + k.handle_in, ori, Vec3::ONE),
+                    handle_out: Transform::new(pos + k.handle_out, ori, Vec3::ONE),
+                }
+            })
+            .collect();
+
+        return AnimatedTransform::new(keys, interpolation);
+    }
+
+    fn validate_keys(&self, problems: &mut Vec<String>, path: &str, keys: &[CameraKeyframeDesc]) {
+        for (i, key) in keys.iter().enumerate() {
+            if !key.time.is_finite() {
+                problems.push(format!("{path}[{i}].time: must be finite"));
+            }
+            if key.look_at.is_some() && key.rotation.is_some() {
+                problems.push(format!("{path}[{i}]: look_at and rotation are mutually exclusive"));
+            }
+            if let Some(target) = key.look_at.or(self.look_at) {
+                if target == key.position.unwrap_or(self.position) {
+                    problems.push(format!("{path}[{i}].look_at: must differ from the camera position"));
+                }
+            }
+            if let Some(rotation) = key.rotation {
+                rotation.validate(problems, &format!("{path}[{i}].rotation"));
+            }
+        }
+    }
+
+    fn validate(&self, problems: &mut Vec<String>) {
+        if !self.position.is_finite() {
+            problems.push("camera.position: must be finite".to_string());
+        }
+
+        match self.look_at {
+            Some(target) => {
+                let forward = target - self.position;
+                if forward.length_squared() == 0.0 {
+                    problems.push("camera.look_at: must differ from camera.position".to_string());
+                } else if self.up.cross(forward).length_squared() == 0.0 {
+                    problems.push("camera.up: must not be parallel to the viewing direction".to_string());
+                }
+            },
+            None => {
+                if self.rot_axis.length_squared() == 0.0 {
+                    problems.push("camera.rot_axis: must not be a zero vector".to_string());
+                }
+            },
+        }
+
+        if let Some(fov) = self.fov {
+            let max_fov = match self.projection {
+                Projection::FisheyeEquidistant | Projection::FisheyeEquisolid => 360.0,
+                _ => 180.0,
+            };
+            if fov <= 0.0 || fov >= max_fov {
+                problems.push(format!("camera.fov: must be between 0 and {max_fov} degrees for a {} projection", self.projection.name()));
+            }
+            if self.focal_length.is_some() {
+                problems.push("camera: fov and focal_length are mutually exclusive".to_string());
+            }
+        }
+        if self.focal_length.is_some_and(|f| f <= 0.0) {
+            problems.push("camera.focal_length: must be positive".to_string());
+        }
+        if self.sensor_width <= 0.0 || self.sensor_height.is_some_and(|h| h <= 0.0) {
+            problems.push("camera: sensor_width and sensor_height must be positive".to_string());
+        }
+
+        if self.ortho_width.is_some_and(|w| w <= 0.0) {
+            problems.push("camera.ortho_width: must be positive".to_string());
+        }
+        if self.ortho_width.is_some() && self.projection != Projection::Orthographic {
+            problems.push("camera.ortho_width: only applies to the orthographic projection".to_string());
+        }
+
+        if self.aperture_radius.is_some() && self.f_stop.is_some() {
+            problems.push("camera: aperture_radius and f_stop are mutually exclusive".to_string());
+        }
+        if self.aperture_radius.is_some_and(|r| r < 0.0) {
+            problems.push("camera.aperture_radius: must not be negative".to_string());
+        }
+        if self.f_stop.is_some_and(|n| n <= 0.0) {
+            problems.push("camera.f_stop: must be positive".to_string());
+        }
+        if self.focus_distance.is_some() && self.autofocus.is_some() {
+            problems.push("camera: focus_distance and autofocus are mutually exclusive".to_string());
+        }
+        if self.focus_distance.is_some_and(|d| d <= 0.0) {
+            problems.push("camera.focus_distance: must be positive".to_string());
+        }
+        if let Some(stereo) = self.stereo {
+            if stereo.ipd <= 0.0 {
+                problems.push("camera.stereo.ipd: must be positive".to_string());
+            }
+            if let Convergence::OffAxis(dist) | Convergence::ToeIn(dist) = stereo.convergence {
+                if dist <= 0.0 {
+                    problems.push("camera.stereo.convergence: distance must be positive".to_string());
+                }
+            }
+        }
+
+        if !self.shutter_open.is_finite() || !self.shutter_close.is_finite() || self.shutter_close < self.shutter_open {
+            problems.push("camera: shutter_close must not be before shutter_open".to_string());
+        }
+        self.validate_keys(problems, "camera.motion", &self.motion);
+
+        if self.distortion != Distortion::None && self.projection != Projection::Perspective {
+            problems.push("camera.distortion: only applies to the perspective projection".to_string());
+        }
+        if let Some(sensor) = self.sensor {
+            if sensor.full_well <= 0.0 {
+                problems.push("camera.sensor.full_well: must be positive".to_string());
+            }
+            if sensor.read_noise < 0.0 {
+                problems.push("camera.sensor.read_noise: must not be negative".to_string());
+            }
+            if !(0.0..=1.0).contains(&sensor.vignetting) {
+                problems.push("camera.sensor.vignetting: must be between 0 and 1".to_string());
+            }
+        }
+
+        match self.bokeh {
+            BokehDesc::Circle => {},
+            BokehDesc::Polygon { blades, .. } => {
+                if blades < 3 {
+                    problems.push("camera.bokeh.polygon.blades: must be at least 3".to_string());
+                }
+            },
+            BokehDesc::Image(ref path) => {
+                if !Path::new(path).is_file() {
+                    problems.push(format!("camera.bokeh.image: file \"{path}\" does not exist"));
+                }
+            },
+        }
+    }
+}
+
+fn default_rot_axis() -> Vec3 {
+    return Vec3::Y;
+}
+
+fn default_up() -> Vec3 {
+    return Vec3::Y;
+}
+
+fn default_sensor_width() -> f32 {
+    // full frame 35mm sensor
+    return 36.0;
+}
+
+fn default_frame_start() -> u32 {
+    return 1;
+}
+
+fn default_emission() -> Vec3 {
+    return Vec3::ONE;
+}
+
+fn default_quadratic() -> f32 {
+    return 1.0;
+}
+
+fn default_wrap() -> String {
+    return "repeat".to_string();
+}
+
+fn default_triplanar_scale() -> f32 {
+    return 1.0;
+}
+
+fn default_sharpness() -> f32 {
+    return 4.0;
+}
+
+fn default_uv_scale() -> Vec2 {
+    return Vec2::ONE;
+}
+
+fn default_range() -> Vec2 {
+    return Vec2::new(0.0, 1.0);
+}
+
+fn default_clamp_min() -> InputDesc {
+    return InputDesc::Scalar(0.0);
+}
+
+fn default_clamp_max() -> InputDesc {
+    return InputDesc::Scalar(1.0);
+}
+
+impl MaterialDesc {
+    /**
+     * Switches the material to the principled BSDF with the given parameters or to the node
+     * graph, texture names must be present in textures
+     */
+    pub fn apply(&self, mat: &mut Material, textures: &BTreeMap<String, ProceduralTexture>, cache: &mut TextureCache) {
+        if let Some(ref output) = self.output {
+            mat.graph = Some(self.graph(mat, output, textures, cache));
+            return;
+        }
+
+        mat.surface = Surface::Principled;
+        if let Some(base_color) = self.base_color {
+            mat.diffuse = base_color;
+            mat.diffuse_texture.texture = Texture::None;
+        }
+        if self.metallic.is_some() {
+            mat.metallic_texture.texture = Texture::None;
+        }
+        if self.roughness.is_some() {
+            mat.roughness_texture.texture = Texture::None;
+        }
+        if let Some(emission) = self.emission {
+            mat.emission = emission;
+        }
+
+        let maps = [
+            (&self.base_color_texture, &mut mat.diffuse_texture),
+            (&self.alpha_texture, &mut mat.alpha_texture),
+            (&self.metallic_texture, &mut mat.metallic_texture),
+            (&self.roughness_texture, &mut mat.roughness_texture),
+            (&self.emission_texture, &mut mat.emission_texture),
+            (&self.bump_texture, &mut mat.normal_texture),
+        ];
+        for (name, map) in maps {
+            if let Some(name) = name {
+                *map = TextureMap {
+                    texture: Texture::Procedural(textures[name].clone()),
+                    options: TextureOptions::default(),
+                };
+            }
+        }
+        // emission textures are scaled by the emission, as with map_Ke a black one counts as white
+        if self.emission_texture.is_some() && mat.emission == Vec3::ZERO {
+            mat.emission = Vec3::ONE;
+        }
+        if let Some(bump_multiplier) = self.bump_multiplier {
+            mat.normal_texture.options.bump_multiplier = bump_multiplier;
+        }
+
+        let params = [
+            (self.metallic, &mut mat.metallic),
+            (self.roughness, &mut mat.roughness),
+            (self.subsurface, &mut mat.subsurface),
+            (self.specular_tint, &mut mat.specular_tint),
+            (self.anisotropic, &mut mat.anisotropic),
+            (self.anisotropic_rotation, &mut mat.anisotropic_rotation),
+            (self.sheen, &mut mat.sheen),
+            (self.sheen_tint, &mut mat.sheen_tint),
+            (self.clearcoat, &mut mat.clearcoat),
+            (self.clearcoat_roughness, &mut mat.clearcoat_roughness),
+            (self.transmission, &mut mat.transmission),
+            (self.ior, &mut mat.ior),
+        ];
+        for (value, param) in params {
+            if let Some(value) = value {
+                *param = value;
+            }
+        }
+        // materials without an index of refraction above one would not reflect at all
+        if self.ior.is_none() && mat.ior <= 1.0 {
+            mat.ior = PRINCIPLED_IOR;
+        }
+    }
+
+    /**
+     * Replaces the material by the graph of the nodes the output depends on, ordered after the
+     * nodes they link to. Parameters not connected on the BSDF node take its defaults and the
+     * texture maps of the material are removed
+     */
+    fn graph(&self, mat: &mut Material, output: &str, textures: &BTreeMap<String, ProceduralTexture>, cache: &mut TextureCache) -> ShaderGraph {
+        let mut order: Vec<&str> = Vec::new();
+        self.visit(output, &mut order);
+        order.pop();
+
+        let data_sockets = data_sockets(&self.nodes, output);
+        let decoded_as = |name: &str, color_space: Option<ColorSpace>| match data_sockets.contains_key(name) {
+            true => color_space.unwrap_or(ColorSpace::Linear),
+            false => color_space.unwrap_or(ColorSpace::Srgb),
+        };
+        let mut index: HashMap<&str, usize> = HashMap::new();
+        let mut uvs: HashMap<&str, Affine2> = HashMap::new();
+        let mut nodes: Vec<Node> = Vec::new();
+        for name in order {
+            let uv = |uv: &Option<String>| uv.as_deref().map_or(Affine2::IDENTITY, |uv| uvs[uv]);
+            let node = match self.nodes[name] {
+                NodeDesc::Image { ref file, color_space, ref wrap, uv: ref node_uv } => Node::Image {
+                    texture: cache.get(Path::new(file), Channels::Rgba, decoded_as(name, color_space)),
+                    wrap: WrapMode::from_name(wrap).unwrap(),
+                    uv: uv(node_uv),
+                },
+                NodeDesc::Texture { ref texture, uv: ref node_uv } => Node::Procedural {
+                    texture: textures[texture].clone(),
+                    uv: uv(node_uv),
+                },
+                NodeDesc::Triplanar { ref file, color_space, ref wrap, scale, sharpness } => Node::Triplanar {
+                    texture: cache.get(Path::new(file), Channels::Rgba, decoded_as(name, color_space)),
+                    wrap: WrapMode::from_name(wrap).unwrap(),
+                    scale,
+                    sharpness,
+                },
+                NodeDesc::UvTransform { scale, rotation, offset, uv: ref node_uv } => {
+                    let transform = Affine2::from_scale_angle_translation(scale, rotation.to_radians(), offset) * uv(node_uv);
+                    uvs.insert(name, transform);
+                    Node::UvTransform { uv: transform }
+                },
+                NodeDesc::Mix { ref a, ref b, ref factor } => Node::Mix {
+                    a: a.to_input(&index),
+                    b: b.to_input(&index),
+                    factor: factor.to_input(&index),
+                },
+                NodeDesc::Multiply { ref a, ref b } => Node::Multiply {
+                    a: a.to_input(&index),
+                    b: b.to_input(&index),
+                },
+                NodeDesc::Remap { ref input, from, to } => Node::Remap { input: input.to_input(&index), from, to },
+                NodeDesc::Clamp { ref input, ref min, ref max } => Node::Clamp {
+                    input: input.to_input(&index),
+                    min: min.to_input(&index),
+                    max: max.to_input(&index),
+                },
+                NodeDesc::Principled(_) | NodeDesc::MetallicRoughness(_) | NodeDesc::Diffuse(_) => unreachable!("BSDF nodes are only outputs"),
+            };
+            index.insert(name, nodes.len());
+            nodes.push(node);
+        }
+
+        let (surface, inputs) = self.nodes[output].surface().unwrap();
+        mat.surface = surface;
+        mat.diffuse = Vec3::splat(0.8);
+        mat.specular = Vec3::ZERO;
+        mat.shininess = 32.0;
+        mat.emission = Vec3::ZERO;
+        mat.ior = inputs.get("ior").and_then(InputDesc::constant).map_or(PRINCIPLED_IOR, |ior| ior.x);
+        mat.dissolve = 1.0;
+        mat.transmission_filter = Vec3::ONE;
+        mat.roughness = 0.5;
+        mat.metallic = 0.0;
+        mat.subsurface = 0.0;
+        mat.specular_tint = 0.0;
+        mat.anisotropic = 0.0;
+        mat.anisotropic_rotation = 0.0;
+        mat.sheen = 0.0;
+        mat.sheen_tint = 0.5;
+        mat.clearcoat = 0.0;
+        mat.clearcoat_roughness = 0.03;
+        mat.transmission = 0.0;
+        let maps = [
+            &mut mat.diffuse_texture, &mut mat.alpha_texture, &mut mat.roughness_texture, &mut mat.metallic_texture,
+            &mut mat.normal_texture, &mut mat.specular_texture, &mut mat.shininess_texture, &mut mat.emission_texture,
+            &mut mat.ambient_texture,
+        ];
+        for map in maps {
+            *map = TextureMap {
+                texture: Texture::None,
+                options: TextureOptions::default(),
+            };
+        }
+
+        let mut graph = ShaderGraph::new(nodes);
+        for (name, socket) in Socket::inputs(surface) {
+            if let Some(input) = inputs.get(*name) {
+                graph.connect(*socket, input.to_input(&index));
+            }
+        }
+        return graph;
+    }
+
+    /**
+     * Appends a node to the order after the nodes it links to
+     */
+    fn visit<'a>(&'a self, name: &'a str, order: &mut Vec<&'a str>) {
+        if order.contains(&name) {
+            return;
+        }
+        for link in self.nodes[name].links() {
+            self.visit(link, order);
+        }
+        order.push(name);
+    }
+
+    fn validate(&self, problems: &mut Vec<String>, path: &str, textures: &BTreeMap<String, ProceduralTexture>) {
+        if self.base_color.is_some_and(|c| c.min_element() < 0.0 || c.max_element() > 1.0) {
+            problems.push(format!("{path}.base_color: components must be within [0, 1]"));
+        }
+        if self.emission.is_some_and(|e| e.min_element() < 0.0) {
+            problems.push(format!("{path}.emission: must not be negative"));
+        }
+
+        let params = [
+            ("metallic", self.metallic),
+            ("roughness", self.roughness),
+            ("subsurface", self.subsurface),
+            ("specular_tint", self.specular_tint),
+            ("anisotropic", self.anisotropic),
+            ("sheen", self.sheen),
+            ("sheen_tint", self.sheen_tint),
+            ("clearcoat", self.clearcoat),
+            ("clearcoat_roughness", self.clearcoat_roughness),
+            ("transmission", self.transmission),
+        ];
+        for (name, value) in params {
+            if value.is_some_and(|v| !(0.0..=1.0).contains(&v)) {
+                problems.push(format!("{path}.{name}: must be within [0, 1]"));
+            }
+        }
+        if self.anisotropic_rotation.is_some_and(|r| !r.is_finite()) {
+            problems.push(format!("{path}.anisotropic_rotation: must be finite"));
+        }
+        if self.ior.is_some_and(|ior| ior < 1.0) {
+            problems.push(format!("{path}.ior: must be at least 1"));
+        }
+
+        let maps = [
+            ("base_color_texture", &self.base_color_texture),
+            ("alpha_texture", &self.alpha_texture),
+            ("metallic_texture", &self.metallic_texture),
+            ("roughness_texture", &self.roughness_texture),
+            ("emission_texture", &self.emission_texture),
+            ("bump_texture", &self.bump_texture),
+        ];
+        for (name, texture) in maps {
+            if let Some(texture) = texture {
+                if !textures.contains_key(texture) {
+                    problems.push(format!("{path}.{name}: no texture named \"{texture}\" in textures"));
+                }
+            }
+        }
+        if self.bump_multiplier.is_some_and(|m| !m.is_finite()) {
+            problems.push(format!("{path}.bump_multiplier: must be finite"));
+        }
+
+        self.validate_graph(problems, path, textures);
+    }
+
+    fn validate_graph(&self, problems: &mut Vec<String>, path: &str, textures: &BTreeMap<String, ProceduralTexture>) {
+        match self.output {
+            Some(ref output) => {
+                match self.nodes.get(output) {
+                    None => problems.push(format!("{path}.output: no node named \"{output}\" in nodes")),
+                    Some(node) if node.surface().is_none() => problems.push(format!("{path}.output: node \"{output}\" is not a BSDF node")),
+                    Some(_) => {},
+                }
+
+                let scalars = [
+                    self.metallic, self.roughness, self.subsurface, self.specular_tint, self.anisotropic, self.anisotropic_rotation,
+                    self.sheen, self.sheen_tint, self.clearcoat, self.clearcoat_roughness, self.transmission, self.ior, self.bump_multiplier,
+                ];
+                let maps = [
+                    &self.base_color_texture, &self.alpha_texture, &self.metallic_texture, &self.roughness_texture,
+                    &self.emission_texture, &self.bump_texture,
+                ];
+                if self.base_color.is_some() || self.emission.is_some() || scalars.iter().any(Option::is_some) || maps.iter().any(|m| m.is_some()) {
+                    problems.push(format!("{path}: parameters and textures of a node graph are given on its output node"));
+                }
+            },
+            None if !self.nodes.is_empty() => problems.push(format!("{path}.output: a node graph needs an output node")),
+            None => {},
+        }
+
+        // images decoded as sRGB would distort roughness, heights or normals
+        if let Some(output) = self.output.as_deref().filter(|output| self.nodes.contains_key(*output)) {
+            for (name, socket) in data_sockets(&self.nodes, output) {
+                if let Some(NodeDesc::Image { color_space: Some(ColorSpace::Srgb), .. } | NodeDesc::Triplanar { color_space: Some(ColorSpace::Srgb), .. }) = self.nodes.get(name) {
+                    problems.push(format!("{path}.nodes.{name}.color_space: sRGB image feeds the {socket} input, which is not a color"));
+                }
+            }
+        }
+
+        for (name, node) in &self.nodes {
+            let path = format!("{path}.nodes.{name}");
+            for (input_name, input) in node.inputs() {
+                match (input, input.link()) {
+                    (_, Some((link, _))) => match self.nodes.get(link) {
+                        None => problems.push(format!("{path}.{input_name}: no node named \"{link}\" in nodes")),
+                        Some(linked) if linked.surface().is_some() => problems.push(format!("{path}.{input_name}: BSDF node \"{link}\" can only be the output")),
+                        Some(_) => {},
+                    },
+                    (InputDesc::Color(c), None) if c.len() != 3 && c.len() != 4 => {
+                        problems.push(format!("{path}.{input_name}: colors must have 3 or 4 components"));
+                    },
+                    _ => {},
+                }
+                if input.constant().is_some_and(|c| !c.is_finite()) {
+                    problems.push(format!("{path}.{input_name}: must be finite"));
+                }
+            }
+            if let Some(uv) = node.uv() {
+                if !matches!(self.nodes.get(uv), Some(NodeDesc::UvTransform { .. })) {
+                    problems.push(format!("{path}.uv: no uv_transform node named \"{uv}\" in nodes"));
+                }
+            }
+            if in_cycle(&self.nodes, name) {
+                problems.push(format!("{path}: node depends on itself"));
+            }
+
+            match node {
+                NodeDesc::Image { file, wrap, .. } => validate_image(problems, &path, file, wrap),
+                NodeDesc::Texture { texture, .. } => {
+                    if !textures.contains_key(texture) {
+                        problems.push(format!("{path}.texture: no texture named \"{texture}\" in textures"));
+                    }
+                },
+                NodeDesc::Triplanar { file, wrap, scale, sharpness, .. } => {
+                    validate_image(problems, &path, file, wrap);
+                    if *scale == 0.0 || !scale.is_finite() {
+                        problems.push(format!("{path}.scale: must be finite and non-zero"));
+                    }
+                    if *sharpness <= 0.0 || !sharpness.is_finite() {
+                        problems.push(format!("{path}.sharpness: must be finite and positive"));
+                    }
+                },
+                NodeDesc::UvTransform { scale, rotation, offset, .. } => {
+                    if scale.cmpeq(Vec2::ZERO).any() || !scale.is_finite() {
+                        problems.push(format!("{path}.scale: components must be finite and non-zero"));
+                    }
+                    if !rotation.is_finite() {
+                        problems.push(format!("{path}.rotation: must be finite"));
+                    }
+                    if !offset.is_finite() {
+                        problems.push(format!("{path}.offset: must be finite"));
+                    }
+                },
+                NodeDesc::Remap { from, to, .. } => {
+                    if from.x == from.y || !from.is_finite() {
+                        problems.push(format!("{path}.from: must be a finite range of non-zero width"));
+                    }
+                    if !to.is_finite() {
+                        problems.push(format!("{path}.to: must be finite"));
+                    }
+                },
+                NodeDesc::Principled(inputs) | NodeDesc::MetallicRoughness(inputs) | NodeDesc::Diffuse(inputs) => {
+                    if matches!(node, NodeDesc::Principled(_)) && !matches!(inputs.get("ior"), None | Some(InputDesc::Scalar(1.0..))) {
+                        problems.push(format!("{path}.ior: must be a number of at least 1"));
+                    }
+                    if inputs.contains_key("bump") && inputs.contains_key("normal") {
+                        problems.push(format!("{path}: bump and normal are mutually exclusive"));
+                    }
+                },
+                NodeDesc::Mix { .. } | NodeDesc::Multiply { .. } | NodeDesc::Clamp { .. } => {},
+            }
+        }
+    }
+}
+
+fn validate_image(problems: &mut Vec<String>, path: &str, file: &str, wrap: &str) {
+    if !Path::new(file).is_file() {
+        problems.push(format!("{path}.file: file \"{file}\" does not exist"));
+    }
+    if WrapMode::from_name(wrap).is_none() {
+        problems.push(format!("{path}.wrap: unknown wrap mode \"{wrap}\", expected \"repeat\", \"clamp\", \"mirror\" or \"border\""));
+    }
+}
+
+/**
+ * Nodes of a graph feeding inputs of its output node other than colors, directly or through
+ * other nodes, with the first such input they reach
+ */
+fn data_sockets<'a>(nodes: &'a BTreeMap<String, NodeDesc>, output: &str) -> HashMap<&'a str, &'a str> {
+    let mut reached: HashMap<&str, &str> = HashMap::new();
+    for (socket, input) in nodes[output].inputs() {
+        if matches!(socket, "base_color" | "color" | "specular" | "emission") {
+            continue;
+        }
+        let mut stack: Vec<&str> = input.link().into_iter().map(|(link, _)| link).collect();
+        while let Some(link) = stack.pop() {
+            if let Some((name, node)) = nodes.get_key_value(link) {
+                if !reached.contains_key(name.as_str()) {
+                    reached.insert(name, socket);
+                    stack.extend(node.links());
+                }
+            }
+        }
+    }
+    return reached;
+}
+
+/**
+ * Whether a node of a graph links to itself through other nodes
+ */
+fn in_cycle(nodes: &BTreeMap<String, NodeDesc>, name: &str) -> bool {
+    let mut stack: Vec<&str> = nodes[name].links();
+    let mut seen: HashSet<&str> = HashSet::new();
+    while let Some(link) = stack.pop() {
+        if link == name {
+            return true;
+        }
+        if seen.insert(link) {
+            if let Some(node) = nodes.get(link) {
+                stack.extend(node.links());
+            }
+        }
+    }
+    return false;
+}
+
+fn validate_texture(problems: &mut Vec<String>, path: &str, texture: &ProceduralTexture) {
+    if texture.scale.cmpeq(Vec3::ZERO).any() || !texture.scale.is_finite() {
+        problems.push(format!("{path}.scale: components must be finite and non-zero"));
+    }
+    if !texture.offset.is_finite() {
+        problems.push(format!("{path}.offset: must be finite"));
+    }
+    if texture.colors.is_empty() {
+        problems.push(format!("{path}.colors: must contain at least one color"));
+    }
+    if texture.colors.iter().any(|c| c.min_element() < 0.0) {
+        problems.push(format!("{path}.colors: components must not be negative"));
+    }
+    let fractal = matches!(texture.pattern, Pattern::Fbm | Pattern::Turbulence | Pattern::Marble | Pattern::Wood);
+    if fractal && texture.octaves == 0 {
+        problems.push(format!("{path}.octaves: must be at least 1"));
+    }
+    if !(0.0..=1.0).contains(&texture.roughness) {
+        problems.push(format!("{path}.roughness: must be within [0, 1]"));
+    }
+    if !texture.variation.is_finite() {
+        problems.push(format!("{path}.variation: must be finite"));
+    }
+}
+
+impl SceneDesc {
+    /**
+     * Loads and validates a scene file, returning every problem found. Unknown fields
+     * are reported as warnings, or as errors when strict is set
+     */
+    pub fn from_file(path: &str, strict: bool) -> Result<SceneDesc, Vec<String>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| vec![format!("{path}: failed to open scene file: {e}")])?;
+
+        // deserialize while tracking the JSON path of errors and any ignored fields
+        let mut unknown_fields: Vec<String> = Vec::new();
+        let mut json = serde_json::Deserializer::from_str(&text);
+        // optional values show up as "?" segments in the path
+        let mut track_unknown = |p: serde_ignored::Path| unknown_fields.push(p.to_string().replace(".?", ""));
+        let json = serde_ignored::Deserializer::new(&mut json, &mut track_unknown);
+        let desc: SceneDesc = serde_path_to_error::deserialize(json)
+            .map_err(|e| vec![format!("{path}: {}: {}", e.path(), e.inner())])?;
+
+        // internally tagged lights are buffered by serde, check their fields by hand
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        if let Some(lights) = value.get("lights").and_then(|v| v.as_array()) {
+            for (i, light) in lights.iter().enumerate() {
+                let known = LightDesc::fields(light.get("type").and_then(|t| t.as_str()).unwrap_or(""));
+                for key in light.as_object().unwrap().keys() {
+                    if key != "type" && !known.contains(&key.as_str()) {
+                        unknown_fields.push(format!("lights[{i}].{key}"));
+                    }
+                }
+            }
+        }
+
+        // nodes of material graphs are internally tagged as well
+        if let Some(materials) = value.get("materials").and_then(|v| v.as_object()) {
+            for (name, material) in materials {
+                let nodes = match material.get("nodes").and_then(|v| v.as_object()) {
+                    Some(nodes) => nodes,
+                    None => continue,
+                };
+                for (node_name, node) in nodes {
+                    let known = NodeDesc::fields(node.get("type").and_then(|t| t.as_str()).unwrap_or(""));
+                    for key in node.as_object().unwrap().keys() {
+                        if key != "type" && !known.contains(&key.as_str()) {
+                            unknown_fields.push(format!("materials.{name}.nodes.{node_name}.{key}"));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut problems: Vec<String> = Vec::new();
+        for field in unknown_fields {
+            match strict {
+                true => problems.push(format!("{path}: {field}: unknown field")),
+                false => println!("warning: {path}: {field}: unknown field is ignored"),
+            }
+        }
+        problems.extend(desc.validate().into_iter().map(|p| format!("{path}: {p}")));
+
+        match problems.is_empty() {
+            true => return Ok(desc),
+            false => return Err(problems),
+        }
+    }
+
+    /**
+     * Checks the values of an already parsed scene, problems are prefixed by their JSON path
+     */
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+
+        if let Some(ref name) = self.renderer {
+            if Renderer::from_name(name).is_none() {
+                problems.push(format!("renderer: unknown renderer \"{name}\", expected \"raytracer\" or \"pathtracer\""));
+            }
+        }
+
+        self.camera.validate(&mut problems);
+
+        if let Some(ref animation) = self.animation {
+            if animation.frame_end < animation.frame_start {
+                problems.push("animation.frame_end: must not be before frame_start".to_string());
+            }
+            if !animation.camera.is_empty() && !self.camera.motion.is_empty() {
+                problems.push("animation.camera: camera.motion and animation.camera are mutually exclusive".to_string());
+            }
+            self.camera.validate_keys(&mut problems, "animation.camera", &animation.camera);
+            for (i, track) in animation.lights.iter().enumerate() {
+                match self.lights.get(track.light) {
+                    None => problems.push(format!("animation.lights[{i}].light: no light with index {}", track.light)),
+                    Some(light) => {
+                        for (j, key) in track.keys.iter().enumerate() {
+                            let path = format!("animation.lights[{i}].keys[{j}]");
+                            if !key.time.is_finite() {
+                                problems.push(format!("{path}.time: must be finite"));
+                            }
+                            if key.emission.is_some_and(|e| e.min_element() < 0.0) {
+                                problems.push(format!("{path}.emission: must not be negative"));
+                            }
+                            if key.position.is_some() && !matches!(light, LightDesc::PointLight { .. }) {
+                                problems.push(format!("{path}.position: only applies to point lights"));
+                            }
+                            if key.direction.is_some() && !matches!(light, LightDesc::DirLight { .. }) {
+                                problems.push(format!("{path}.direction: only applies to directional lights"));
+                            }
+                            if key.direction.is_some_and(|d| d.length_squared() == 0.0) {
+                                problems.push(format!("{path}.direction: must not be a zero vector"));
+                            }
+                        }
+                    },
+                }
+            }
+        }
+
+        if self.models.is_empty() {
+            problems.push("models: scene does not contain any models".to_string());
+        }
+        for (i, model) in self.models.iter().enumerate() {
+            if !Path::new(&model.file).is_file() {
+                problems.push(format!("models[{i}].file: file \"{}\" does not exist", model.file));
+            }
+            validate_transform(&mut problems, &format!("models[{i}]"), &model.rotation, &model.scale);
+            for (j, instance) in model.instances.iter().enumerate() {
+                validate_transform(&mut problems, &format!("models[{i}].instances[{j}]"), &instance.rotation, &instance.scale);
+            }
+            for (j, key) in model.motion.iter().enumerate() {
+                if !key.time.is_finite() {
+                    problems.push(format!("models[{i}].motion[{j}].time: must be finite"));
+                }
+                validate_transform(&mut problems, &format!("models[{i}].motion[{j}]"), &key.rotation, &key.scale.unwrap_or(Vec3::ONE));
+            }
+        }
+
+        for (name, texture) in &self.textures {
+            validate_texture(&mut problems, &format!("textures.{name}"), texture);
+        }
+        for (name, material) in &self.materials {
+            material.validate(&mut problems, &format!("materials.{name}"), &self.textures);
+        }
+
+        for (i, light) in self.lights.iter().enumerate() {
+            match light {
+                LightDesc::AmbientLight { emission } => {
+                    check_emission(&mut problems, i, emission);
+                },
+                LightDesc::DirLight { direction, emission } => {
+                    check_emission(&mut problems, i, emission);
+                    if direction.length_squared() == 0.0 {
+                        problems.push(format!("lights[{i}].direction: must not be a zero vector"));
+                    }
+                },
+                LightDesc::PointLight { emission, c, l, q, .. } => {
+                    check_emission(&mut problems, i, emission);
+                    if *c < 0.0 || *l < 0.0 || *q < 0.0 {
+                        problems.push(format!("lights[{i}]: attenuation terms c, l and q must not be negative"));
+                    }
+                    if *c + *l + *q == 0.0 {
+                        problems.push(format!("lights[{i}]: at least one of the attenuation terms c, l and q must be positive"));
+                    }
+                },
+            }
+        }
+
+        return problems;
+    }
+}
+
+fn check_emission(problems: &mut Vec<String>, i: usize, emission: &Vec3) {
+    if emission.min_element() < 0.0 {
+        problems.push(format!("lights[{i}].emission: must not be negative"));
+    }
+}
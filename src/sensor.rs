@@ -0,0 +1,119 @@
+use image::Rgb32FImage;
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+use serde::Deserialize;
+
+use crate::camera::Camera;
+
+/**
+ * Color filter array layout, named by the colors of the top left 2x2 block in reading order
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BayerPattern {
+    Rggb,
+    Bggr,
+    Grbg,
+    Gbrg,
+}
+
+impl BayerPattern {
+    /**
+     * Index of the color channel recorded by the pixel
+     */
+    pub fn channel(&self, x: u32, y: u32) -> usize {
+        let cfa = match self {
+            BayerPattern::Rggb => [0, 1, 1, 2],
+            BayerPattern::Bggr => [2, 1, 1, 0],
+            BayerPattern::Grbg => [1, 0, 2, 1],
+            BayerPattern::Gbrg => [1, 2, 0, 1],
+        };
+        return cfa[((y % 2) * 2 + (x % 2)) as usize];
+    }
+}
+
+/**
+ * Simple image sensor model applied to the linear film. A radiance of 1.0 fills the full well
+ * capacity, photon shot noise and gaussian read noise are simulated in electrons before the
+ * pixel saturates. Vignetting scales the natural cos^4 falloff of the lens, and a bayer
+ * pattern turns the result into a single channel raw mosaic written to all three channels
+ * Reference: https://www.pbr-book.org/4ed/Cameras_and_Film/Film_and_Imaging#TheCameraMeasurementEquation
+ */
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Sensor {
+    #[serde(default = "default_full_well")]
+    pub full_well: f32,
+    #[serde(default = "default_true")]
+    pub shot_noise: bool,
+    #[serde(default)]
+    pub read_noise: f32,
+    #[serde(default)]
+    pub vignetting: f32,
+    #[serde(default)]
+    pub bayer: Option<BayerPattern>,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+fn default_full_well() -> f32 {
+    return 10000.0;
+}
+
+fn default_true() -> bool {
+    return true;
+}
+
+impl Sensor {
+    pub fn apply(&self, img: &mut Rgb32FImage, camera: &Camera) {
+        let mut rng = SmallRng::seed_from_u64(self.seed);
+
+        for (x, y, pix) in img.enumerate_pixels_mut() {
+            let cos_theta = camera.cos_theta(x as f32 + 0.5, y as f32 + 0.5).unwrap_or(1.0);
+            let falloff = 1.0 - self.vignetting * (1.0 - cos_theta.powi(4));
+
+            let mut electrons = [0.0_f32; 3];
+            for (c, v) in electrons.iter_mut().enumerate() {
+                let mut e = (pix.0[c] * falloff).max(0.0) * self.full_well;
+                if self.shot_noise {
+                    e = sample_poisson(&mut rng, e);
+                }
+                e += self.read_noise * sample_gaussian(&mut rng);
+                *v = e.clamp(0.0, self.full_well) / self.full_well;
+            }
+
+            *pix = match self.bayer {
+                Some(pattern) => image::Rgb([electrons[pattern.channel(x, y)]; 3]),
+                None => image::Rgb(electrons),
+            };
+        }
+    }
+}
+
+/**
+ * Standard normal sample by the Box-Muller transform
+ */
+fn sample_gaussian<R: Rng>(rng: &mut R) -> f32 {
+    let u1: f32 = 1.0 - rng.gen::<f32>();
+    let u2: f32 = rng.gen();
+    return (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos();
+}
+
+/**
+ * Poisson sample by multiplying uniforms for small means, large means use the normal approximation
+ * Reference: https://en.wikipedia.org/wiki/Poisson_distribution#Random_variate_generation
+ */
+fn sample_poisson<R: Rng>(rng: &mut R, mean: f32) -> f32 {
+    if mean > 30.0 {
+        return (mean + mean.sqrt() * sample_gaussian(rng)).max(0.0).round();
+    }
+
+    let limit = (-mean).exp();
+    let mut k = 0.0;
+    let mut p: f32 = rng.gen();
+    while p > limit {
+        k += 1.0;
+        p *= rng.gen::<f32>();
+    }
+
+    return k;
+}
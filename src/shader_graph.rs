@@ -0,0 +1,405 @@
+use std::cell::RefCell;
+
+use glam::{Affine2, Vec2, Vec3, Vec4};
+
+use crate::material::Surface;
+use crate::procedural::ProceduralTexture;
+use crate::texture::{Footprint, WrapMode};
+use crate::texture_cache::CachedTexture;
+
+// smallest weight of a triplanar projection that is still looked up
+const MIN_TRIPLANAR_WEIGHT: f32 = 1e-3;
+
+/**
+ * Inputs of the BSDF node of a shader graph, connected ones take the place of the material
+ * parameters and texture maps. Scalar parameters are read from the first channel. Alpha cuts
+ * the surface away below one half, bump gives heights and normal a tangent space normal
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Socket {
+    BaseColor,
+    Specular,
+    Shininess,
+    Metallic,
+    Roughness,
+    Subsurface,
+    SpecularTint,
+    Anisotropic,
+    AnisotropicRotation,
+    Sheen,
+    SheenTint,
+    Clearcoat,
+    ClearcoatRoughness,
+    Transmission,
+    Emission,
+    Alpha,
+    Occlusion,
+    Bump,
+    Normal,
+}
+
+const SOCKET_COUNT: usize = Socket::Normal as usize + 1;
+
+const ALL_SOCKETS: [Socket; SOCKET_COUNT] = [
+    Socket::BaseColor, Socket::Specular, Socket::Shininess, Socket::Metallic, Socket::Roughness,
+    Socket::Subsurface, Socket::SpecularTint, Socket::Anisotropic, Socket::AnisotropicRotation,
+    Socket::Sheen, Socket::SheenTint, Socket::Clearcoat, Socket::ClearcoatRoughness,
+    Socket::Transmission, Socket::Emission, Socket::Alpha, Socket::Occlusion, Socket::Bump, Socket::Normal,
+];
+
+impl Socket {
+    /**
+     * Inputs of the BSDF node of a surface type by name, the diffuse surface takes the MTL
+     * colors and shininess
+     */
+    pub fn inputs(surface: Surface) -> &'static [(&'static str, Socket)] {
+        match surface {
+            Surface::Principled => return &[
+                ("base_color", Socket::BaseColor),
+                ("metallic", Socket::Metallic),
+                ("roughness", Socket::Roughness),
+                ("subsurface", Socket::Subsurface),
+                ("specular_tint", Socket::SpecularTint),
+                ("anisotropic", Socket::Anisotropic),
+                ("anisotropic_rotation", Socket::AnisotropicRotation),
+                ("sheen", Socket::Sheen),
+                ("sheen_tint", Socket::SheenTint),
+                ("clearcoat", Socket::Clearcoat),
+                ("clearcoat_roughness", Socket::ClearcoatRoughness),
+                ("transmission", Socket::Transmission),
+                ("emission", Socket::Emission),
+                ("alpha", Socket::Alpha),
+                ("occlusion", Socket::Occlusion),
+                ("bump", Socket::Bump),
+                ("normal", Socket::Normal),
+            ],
+            Surface::MetallicRoughness => return &[
+                ("base_color", Socket::BaseColor),
+                ("metallic", Socket::Metallic),
+                ("roughness", Socket::Roughness),
+                ("emission", Socket::Emission),
+                ("alpha", Socket::Alpha),
+                ("occlusion", Socket::Occlusion),
+                ("bump", Socket::Bump),
+                ("normal", Socket::Normal),
+            ],
+            _ => return &[
+                ("color", Socket::BaseColor),
+                ("specular", Socket::Specular),
+                ("shininess", Socket::Shininess),
+                ("emission", Socket::Emission),
+                ("alpha", Socket::Alpha),
+                ("occlusion", Socket::Occlusion),
+                ("bump", Socket::Bump),
+                ("normal", Socket::Normal),
+            ],
+        }
+    }
+
+    /**
+     * Alpha is tested over single texels and bump heights at offsets of the hit, all other
+     * sockets share the footprint of the hit
+     */
+    fn is_per_hit(&self) -> bool {
+        return *self != Socket::Alpha && *self != Socket::Bump;
+    }
+
+    /**
+     * Keeps a value within the range of the parameter
+     */
+    fn clamp(&self, v: Vec4) -> Vec4 {
+        match self {
+            Socket::Specular | Socket::Shininess | Socket::Emission | Socket::Occlusion => return v.max(Vec4::ZERO),
+            Socket::AnisotropicRotation | Socket::Alpha | Socket::Bump | Socket::Normal => return v,
+            _ => return v.clamp(Vec4::ZERO, Vec4::ONE),
+        }
+    }
+}
+
+/**
+ * Value of a node input, a constant or the output of an earlier node, optionally a single
+ * channel of it repeated in all channels
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    Constant(Vec4),
+    Link { node: usize, channel: Option<usize> },
+}
+
+impl Input {
+    fn value(&self, values: &[Vec4]) -> Vec4 {
+        match *self {
+            Input::Constant(c) => return c,
+            Input::Link { node, channel: None } => return values[node],
+            Input::Link { node, channel: Some(channel) } => return Vec4::splat(values[node][channel]),
+        }
+    }
+
+    fn node(&self) -> Option<usize> {
+        match *self {
+            Input::Constant(_) => return None,
+            Input::Link { node, .. } => return Some(node),
+        }
+    }
+}
+
+/**
+ * Node of a shader graph giving an RGBA value per hit. Texture nodes look up images or
+ * procedural textures at the texture coordinates mapped by an affine uv transform, triplanar
+ * nodes project an image along the world axes, blended by the normal raised to the sharpness.
+ * Uv transform nodes give the mapped texture coordinates themselves. Math nodes work on each
+ * channel: mix blends from a to b by the factor, remap maps the range from onto the range to
+ * Reference: https://bgolus.medium.com/normal-mapping-for-a-triplanar-shader-10bf39dca05a
+ */
+pub enum Node {
+    Image { texture: CachedTexture, wrap: WrapMode, uv: Affine2 },
+    Procedural { texture: ProceduralTexture, uv: Affine2 },
+    Triplanar { texture: CachedTexture, wrap: WrapMode, scale: f32, sharpness: f32 },
+    UvTransform { uv: Affine2 },
+    Mix { a: Input, b: Input, factor: Input },
+    Multiply { a: Input, b: Input },
+    Remap { input: Input, from: Vec2, to: Vec2 },
+    Clamp { input: Input, min: Input, max: Input },
+}
+
+impl Node {
+    fn inputs(&self) -> Vec<Input> {
+        match *self {
+            Node::Mix { a, b, factor } => return vec![a, b, factor],
+            Node::Multiply { a, b } => return vec![a, b],
+            Node::Remap { input, .. } => return vec![input],
+            Node::Clamp { input, min, max } => return vec![input, min, max],
+            _ => return Vec::new(),
+        }
+    }
+
+    fn eval(&self, values: &[Vec4], fp: &Footprint) -> Vec4 {
+        match self {
+            Node::Image { texture, wrap, uv } => return texture.lookup(&transform(uv, fp), *wrap),
+            Node::Procedural { texture, uv } => return texture.lookup(&transform(uv, fp)),
+            Node::Triplanar { texture, wrap, scale, sharpness } => {
+                let weights = fp.nrm.abs().powf(*sharpness);
+                let weights = weights / weights.dot(Vec3::ONE).max(f32::MIN_POSITIVE);
+                let p = fp.pos * *scale;
+                let width = fp.width * scale.abs();
+
+                // image rows run down the y axis, or along z when looking down on the image
+                let planes = [
+                    (weights.x, Vec2::new(p.z, -p.y)),
+                    (weights.y, Vec2::new(p.x, -p.z)),
+                    (weights.z, Vec2::new(p.x, -p.y)),
+                ];
+                let mut c = Vec4::ZERO;
+                for (weight, tex) in planes {
+                    if weight >= MIN_TRIPLANAR_WEIGHT {
+                        let plane_fp = Footprint { tex, axis_a: Vec2::new(width, 0.0), axis_b: Vec2::new(0.0, width), ..*fp };
+                        c += texture.lookup(&plane_fp, *wrap) * weight;
+                    }
+                }
+                return c;
+            },
+            Node::UvTransform { uv } => {
+                let tex = uv.transform_point2(Vec2::new(fp.tex.x, 1.0 - fp.tex.y));
+                return Vec4::new(tex.x, tex.y, 0.0, 1.0);
+            },
+            Node::Mix { a, b, factor } => {
+                let a = a.value(values);
+                return a + (b.value(values) - a) * factor.value(values);
+            },
+            Node::Multiply { a, b } => return a.value(values) * b.value(values),
+            Node::Remap { input, from, to } => {
+                let t = (input.value(values) - Vec4::splat(from.x)) / (from.y - from.x);
+                return Vec4::splat(to.x) + t * (to.y - to.x);
+            },
+            Node::Clamp { input, min, max } => return input.value(values).max(min.value(values)).min(max.value(values)),
+        }
+    }
+}
+
+/**
+ * Footprint at the texture coordinates mapped by an affine transform, which applies to the
+ * coordinates with v pointing up
+ */
+fn transform(uv: &Affine2, fp: &Footprint) -> Footprint {
+    // the stored v coordinate is flipped
+    let flip = Vec2::new(1.0, -1.0);
+    let tex = uv.transform_point2(Vec2::new(fp.tex.x, 1.0 - fp.tex.y));
+    return Footprint {
+        tex: Vec2::new(tex.x, 1.0 - tex.y),
+        axis_a: uv.transform_vector2(fp.axis_a * flip) * flip,
+        axis_b: uv.transform_vector2(fp.axis_b * flip) * flip,
+        ..*fp
+    };
+}
+
+/**
+ * Socket connection with the nodes it depends on, in the order they are evaluated
+ */
+struct Output {
+    schedule: Vec<usize>,
+    input: Input,
+}
+
+/**
+ * Values of the sockets of a shader graph at a hit, None for unconnected sockets
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SocketValues {
+    values: [Option<Vec4>; SOCKET_COUNT],
+}
+
+impl SocketValues {
+    pub fn get(&self, socket: Socket) -> Option<Vec4> {
+        return self.values[socket as usize];
+    }
+}
+
+thread_local! {
+    // node values of the graph evaluated last on this thread, reused from hit to hit
+    static NODE_VALUES: RefCell<Vec<Vec4>> = const { RefCell::new(Vec::new()) };
+}
+
+/**
+ * Material defined by a graph of nodes feeding the inputs of a BSDF node, evaluated per hit.
+ * Nodes only link to nodes before them. A hit evaluates the nodes its sockets depend on once and
+ * reads them all from the results, alpha and bump evaluate the nodes of their socket alone
+ */
+pub struct ShaderGraph {
+    nodes: Vec<Node>,
+    outputs: [Option<Output>; SOCKET_COUNT],
+    schedule: Vec<usize>,
+}
+
+impl ShaderGraph {
+    pub fn new(nodes: Vec<Node>) -> ShaderGraph {
+        ShaderGraph {
+            nodes,
+            outputs: std::array::from_fn(|_| None),
+            schedule: Vec::new(),
+        }
+    }
+
+    pub fn connect(&mut self, socket: Socket, input: Input) {
+        let mut schedule: Vec<usize> = Vec::new();
+        let mut stack: Vec<usize> = input.node().into_iter().collect();
+        while let Some(node) = stack.pop() {
+            if !schedule.contains(&node) {
+                schedule.push(node);
+                stack.extend(self.nodes[node].inputs().iter().filter_map(Input::node));
+            }
+        }
+        schedule.sort_unstable();
+
+        if socket.is_per_hit() {
+            self.schedule.extend(&schedule);
+            self.schedule.sort_unstable();
+            self.schedule.dedup();
+        }
+        self.outputs[socket as usize] = Some(Output { schedule, input });
+    }
+
+    pub fn is_connected(&self, socket: Socket) -> bool {
+        return self.outputs[socket as usize].is_some();
+    }
+
+    /**
+     * Values of the sockets over the footprint, alpha and bump are left unevaluated
+     */
+    pub fn eval(&self, fp: &Footprint) -> SocketValues {
+        return self.with_nodes(&self.schedule, fp, |values| {
+            let mut sockets = SocketValues::default();
+            for (&socket, output) in ALL_SOCKETS.iter().zip(&self.outputs) {
+                match output {
+                    Some(output) if socket.is_per_hit() => sockets.values[socket as usize] = Some(socket.clamp(output.input.value(values))),
+                    _ => {},
+                }
+            }
+            return sockets;
+        });
+    }
+
+    /**
+     * Value of a single socket over the footprint, None if it is not connected
+     */
+    pub fn eval_socket(&self, socket: Socket, fp: &Footprint) -> Option<Vec4> {
+        let output = self.outputs[socket as usize].as_ref()?;
+        if let Input::Constant(c) = output.input {
+            return Some(socket.clamp(c));
+        }
+
+        return Some(self.with_nodes(&output.schedule, fp, |values| socket.clamp(output.input.value(values))));
+    }
+
+    /**
+     * Evaluates the scheduled nodes into the node values of this thread and reads from them
+     */
+    fn with_nodes<T>(&self, schedule: &[usize], fp: &Footprint, read: impl FnOnce(&[Vec4]) -> T) -> T {
+        return NODE_VALUES.with(|values| {
+            let mut values = values.borrow_mut();
+            if values.len() < self.nodes.len() {
+                values.resize(self.nodes.len(), Vec4::ZERO);
+            }
+            for &node in schedule {
+                let value = self.nodes[node].eval(&values, fp);
+                values[node] = value;
+            }
+            return read(&values);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::TextureFilter;
+
+    fn footprint(u: f32, v: f32) -> Footprint {
+        return Footprint {
+            tex: Vec2::new(u, v),
+            axis_a: Vec2::ZERO,
+            axis_b: Vec2::ZERO,
+            pos: Vec3::ZERO,
+            nrm: Vec3::Z,
+            width: 0.0,
+            filter: TextureFilter::Nearest,
+        };
+    }
+
+    fn graph() -> ShaderGraph {
+        let uv = Input::Link { node: 0, channel: None };
+        let mut graph = ShaderGraph::new(vec![
+            Node::UvTransform { uv: Affine2::IDENTITY },
+            Node::Multiply { a: uv, b: Input::Constant(Vec4::splat(2.0)) },
+            Node::Remap { input: Input::Link { node: 0, channel: Some(1) }, from: Vec2::new(0.0, 1.0), to: Vec2::new(1.0, -1.0) },
+        ]);
+        graph.connect(Socket::BaseColor, Input::Link { node: 1, channel: None });
+        graph.connect(Socket::Roughness, Input::Link { node: 0, channel: Some(0) });
+        graph.connect(Socket::Bump, Input::Link { node: 2, channel: None });
+        graph.connect(Socket::Emission, Input::Constant(Vec4::splat(-1.0)));
+        return graph;
+    }
+
+    #[test]
+    fn sockets_of_a_hit_match_single_sockets() {
+        let graph = graph();
+        for (u, v) in [(0.25, 0.5), (0.75, 0.1), (0.4, 0.9)] {
+            let fp = footprint(u, v);
+            let values = graph.eval(&fp);
+            for socket in ALL_SOCKETS.into_iter().filter(Socket::is_per_hit) {
+                assert_eq!(values.get(socket), graph.eval_socket(socket, &fp), "{socket:?} at ({u}, {v})");
+            }
+        }
+    }
+
+    #[test]
+    fn sockets_of_a_hit_skip_alpha_and_bump() {
+        let graph = graph();
+        let values = graph.eval(&footprint(0.25, 0.5));
+        assert_eq!(values.get(Socket::BaseColor), Some(Vec4::new(0.5, 1.0, 0.0, 1.0)));
+        assert_eq!(values.get(Socket::Roughness), Some(Vec4::splat(0.25)));
+        assert_eq!(values.get(Socket::Emission), Some(Vec4::ZERO));
+        assert_eq!(values.get(Socket::Bump), None);
+        assert_eq!(values.get(Socket::Metallic), None);
+        assert_eq!(graph.eval_socket(Socket::Bump, &footprint(0.25, 0.5)), Some(Vec4::splat(0.0)));
+    }
+}
@@ -0,0 +1,127 @@
+use glam::{Vec3, Vec4};
+use std::collections::HashMap;
+
+use crate::vertex::Vertex;
+
+/**
+ * Generates per-vertex tangents for a triangle list in the MikkTSpace convention. Face tangents
+ * follow the direction of increasing u and are accumulated, weighted by the corner angle, over
+ * all corners sharing position, normal and texture coordinate, while corners of opposite
+ * handedness are kept apart. The result is orthogonal to the vertex normal and w holds the sign
+ * of the bitangent, which is w * cross(normal, tangent). Vertices without valid texture
+ * coordinates keep a zero tangent
+ * Reference: http://www.mikktspace.com/
+ */
+pub fn generate_tangents(vertices: &mut [Vertex]) {
+    let mut sums: HashMap<([u32; 8], bool), Vec3> = HashMap::new();
+    let mut corner_keys: Vec<Option<([u32; 8], bool)>> = Vec::with_capacity(vertices.len());
+
+    for tri in vertices.chunks_exact(3) {
+        let edge_a = tri[1].pos - tri[0].pos;
+        let edge_b = tri[2].pos - tri[0].pos;
+        let duv_a = tri[1].tex - tri[0].tex;
+        let duv_b = tri[2].tex - tri[0].tex;
+        let det = duv_a.x * duv_b.y - duv_a.y * duv_b.x;
+        if det == 0.0 {
+            corner_keys.extend([None, None, None]);
+            continue;
+        }
+        let tangent = (edge_a * duv_b.y - edge_b * duv_a.y) / det;
+        let bitangent = (edge_b * duv_a.x - edge_a * duv_b.x) / det;
+
+        for k in 0..3 {
+            let v = &tri[k];
+
+            // weight by the angle of the triangle at this corner
+            let to_next = tri[(k + 1) % 3].pos - v.pos;
+            let to_prev = tri[(k + 2) % 3].pos - v.pos;
+            let angle = to_next.angle_between(to_prev);
+
+            let t = match (tangent - v.nrm * v.nrm.dot(tangent)).try_normalize() {
+                Some(t) if angle.is_finite() => t,
+                _ => {
+                    corner_keys.push(None);
+                    continue;
+                },
+            };
+
+            let key = (vertex_key(v), v.nrm.cross(t).dot(bitangent) < 0.0);
+            *sums.entry(key).or_insert(Vec3::ZERO) += t * angle;
+            corner_keys.push(Some(key));
+        }
+    }
+
+    for (v, key) in vertices.iter_mut().zip(corner_keys) {
+        if let Some(key) = key {
+            let t = sums[&key].normalize_or_zero();
+            let sign = if key.1 { -1.0 } else { 1.0 };
+            v.tan = Vec4::new(t.x, t.y, t.z, sign);
+        }
+    }
+}
+
+fn vertex_key(v: &Vertex) -> [u32; 8] {
+    return [
+        v.pos.x.to_bits(), v.pos.y.to_bits(), v.pos.z.to_bits(),
+        v.nrm.x.to_bits(), v.nrm.y.to_bits(), v.nrm.z.to_bits(),
+        v.tex.x.to_bits(), v.tex.y.to_bits(),
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec2;
+
+    fn vertex(x: f32, y: f32, u: f32, v: f32) -> Vertex {
+        return Vertex::new(Vec3::new(x, y, 0.0), Vec3::Z, Vec2::new(u, v));
+    }
+
+    fn quad(tex: impl Fn(f32, f32) -> (f32, f32)) -> Vec<Vertex> {
+        return [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 0.0), (1.0, 1.0), (0.0, 1.0)]
+            .into_iter()
+            .map(|(x, y)| {
+                let (u, v) = tex(x, y);
+                vertex(x, y, u, v)
+            })
+            .collect();
+    }
+
+    fn assert_tangent(v: &Vertex, expected: Vec4) {
+        assert!((v.tan - expected).abs().max_element() < 1e-5, "{:?} expected: {:?}", v, expected);
+    }
+
+    #[test]
+    fn aligned_uvs_give_tangents_along_u() {
+        let mut vertices = quad(|x, y| (x, y));
+        generate_tangents(&mut vertices);
+        for v in &vertices {
+            assert_tangent(v, Vec4::new(1.0, 0.0, 0.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_flip_the_bitangent_sign() {
+        let mut vertices = quad(|x, y| (1.0 - x, y));
+        generate_tangents(&mut vertices);
+        for v in &vertices {
+            assert_tangent(v, Vec4::new(-1.0, 0.0, 0.0, -1.0));
+        }
+    }
+
+    #[test]
+    fn shared_corners_average_their_faces() {
+        // u runs along x on the first triangle and along y on the second, both meet at the origin
+        let mut vertices = vec![
+            vertex(0.0, 0.0, 0.0, 0.0), vertex(1.0, 0.0, 1.0, 0.0), vertex(0.0, 1.0, 0.0, 1.0),
+            vertex(0.0, 0.0, 0.0, 0.0), vertex(0.0, 1.0, 1.0, 0.0), vertex(-1.0, 0.0, 0.0, 1.0),
+        ];
+        generate_tangents(&mut vertices);
+
+        let diagonal = Vec3::new(1.0, 1.0, 0.0).normalize();
+        assert_tangent(&vertices[0], diagonal.extend(1.0));
+        assert_tangent(&vertices[3], diagonal.extend(1.0));
+        assert_tangent(&vertices[1], Vec4::new(1.0, 0.0, 0.0, 1.0));
+        assert_tangent(&vertices[4], Vec4::new(0.0, 1.0, 0.0, 1.0));
+    }
+}
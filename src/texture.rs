@@ -0,0 +1,564 @@
+use glam::{Vec2, Vec3, Vec4};
+use std::ops::Deref;
+use image::{ColorType, DynamicImage, ImageBuffer, Pixel, Primitive};
+use serde::Deserialize;
+
+use crate::intersection::Intersection;
+use crate::tonemap::srgb_to_linear;
+
+// largest ratio of the footprint axes before EWA blurs along the minor axis, bounds the texels per lookup
+const MAX_ANISOTROPY: f32 = 8.0;
+// falloff of the gaussian EWA filter
+const EWA_ALPHA: f32 = 2.0;
+
+/**
+ * Texture lookup for coordinates outside [0, 1), the border color is given as RGBA
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+    Border(Vec4),
+}
+
+impl WrapMode {
+    pub fn from_name(name: &str) -> Option<WrapMode> {
+        match name {
+            "repeat" => return Some(WrapMode::Repeat),
+            "clamp" => return Some(WrapMode::Clamp),
+            "mirror" => return Some(WrapMode::Mirror),
+            "border" => return Some(WrapMode::Border(Vec4::new(0.0, 0.0, 0.0, 1.0))),
+            _ => return None,
+        }
+    }
+
+    fn border(&self) -> Vec4 {
+        match self {
+            WrapMode::Border(color) => return *color,
+            _ => return Vec4::ZERO,
+        }
+    }
+
+    /**
+     * Texel index along an axis of the given size, None for texels of the border
+     */
+    fn index(&self, i: i64, size: i64) -> Option<i64> {
+        match self {
+            WrapMode::Repeat => return Some(i.rem_euclid(size)),
+            WrapMode::Clamp => return Some(i.clamp(0, size - 1)),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * size);
+                return Some(if m < size { m } else { 2 * size - 1 - m });
+            },
+            WrapMode::Border(_) => return if (0..size).contains(&i) { Some(i) } else { None },
+        }
+    }
+}
+
+/**
+ * Texture filtering: single texels, bilinear interpolation of the full resolution texture,
+ * trilinear interpolation between the mip levels matching the footprint width or elliptically
+ * weighted averages over anisotropic footprints
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+    Trilinear,
+    Ewa,
+}
+
+impl TextureFilter {
+    pub fn from_name(name: &str) -> Option<TextureFilter> {
+        match name {
+            "nearest" => return Some(TextureFilter::Nearest),
+            "bilinear" => return Some(TextureFilter::Bilinear),
+            "trilinear" => return Some(TextureFilter::Trilinear),
+            "ewa" => return Some(TextureFilter::Ewa),
+            _ => return None,
+        }
+    }
+}
+
+/**
+ * Cone around a ray covering the footprint of one pixel, the width grows by the spread angle
+ * (in radians) with the distance travelled. Reflection and refraction continue the cone from
+ * the hit point, ignoring the curvature of the surface
+ * Reference: https://link.springer.com/chapter/10.1007/978-1-4842-4427-2_20
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayCone {
+    pub width: f32,
+    pub spread: f32,
+}
+
+impl RayCone {
+    pub fn propagate(&self, dist: f32) -> RayCone {
+        return RayCone {
+            width: self.width + self.spread * dist,
+            spread: self.spread,
+        };
+    }
+}
+
+/**
+ * Area of a texture seen by a lookup, an ellipse around the texture coordinate with the given
+ * axes in texture coordinates. Procedural textures may use the world space position and the
+ * width of the footprint on the surface instead, triplanar projections also the normal
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Footprint {
+    pub tex: Vec2,
+    pub axis_a: Vec2,
+    pub axis_b: Vec2,
+    pub pos: Vec3,
+    pub nrm: Vec3,
+    pub width: f32,
+    pub filter: TextureFilter,
+}
+
+impl Footprint {
+    pub fn point(isect: &Intersection, filter: TextureFilter) -> Footprint {
+        Footprint {
+            tex: isect.tex,
+            axis_a: Vec2::ZERO,
+            axis_b: Vec2::ZERO,
+            pos: isect.pos,
+            nrm: isect.nrm,
+            width: 0.0,
+            filter,
+        }
+    }
+
+    /**
+     * Footprint of a ray cone of the given width hitting the surface along dir. The cone covers
+     * an ellipse on the tangent plane, stretched along the direction of incidence, which maps
+     * to texture coordinates through the position derivatives of the intersection
+     * Reference: https://www.pbr-book.org/3ed-2018/Texture/Sampling_and_Antialiasing
+     */
+    pub fn new(isect: &Intersection, dir: &Vec3, width: f32, filter: TextureFilter) -> Footprint {
+        let (a, b, c) = (isect.dpdu.dot(isect.dpdu), isect.dpdu.dot(isect.dpdv), isect.dpdv.dot(isect.dpdv));
+        let det = a * c - b * b;
+        if det <= 0.0 || width <= 0.0 {
+            return Footprint::point(isect, filter);
+        }
+
+        // least squares texture coordinate offset of a tangent plane offset, v is flipped in tex
+        let to_tex = |p: Vec3| {
+            let (pu, pv) = (p.dot(isect.dpdu), p.dot(isect.dpdv));
+            return Vec2::new(c * pu - b * pv, -(a * pv - b * pu)) / det;
+        };
+
+        let n = isect.nrm;
+        let cos_theta = n.dot(*dir).abs().max(1e-2);
+        let major = (*dir - n * n.dot(*dir)).try_normalize().unwrap_or_else(|| n.any_orthonormal_vector());
+        let minor = n.cross(major);
+
+        return Footprint {
+            tex: isect.tex,
+            axis_a: to_tex(major * width / cos_theta),
+            axis_b: to_tex(minor * width),
+            pos: isect.pos,
+            nrm: n,
+            width: width / cos_theta,
+            filter,
+        };
+    }
+}
+
+/**
+ * Color encoding of the values in an image file, color maps are usually sRGB encoded while
+ * data such as normals, heights or roughness is stored linearly
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Option<ColorSpace> {
+        match name {
+            "srgb" => return Some(ColorSpace::Srgb),
+            "linear" => return Some(ColorSpace::Linear),
+            _ => return None,
+        }
+    }
+}
+
+/**
+ * Channels kept from an image file, gray images repeat their value in the color channels
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channels {
+    Luma,
+    LumaAlpha,
+    Rgb,
+    Rgba,
+}
+
+/**
+ * Channel value type of a texture, read as floats that are normalized for integer types
+ */
+pub trait Texel: Primitive + Send + Sync + 'static {
+    fn to_unit(self) -> f32;
+    fn from_unit(value: f32) -> Self;
+}
+
+impl Texel for u8 {
+    fn to_unit(self) -> f32 {
+        return self as f32 / 255.0;
+    }
+
+    fn from_unit(value: f32) -> u8 {
+        return (value * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+impl Texel for u16 {
+    fn to_unit(self) -> f32 {
+        return self as f32 / 65535.0;
+    }
+
+    fn from_unit(value: f32) -> u16 {
+        return (value * 65535.0).round().clamp(0.0, 65535.0) as u16;
+    }
+}
+
+impl Texel for f32 {
+    fn to_unit(self) -> f32 {
+        return self;
+    }
+
+    fn from_unit(value: f32) -> f32 {
+        return value;
+    }
+}
+
+/**
+ * Image of a single mip level in any channel layout and precision
+ */
+pub trait MipLevel: Send + Sync {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+    fn texel(&self, x: u32, y: u32) -> Vec4;
+    fn bytes(&self) -> usize;
+
+    /**
+     * Next smaller level, averaging blocks of 2x2 texels
+     */
+    fn downsample(&self) -> Box<dyn MipLevel>;
+}
+
+impl<P> MipLevel for ImageBuffer<P, Vec<P::Subpixel>> where P: Pixel + Send + Sync + 'static, P::Subpixel: Texel {
+    fn width(&self) -> u32 {
+        return ImageBuffer::width(self);
+    }
+
+    fn height(&self) -> u32 {
+        return ImageBuffer::height(self);
+    }
+
+    fn texel(&self, x: u32, y: u32) -> Vec4 {
+        let p = self.get_pixel(x, y).to_rgba();
+        return Vec4::new(p[0].to_unit(), p[1].to_unit(), p[2].to_unit(), p[3].to_unit());
+    }
+
+    fn bytes(&self) -> usize {
+        return self.as_raw().len() * std::mem::size_of::<P::Subpixel>();
+    }
+
+    fn downsample(&self) -> Box<dyn MipLevel> {
+        let (w, h) = (ImageBuffer::width(self), ImageBuffer::height(self));
+        let level = ImageBuffer::from_fn((w / 2).max(1), (h / 2).max(1), |x, y| {
+            let mut sum = [0.0; 4];
+            for (sx, sy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let p = self.get_pixel((2 * x + sx).min(w - 1), (2 * y + sy).min(h - 1));
+                for (s, c) in sum.iter_mut().zip(p.channels()) {
+                    *s += c.to_unit();
+                }
+            }
+
+            let mut p = *self.get_pixel(0, 0);
+            for (c, s) in p.channels_mut().iter_mut().zip(sum) {
+                *c = P::Subpixel::from_unit(s * 0.25);
+            }
+            return p;
+        });
+
+        return Box::new(level);
+    }
+}
+
+/**
+ * Converts the sRGB encoded color channels of an image to linear values, alpha is linear already
+ */
+fn linearize<P: Pixel<Subpixel = u16>>(image: &mut ImageBuffer<P, Vec<u16>>) {
+    let color_channels = match P::COLOR_MODEL.ends_with('A') {
+        true => P::CHANNEL_COUNT as usize - 1,
+        false => P::CHANNEL_COUNT as usize,
+    };
+    for p in image.pixels_mut() {
+        for c in p.channels_mut().iter_mut().take(color_channels) {
+            *c = u16::from_unit(srgb_to_linear(c.to_unit()));
+        }
+    }
+}
+
+/**
+ * Texture with a chain of mip levels, each half the size of the previous one down to a single
+ * texel. Texels are stored linearly, lookups return RGBA with gray images repeating their value
+ * in the color channels
+ */
+pub struct MipMap {
+    levels: Vec<Box<dyn MipLevel>>,
+}
+
+impl MipMap {
+    pub fn new<P>(image: ImageBuffer<P, Vec<P::Subpixel>>) -> MipMap where P: Pixel + Send + Sync + 'static, P::Subpixel: Texel {
+        let mut levels: Vec<Box<dyn MipLevel>> = vec![Box::new(image)];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width() == 1 && last.height() == 1 {
+                break;
+            }
+            levels.push(last.downsample());
+        }
+
+        return MipMap {
+            levels,
+        };
+    }
+
+    /**
+     * Mip map keeping the precision of a decoded image file, floating point images such as HDR
+     * and OpenEXR are linear. 8-bit and 16-bit images keep their precision, sRGB encoded ones are
+     * decoded to linear 16-bit values so that dark colors do not band
+     */
+    pub fn from_image(image: &DynamicImage, channels: Channels, color_space: ColorSpace) -> MipMap {
+        let color = image.color();
+        if matches!(color, ColorType::Rgb32F | ColorType::Rgba32F) {
+            match channels {
+                Channels::Luma => return MipMap::new(image.to_luma32f()),
+                Channels::LumaAlpha => return MipMap::new(image.to_luma_alpha32f()),
+                Channels::Rgb => return MipMap::new(image.to_rgb32f()),
+                Channels::Rgba => return MipMap::new(image.to_rgba32f()),
+            }
+        }
+
+        if color.bytes_per_pixel() > color.channel_count() || color_space == ColorSpace::Srgb {
+            let srgb = color_space == ColorSpace::Srgb;
+            match channels {
+                Channels::Luma => {
+                    let mut image = image.to_luma16();
+                    if srgb {
+                        linearize(&mut image);
+                    }
+                    return MipMap::new(image);
+                },
+                Channels::LumaAlpha => {
+                    let mut image = image.to_luma_alpha16();
+                    if srgb {
+                        linearize(&mut image);
+                    }
+                    return MipMap::new(image);
+                },
+                Channels::Rgb => {
+                    let mut image = image.to_rgb16();
+                    if srgb {
+                        linearize(&mut image);
+                    }
+                    return MipMap::new(image);
+                },
+                Channels::Rgba => {
+                    let mut image = image.to_rgba16();
+                    if srgb {
+                        linearize(&mut image);
+                    }
+                    return MipMap::new(image);
+                },
+            }
+        }
+
+        match channels {
+            Channels::Luma => return MipMap::new(image.to_luma8()),
+            Channels::LumaAlpha => return MipMap::new(image.to_luma_alpha8()),
+            Channels::Rgb => return MipMap::new(image.to_rgb8()),
+            Channels::Rgba => return MipMap::new(image.to_rgba8()),
+        }
+    }
+
+    /**
+     * Hands over the levels, from full resolution down to a single texel
+     */
+    pub fn into_levels(self) -> Vec<Box<dyn MipLevel>> {
+        return self.levels;
+    }
+}
+
+fn texel(img: &dyn MipLevel, x: i64, y: i64, wrap: WrapMode) -> Vec4 {
+    let (x, y) = match (wrap.index(x, img.width() as i64), wrap.index(y, img.height() as i64)) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return wrap.border(),
+    };
+
+    return img.texel(x as u32, y as u32);
+}
+
+/**
+ * Texel of a level containing the texture coordinate
+ */
+pub fn nearest(img: &dyn MipLevel, tex: &Vec2, wrap: WrapMode) -> Vec4 {
+    let st = *tex * Vec2::new(img.width() as f32, img.height() as f32);
+    return texel(img, st.x.floor() as i64, st.y.floor() as i64, wrap);
+}
+
+fn bilinear(img: &dyn MipLevel, tex: &Vec2, wrap: WrapMode) -> Vec4 {
+    let st = *tex * Vec2::new(img.width() as f32, img.height() as f32) - 0.5;
+    let (x, y) = (st.x.floor(), st.y.floor());
+    let (fx, fy) = (st.x - x, st.y - y);
+    let (x, y) = (x as i64, y as i64);
+
+    let top = texel(img, x, y, wrap).lerp(texel(img, x + 1, y, wrap), fx);
+    let bottom = texel(img, x, y + 1, wrap).lerp(texel(img, x + 1, y + 1, wrap), fx);
+    return top.lerp(bottom, fy);
+}
+
+/**
+ * Fractional mip level whose texels are as wide as the given width in texture coordinates, for
+ * a mip map with the given size and level count
+ */
+fn level_of(width: f32, size: (u32, u32), level_count: usize) -> f32 {
+    let texels = width * size.0.max(size.1) as f32;
+    return texels.max(1.0).log2().min((level_count - 1) as f32);
+}
+
+/**
+ * Gaussian weighted average of the texels inside the footprint ellipse at one mip level
+ * Reference: https://www.pbr-book.org/3ed-2018/Texture/Image_Texture#EllipticallyWeightedAverage
+ */
+fn ewa(img: &dyn MipLevel, tex: &Vec2, axis_a: &Vec2, axis_b: &Vec2, wrap: WrapMode) -> Vec4 {
+    let size = Vec2::new(img.width() as f32, img.height() as f32);
+    let st = *tex * size - 0.5;
+    let (a0, a1) = (*axis_a * size, *axis_b * size);
+
+    // implicit ellipse A s^2 + B s t + C t^2 = 1, widened to cover at least one texel
+    let a = a0.y * a0.y + a1.y * a1.y + 1.0;
+    let b = -2.0 * (a0.x * a0.y + a1.x * a1.y);
+    let c = a0.x * a0.x + a1.x * a1.x + 1.0;
+    let inv_f = 1.0 / (a * c - b * b * 0.25);
+    let (a, b, c) = (a * inv_f, b * inv_f, c * inv_f);
+
+    // bounding box of the ellipse
+    let det = 4.0 * a * c - b * b;
+    let s_ext = 2.0 * (det * c).sqrt() / det;
+    let t_ext = 2.0 * (det * a).sqrt() / det;
+    let (s0, s1) = ((st.x - s_ext).ceil() as i64, (st.x + s_ext).floor() as i64);
+    let (t0, t1) = ((st.y - t_ext).ceil() as i64, (st.y + t_ext).floor() as i64);
+
+    let mut sum = Vec4::ZERO;
+    let mut weight_sum = 0.0;
+    for t in t0..=t1 {
+        let dt = t as f32 - st.y;
+        for s in s0..=s1 {
+            let ds = s as f32 - st.x;
+            let r2 = a * ds * ds + b * ds * dt + c * dt * dt;
+            if r2 < 1.0 {
+                let weight = (-EWA_ALPHA * r2).exp() - (-EWA_ALPHA).exp();
+                sum += texel(img, s, t, wrap) * weight;
+                weight_sum += weight;
+            }
+        }
+    }
+
+    match weight_sum > 0.0 {
+        true => return sum / weight_sum,
+        false => return bilinear(img, tex, wrap),
+    }
+}
+
+/**
+ * Filtered lookup over the footprint in a mip map with the given full resolution size and level
+ * count, the footprint size selects the mip levels that are fetched by index
+ */
+pub fn lookup<L: Deref<Target = dyn MipLevel>>(size: (u32, u32), level_count: usize, fp: &Footprint, wrap: WrapMode, fetch: impl Fn(usize) -> L) -> Vec4 {
+    match fp.filter {
+        TextureFilter::Nearest => return nearest(&*fetch(0), &fp.tex, wrap),
+        TextureFilter::Bilinear => return bilinear(&*fetch(0), &fp.tex, wrap),
+        TextureFilter::Trilinear => {
+            let level = level_of(fp.axis_a.length().max(fp.axis_b.length()), size, level_count);
+            let l0 = level.floor() as usize;
+            let l1 = (l0 + 1).min(level_count - 1);
+            return bilinear(&*fetch(l0), &fp.tex, wrap).lerp(bilinear(&*fetch(l1), &fp.tex, wrap), level - l0 as f32);
+        },
+        TextureFilter::Ewa => {
+            let (mut major, mut minor) = (fp.axis_a, fp.axis_b);
+            if major.length_squared() < minor.length_squared() {
+                std::mem::swap(&mut major, &mut minor);
+            }
+
+            // clamp the eccentricity by widening the minor axis
+            let (major_len, minor_len) = (major.length(), minor.length());
+            if minor_len == 0.0 {
+                return bilinear(&*fetch(0), &fp.tex, wrap);
+            }
+            if minor_len * MAX_ANISOTROPY < major_len {
+                minor *= major_len / (minor_len * MAX_ANISOTROPY);
+            }
+
+            let level = level_of(minor.length(), size, level_count);
+            let l0 = level.floor() as usize;
+            let l1 = (l0 + 1).min(level_count - 1);
+            let c0 = ewa(&*fetch(l0), &fp.tex, &major, &minor, wrap);
+            if l1 == l0 || level == l0 as f32 {
+                return c0;
+            }
+            return c0.lerp(ewa(&*fetch(l1), &fp.tex, &major, &minor, wrap), level - l0 as f32);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, Rgba};
+
+    fn decoded(image: DynamicImage, channels: Channels, color_space: ColorSpace) -> Vec4 {
+        return MipMap::from_image(&image, channels, color_space).into_levels()[0].texel(0, 0);
+    }
+
+    #[test]
+    fn srgb_is_linearized() {
+        let image = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(1, 1, Rgb([128, 128, 128])));
+        let texel = decoded(image, Channels::Rgb, ColorSpace::Srgb);
+        assert!((texel.x - 0.2158).abs() < 1e-3, "{:?}", texel);
+    }
+
+    #[test]
+    fn linear_data_is_unchanged() {
+        let image = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(1, 1, Rgb([128, 64, 255])));
+        let texel = decoded(image, Channels::Rgb, ColorSpace::Linear);
+        assert_eq!(texel, Vec4::new(128.0 / 255.0, 64.0 / 255.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn sixteen_bit_keeps_its_precision() {
+        let image = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(1, 1, Rgb([40000, 40001, 65535])));
+        let texel = decoded(image.clone(), Channels::Rgb, ColorSpace::Linear);
+        assert!((texel.x - 40000.0 / 65535.0).abs() < 1e-7, "{:?}", texel);
+
+        // neighbouring 16-bit values stay apart where 8 bits would merge them
+        let texel = decoded(image, Channels::Rgb, ColorSpace::Srgb);
+        assert!(texel.x < texel.y, "{:?}", texel);
+    }
+
+    #[test]
+    fn alpha_is_not_linearized() {
+        let image = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, Rgba([128, 128, 128, 128])));
+        let texel = decoded(image, Channels::Rgba, ColorSpace::Srgb);
+        assert!((texel.w - 128.0 / 255.0).abs() < 1e-6, "{:?}", texel);
+        assert!(texel.x < 0.25, "{:?}", texel);
+    }
+}
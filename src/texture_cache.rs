@@ -0,0 +1,439 @@
+use glam::{Vec2, Vec4};
+use image::io::Reader as ImageReader;
+use image::DynamicImage;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, Weak};
+
+use crate::texture::{self, Channels, ColorSpace, Footprint, MipLevel, MipMap, WrapMode};
+
+const MIB: f32 = 1024.0 * 1024.0;
+
+/**
+ * Mip level of a texture, decoded and evicted on its own
+ */
+struct LevelSlot {
+    texels: RwLock<Option<Arc<dyn MipLevel>>>,
+    last_used: AtomicU64,
+    reloaded_in: AtomicU64,
+}
+
+/**
+ * Size and mip levels of a texture, known once the file was decoded for the first time
+ */
+struct Levels {
+    width: u32,
+    height: u32,
+    alpha: bool,
+    slots: Vec<LevelSlot>,
+}
+
+/**
+ * Texture file shared by all materials referencing it with the same channels and color space.
+ * The mip levels are decoded on the first lookup and may be evicted one by one to stay within
+ * the memory budget of the cache, a later lookup of an evicted level then decodes it anew.
+ * Images embedded in model files keep their encoded data, the path only names them
+ */
+struct CacheEntry {
+    path: PathBuf,
+    data: Option<Arc<[u8]>>,
+    channels: Channels,
+    color_space: ColorSpace,
+    levels: OnceLock<Levels>,
+    decoding: Mutex<()>,
+    residency: Arc<Residency>,
+}
+
+impl CacheEntry {
+    fn slot(&self, level: usize) -> &LevelSlot {
+        return &self.levels.get().unwrap().slots[level];
+    }
+
+    /**
+     * Decodes the texture file into its mip levels, along with whether they keep an alpha channel
+     * of the file
+     */
+    fn decode(&self) -> (Vec<Box<dyn MipLevel>>, bool) {
+        let image = match self.data {
+            Some(ref data) => image::load_from_memory(data)
+                .unwrap_or_else(|err| panic!("failed to decode texture {}: {}", self.path.display(), err)),
+            None => ImageReader::open(&self.path)
+                .map_err(image::ImageError::from)
+                .and_then(|reader| reader.decode())
+                .unwrap_or_else(|err| panic!("failed to decode texture {}: {}", self.path.display(), err)),
+        };
+
+        // gray images keep a single channel, lookups repeat it in the color channels
+        let channels = match self.channels {
+            Channels::Rgb if is_gray(&image) => Channels::Luma,
+            channels => channels,
+        };
+        let alpha = image.color().has_alpha() && matches!(channels, Channels::LumaAlpha | Channels::Rgba);
+        return (MipMap::from_image(&image, channels, self.color_space).into_levels(), alpha);
+    }
+}
+
+/**
+ * Decoded mip level of a texture of the cache
+ */
+struct Resident {
+    entry: Weak<CacheEntry>,
+    level: usize,
+    bytes: usize,
+}
+
+#[derive(Default)]
+struct ResidencyState {
+    resident: Vec<Resident>,
+    bytes: usize,
+    peak_bytes: usize,
+    loads: u32,
+    reloads: u32,
+    evictions: u32,
+    over_budget: bool,
+}
+
+/**
+ * Decoded mip levels of a cache. Lookups stamp the levels they read with the current epoch,
+ * which advances with every load and frame, so that the least recently used levels are evicted
+ * first, also while a frame is rendered. Levels decoded along with the one a lookup asked for
+ * count as read just before the load, distant surfaces only read the small levels of their
+ * textures, so that the large ones go first. Eviction runs on loads and whenever a level is
+ * first read in an epoch, so that a texture larger than the budget gives up the levels nobody
+ * reads. Levels decoded again during a frame are kept until the frame ends, they are in use
+ * and evicting them would decode them again and again
+ */
+struct Residency {
+    budget: Option<usize>,
+    epoch: AtomicU64,
+    frame: AtomicU64,
+    state: Mutex<ResidencyState>,
+}
+
+impl Residency {
+    /**
+     * Accounts for decoded levels of a texture, given with their sizes, and evicts levels until
+     * the budget is met again. Of a reload the level looked up counts as read, the others are
+     * only kept by this load
+     */
+    fn admit(&self, entry: &Arc<CacheEntry>, levels: &[(usize, usize)], reloaded: Option<usize>) {
+        let mut state = self.state.lock().unwrap();
+        let epoch = self.epoch.fetch_add(1, Ordering::Relaxed) + 1;
+        for &(level, bytes) in levels {
+            let last_used = match reloaded == Some(level) {
+                true => epoch,
+                false => epoch - 1,
+            };
+            entry.slot(level).last_used.store(last_used, Ordering::Relaxed);
+            state.resident.push(Resident { entry: Arc::downgrade(entry), level, bytes });
+            state.bytes += bytes;
+        }
+        state.peak_bytes = state.peak_bytes.max(state.bytes);
+        state.loads += 1;
+        if let Some(level) = reloaded {
+            entry.slot(level).reloaded_in.store(self.frame.load(Ordering::Relaxed), Ordering::Relaxed);
+            state.reloads += 1;
+        }
+
+        // the levels of a first load are not read yet, the next lookup trims those it does not need
+        let loaded = |victim: &Arc<CacheEntry>, level: usize| Arc::ptr_eq(victim, entry) && levels.iter().any(|l| l.0 == level);
+        if !self.evict(&mut state, epoch, loaded) && reloaded.is_some() {
+            self.warn_over_budget(&mut state);
+        }
+    }
+
+    /**
+     * Evicts levels not read in the given epoch while the budget is exceeded, called when a level
+     * is read for the first time in an epoch
+     */
+    fn trim(&self, epoch: u64) {
+        if self.budget.is_none() {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if !self.evict(&mut state, epoch, |_, _| false) {
+            self.warn_over_budget(&mut state);
+        }
+    }
+
+    /**
+     * Evicts the least recently used levels last read before the given epoch until the budget is
+     * met, except for those decoded again in this frame and those the predicate keeps. Of levels
+     * last used at the same time the largest go first. Returns whether the budget is met
+     */
+    fn evict(&self, state: &mut ResidencyState, epoch: u64, keep: impl Fn(&Arc<CacheEntry>, usize) -> bool) -> bool {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return true,
+        };
+        let frame = self.frame.load(Ordering::Relaxed);
+        while state.bytes > budget {
+            let victim = state.resident.iter()
+                .enumerate()
+                .filter_map(|(i, resident)| resident.entry.upgrade().map(|entry| (i, entry, resident.level, resident.bytes)))
+                .filter(|(_, entry, level, _)| entry.slot(*level).reloaded_in.load(Ordering::Relaxed) != frame && !keep(entry, *level))
+                .map(|(i, entry, level, bytes)| (i, entry.slot(level).last_used.load(Ordering::Relaxed), bytes, entry, level))
+                .filter(|(_, last_used, ..)| *last_used < epoch)
+                .min_by_key(|(_, last_used, bytes, ..)| (*last_used, Reverse(*bytes)));
+            let (i, _, bytes, victim, level) = match victim {
+                Some(victim) => victim,
+                None => return false,
+            };
+
+            state.resident.swap_remove(i);
+            let evicted = victim.slot(level).texels.write().unwrap().take();
+            if evicted.is_some() {
+                state.bytes -= bytes;
+                state.evictions += 1;
+            }
+        }
+
+        return true;
+    }
+
+    fn warn_over_budget(&self, state: &mut ResidencyState) {
+        if !state.over_budget {
+            println!("warning: textures in use exceed the texture memory budget of {:.1} MiB", self.budget.unwrap() as f32 / MIB);
+            state.over_budget = true;
+        }
+    }
+}
+
+/**
+ * Handle to a texture of the cache, lookups decode the texture file on first use
+ */
+#[derive(Clone)]
+pub struct CachedTexture {
+    entry: Arc<CacheEntry>,
+}
+
+impl CachedTexture {
+    /**
+     * Size and levels of the texture, decoding the file on first use. Threads looking up the
+     * same texture meanwhile wait for it
+     */
+    fn levels(&self) -> &Levels {
+        let entry = &self.entry;
+        if let Some(levels) = entry.levels.get() {
+            return levels;
+        }
+
+        let decoding = entry.decoding.lock().unwrap();
+        if let Some(levels) = entry.levels.get() {
+            return levels;
+        }
+
+        let (decoded, alpha) = entry.decode();
+        let (width, height) = (decoded[0].width(), decoded[0].height());
+        let sizes: Vec<(usize, usize)> = decoded.iter().map(|level| level.bytes()).enumerate().collect();
+        println!("loading texture {} ({}x{}, {:.1} MiB) ...", entry.path.display(), width, height, sizes.iter().map(|s| s.1).sum::<usize>() as f32 / MIB);
+
+        let slots = decoded.into_iter()
+            .map(|level| LevelSlot {
+                texels: RwLock::new(Some(Arc::from(level))),
+                last_used: AtomicU64::new(0),
+                reloaded_in: AtomicU64::new(u64::MAX),
+            })
+            .collect();
+        let levels = entry.levels.get_or_init(|| Levels { width, height, alpha, slots });
+        entry.residency.admit(entry, &sizes, None);
+        drop(decoding);
+        return levels;
+    }
+
+    /**
+     * Decoded mip level, stamped as used in the current epoch
+     */
+    fn level(&self, level: usize) -> Arc<dyn MipLevel> {
+        let slot = &self.levels().slots[level];
+        let texels = slot.texels.read().unwrap().clone();
+        if let Some(texels) = texels {
+            let residency = &self.entry.residency;
+            let epoch = residency.epoch.load(Ordering::Relaxed);
+            if slot.last_used.load(Ordering::Relaxed) != epoch {
+                slot.last_used.store(epoch, Ordering::Relaxed);
+                residency.trim(epoch);
+            }
+            return texels;
+        }
+
+        return self.reload(level);
+    }
+
+    /**
+     * Decodes an evicted level again, along with the evicted levels smaller than it, which take
+     * a third of its memory at most
+     */
+    fn reload(&self, level: usize) -> Arc<dyn MipLevel> {
+        let entry = &self.entry;
+        let _decoding = entry.decoding.lock().unwrap();
+        if let Some(ref texels) = *entry.slot(level).texels.read().unwrap() {
+            return texels.clone();
+        }
+
+        let mut sizes: Vec<(usize, usize)> = Vec::new();
+        for (i, texels) in entry.decode().0.into_iter().enumerate().skip(level) {
+            let mut slot = entry.slot(i).texels.write().unwrap();
+            if slot.is_none() {
+                sizes.push((i, texels.bytes()));
+                *slot = Some(Arc::from(texels));
+            }
+        }
+
+        let texels = entry.slot(level).texels.read().unwrap().clone().unwrap();
+        entry.residency.admit(entry, &sizes, Some(level));
+        return texels;
+    }
+
+    pub fn width(&self) -> u32 {
+        return self.levels().width;
+    }
+
+    pub fn height(&self) -> u32 {
+        return self.levels().height;
+    }
+
+    /**
+     * Whether the texture has an alpha channel, without one alpha is one everywhere
+     */
+    pub fn has_alpha(&self) -> bool {
+        return self.levels().alpha;
+    }
+
+    pub fn nearest(&self, tex: &Vec2, wrap: WrapMode) -> Vec4 {
+        return texture::nearest(&*self.level(0), tex, wrap);
+    }
+
+    pub fn lookup(&self, fp: &Footprint, wrap: WrapMode) -> Vec4 {
+        let levels = self.levels();
+        return texture::lookup((levels.width, levels.height), levels.slots.len(), fp, wrap, |level| self.level(level));
+    }
+}
+
+fn is_gray(image: &DynamicImage) -> bool {
+    if !image.color().has_color() {
+        return true;
+    }
+
+    return image.to_rgb8().pixels().all(|p| p[0] == p[1] && p[1] == p[2]);
+}
+
+/**
+ * Textures of all loaded models, files referenced by several materials are decoded once.
+ * Textures are decoded lazily on their first lookup, so unused ones never take memory, and
+ * with a memory budget the least recently used mip levels are evicted when it is exceeded
+ */
+pub struct TextureCache {
+    textures: HashMap<(PathBuf, Channels, ColorSpace), CachedTexture>,
+    residency: Arc<Residency>,
+}
+
+impl TextureCache {
+    /**
+     * Creates a cache with an optional memory budget in bytes
+     */
+    pub fn new(budget: Option<usize>) -> TextureCache {
+        TextureCache {
+            textures: HashMap::new(),
+            residency: Arc::new(Residency {
+                budget,
+                epoch: AtomicU64::new(0),
+                frame: AtomicU64::new(0),
+                state: Mutex::new(ResidencyState::default()),
+            }),
+        }
+    }
+
+    /**
+     * Texture of a file with the given channels and color space, shared with earlier requests
+     * for the same file under any path that resolves to it
+     */
+    pub fn get(&mut self, path: &Path, channels: Channels, color_space: ColorSpace) -> CachedTexture {
+        let path = path.canonicalize()
+            .unwrap_or_else(|err| panic!("failed to open texture file {}: {}", path.display(), err));
+        let key = (path.clone(), channels, color_space);
+        if let Some(texture) = self.textures.get(&key) {
+            println!("  sharing texture {}", path.display());
+            return texture.clone();
+        }
+
+        // decoding waits for the first lookup, check the header now so that a broken file fails
+        // loading the scene rather than a frame
+        if let Err(err) = image::image_dimensions(&path) {
+            panic!("failed to read texture {}: {}", path.display(), err);
+        }
+
+        let texture = CachedTexture {
+            entry: Arc::new(CacheEntry {
+                path,
+                data: None,
+                channels,
+                color_space,
+                levels: OnceLock::new(),
+                decoding: Mutex::new(()),
+                residency: self.residency.clone(),
+            }),
+        };
+        self.textures.insert(key, texture.clone());
+        return texture;
+    }
+
+    /**
+     * Texture of an encoded image embedded in a model file, shared with earlier requests for the
+     * same name. Names are made up by the importer and only need to be unique
+     */
+    pub fn get_embedded(&mut self, name: &str, data: Arc<[u8]>, channels: Channels, color_space: ColorSpace) -> CachedTexture {
+        let key = (PathBuf::from(name), channels, color_space);
+        if let Some(texture) = self.textures.get(&key) {
+            println!("  sharing texture {}", name);
+            return texture.clone();
+        }
+
+        let header = ImageReader::new(Cursor::new(&data[..]))
+            .with_guessed_format()
+            .map_err(image::ImageError::from)
+            .and_then(|reader| reader.into_dimensions());
+        if let Err(err) = header {
+            panic!("failed to read texture {}: {}", name, err);
+        }
+
+        let texture = CachedTexture {
+            entry: Arc::new(CacheEntry {
+                path: PathBuf::from(name),
+                data: Some(data),
+                channels,
+                color_space,
+                levels: OnceLock::new(),
+                decoding: Mutex::new(()),
+                residency: self.residency.clone(),
+            }),
+        };
+        self.textures.insert(key, texture.clone());
+        return texture;
+    }
+
+    /**
+     * Starts a new frame, levels looked up in the previous one age by an epoch and those decoded
+     * again during it may be evicted once more
+     */
+    pub fn begin_frame(&self) {
+        self.residency.epoch.fetch_add(1, Ordering::Relaxed);
+        self.residency.frame.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /**
+     * Prints how often textures were decoded, how many levels were evicted and the memory they
+     * took at most
+     */
+    pub fn report(&self) {
+        let state = self.residency.state.lock().unwrap();
+        println!(
+            "texture cache: {} textures, {} loads, {} reloads, {} evictions, {:.1} MiB resident, {:.1} MiB peak",
+            self.textures.len(), state.loads, state.reloads, state.evictions, state.bytes as f32 / MIB, state.peak_bytes as f32 / MIB,
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use glam::{Vec3, Mat3};
+use image::{ImageBuffer, Rgb32FImage, RgbImage};
+
+/**
+ * Operators mapping linear HDR radiance to the displayable [0, 1] range
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum ToneMapper {
+    Clamp,
+    Reinhard,
+    ReinhardExtended { white: f32 },
+    Hable,
+    Aces,
+}
+
+impl ToneMapper {
+    pub fn from_name(name: &str, white: f32) -> Option<ToneMapper> {
+        match name {
+            "clamp" => return Some(ToneMapper::Clamp),
+            "reinhard" => return Some(ToneMapper::Reinhard),
+            "reinhard-extended" => return Some(ToneMapper::ReinhardExtended { white }),
+            "hable" => return Some(ToneMapper::Hable),
+            "aces" => return Some(ToneMapper::Aces),
+            _ => return None,
+        }
+    }
+
+    pub fn apply(&self, c: Vec3) -> Vec3 {
+        let c = c.max(Vec3::ZERO);
+
+        match *self {
+            ToneMapper::Clamp => return c.min(Vec3::ONE),
+            ToneMapper::Reinhard => return c / (Vec3::ONE + c),
+            ToneMapper::ReinhardExtended { white } => {
+                return (c * (Vec3::ONE + c / (white * white)) / (Vec3::ONE + c)).min(Vec3::ONE);
+            },
+            ToneMapper::Hable => {
+                // exposure bias and linear white point from the Uncharted 2 presentation
+                let white_scale = Vec3::ONE / hable_partial(Vec3::splat(11.2));
+                return (hable_partial(c * 2.0) * white_scale).min(Vec3::ONE);
+            },
+            ToneMapper::Aces => return aces_fitted(c),
+        }
+    }
+}
+
+/**
+ * Reference: http://filmicworlds.com/blog/filmic-tonemapping-operators/
+ */
+fn hable_partial(x: Vec3) -> Vec3 {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+
+    return ((x * (x * a + c * b) + d * e) / (x * (x * a + b) + d * f)) - e / f;
+}
+
+/**
+ * Fit of the ACES reference rendering and output device transforms by Stephen Hill
+ * Reference: https://github.com/TheRealMJP/BakingLab/blob/master/BakingLab/ACES.hlsl
+ */
+fn aces_fitted(c: Vec3) -> Vec3 {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    let aces_input = Mat3::from_cols(
+        Vec3::new(0.59719, 0.07600, 0.02840),
+        Vec3::new(0.35458, 0.90834, 0.13383),
+        Vec3::new(0.04823, 0.01566, 0.83777),
+    );
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    let aces_output = Mat3::from_cols(
+        Vec3::new(1.60475, -0.10208, -0.00327),
+        Vec3::new(-0.53108, 1.10813, -0.07276),
+        Vec3::new(-0.07367, -0.00605, 1.07602),
+    );
+
+    let v = aces_input * c;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (v * 0.983729 + 0.432951) + 0.238081;
+
+    return (aces_output * (a / b)).clamp(Vec3::ZERO, Vec3::ONE);
+}
+
+/**
+ * Encodes a linear channel value with the sRGB transfer function
+ */
+pub fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        return x * 12.92;
+    }
+
+    return 1.055 * x.powf(1.0 / 2.4) - 0.055;
+}
+
+/**
+ * Decodes an sRGB encoded channel value to linear
+ */
+pub fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        return x / 12.92;
+    }
+
+    return ((x + 0.055) / 1.055).powf(2.4);
+}
+
+/**
+ * Applies exposure (in stops), tone mapping and sRGB encoding to a linear HDR image
+ */
+pub fn encode_srgb(hdr: &Rgb32FImage, tone_mapper: &ToneMapper, exposure: f32) -> RgbImage {
+    let scale = 2.0_f32.powf(exposure);
+    let mut img: RgbImage = ImageBuffer::new(hdr.width(), hdr.height());
+
+    for (src, dst) in hdr.pixels().zip(img.pixels_mut()) {
+        let c = tone_mapper.apply(Vec3::new(src[0], src[1], src[2]) * scale);
+        *dst = image::Rgb([
+            (linear_to_srgb(c.x) * 255.0 + 0.5) as u8,
+            (linear_to_srgb(c.y) * 255.0 + 0.5) as u8,
+            (linear_to_srgb(c.z) * 255.0 + 0.5) as u8,
+        ]);
+    }
+
+    return img;
+}
@@ -1,5 +1,6 @@
-use glam::{Vec3, Quat};
+use glam::{Vec3, Quat, Mat3, Mat4};
 
+use crate::animation::{Lerp, Track};
 use crate::utils::EPSILON;
 
 #[derive(Debug, Clone, Copy)]
@@ -26,21 +27,46 @@ impl Transform {
         }
     }
 
-    pub fn from_lookat(pos: Vec3, obj: Vec3) -> Transform {
+    /**
+     * Orients the local +Z axis towards obj with the local +Y axis as close to up as possible
+     */
+    pub fn from_lookat(pos: Vec3, obj: Vec3, up: Vec3) -> Transform {
         let forward = (obj - pos).normalize();
-        let mut rot_axis = Vec3::Z.cross(forward).normalize();
-        if rot_axis.length_squared() < EPSILON {
-            rot_axis.x = 0.0;
-            rot_axis.y = 1.0;
-            rot_axis.z = 0.0;
+        let mut left = up.cross(forward);
+        if left.length_squared() < EPSILON {
+            // looking along the up vector, pick any perpendicular axis
+            left = forward.any_orthonormal_vector();
         }
-        let theta = Vec3::Z.dot(forward).acos();
-        let ori = Quat::from_axis_angle(rot_axis, theta);
-        
+        let left = left.normalize();
+        let ori = Quat::from_mat3(&Mat3::from_cols(left, forward.cross(left), forward));
+
         Transform {
             pos,
             ori,
             scl: Vec3 { x: 1.0, y: 1.0, z: 1.0 },
         }
     }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        return Mat4::from_scale_rotation_translation(self.scl, self.ori, self.pos);
+    }
+}
+
+impl Lerp for Transform {
+    /**
+     * Interpolates position and scale linearly and the orientation along the shortest arc
+     */
+    fn lerp(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            pos: self.pos.lerp(other.pos, t),
+            ori: self.ori.slerp(other.ori, t),
+            scl: self.scl.lerp(other.scl, t),
+        }
+    }
 }
+
+/**
+ * Transform keyframed over time
+ */
+pub type AnimatedTransform = Track<Transform>;
+
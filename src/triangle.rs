@@ -10,6 +10,7 @@ use crate::vertex::Vertex;
 pub struct Triangle {
     pub vrt: [Vertex; 3],
     pub mat: String,
+    pub obj: u32,
     pub node_idx: usize, // for BVH
 }
 
@@ -18,7 +19,7 @@ impl Triangle {
      * Uses the Möller-Trumbore intersection algorithm
      * Reference: http://www.graphics.cornell.edu/pubs/1997/MT97.html
      */
-    pub fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+    pub fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
         // calculate triangle edge vectors
         let edge_a = self.vrt[1].pos - self.vrt[0].pos;
         let edge_b = self.vrt[2].pos - self.vrt[0].pos;
@@ -35,7 +36,7 @@ impl Triangle {
         let t = ray.origin - self.vrt[0].pos;
         let u = t.dot(p) * inv_d;
 
-        if u < 0.0 || u > 1.0 {
+        if !(0.0..=1.0).contains(&u) {
             return None;
         }
 
@@ -58,18 +59,45 @@ impl Triangle {
         // calculate barycentric coords for normals and texture coords
         let (b0, b1, b2) = self.barycentric(&pos, &edge_a, &edge_b);
         let nrm = self.vrt[0].nrm + b1 * (self.vrt[1].nrm - self.vrt[0].nrm) + b2 * (self.vrt[2].nrm - self.vrt[0].nrm);
+        let tan = (self.vrt[0].tan * b0 + self.vrt[1].tan * b1 + self.vrt[2].tan * b2).truncate();
+
+        // the bitangent is built from the unnormalized interpolated vectors, as MikkTSpace expects
+        let bitan = self.vrt[0].tan.w * nrm.cross(tan);
         let nrm = nrm.normalize();
         let tex = self.vrt[0].tex * b0 + self.vrt[1].tex * b1 + self.vrt[2].tex * b2;
+        let (dpdu, dpdv) = self.position_derivatives(&edge_a, &edge_b);
 
         return Some(Intersection {
             t,
             pos,
             nrm,
+            tan,
+            bitan,
+            dpdu,
+            dpdv,
             tex: Vec2::new(tex.x, 1.0 - tex.y),
             mat: &self.mat,
+            obj: self.obj,
         });
     }
 
+    /**
+     * Change of the position along the texture coordinates u and v, zero without texture coordinates
+     * Reference: https://www.pbr-book.org/3ed-2018/Shapes/Triangle_Meshes#Partial_Derivatives
+     */
+    pub fn position_derivatives(&self, edge_a: &Vec3, edge_b: &Vec3) -> (Vec3, Vec3) {
+        let duv_a = self.vrt[1].tex - self.vrt[0].tex;
+        let duv_b = self.vrt[2].tex - self.vrt[0].tex;
+        let det = duv_a.x * duv_b.y - duv_a.y * duv_b.x;
+        if det == 0.0 {
+            return (Vec3::ZERO, Vec3::ZERO);
+        }
+
+        let dpdu = (*edge_a * duv_b.y - *edge_b * duv_a.y) / det;
+        let dpdv = (*edge_b * duv_a.x - *edge_a * duv_b.x) / det;
+        return (dpdu, dpdv);
+    }
+
     /**
      * Reference: https://www.pbr-book.org
      */
@@ -1,7 +1,78 @@
-use glam::Vec3;
+use glam::{Vec2, Vec3};
 
 pub const EPSILON: f32 = 1e-5;
 
 pub fn reflect(incoming: &Vec3, normal: &Vec3) -> Vec3 {
     return *incoming - (*normal * normal.dot(*incoming) * 2.0);
 }
+
+/**
+ * Refracts the unit incoming direction at the normal facing against it, eta is the ratio of the
+ * indices of refraction. Returns None on total internal reflection
+ * Reference: https://www.pbr-book.org/3ed-2018/Reflection_Models/Specular_Reflection_and_Transmission
+ */
+pub fn refract(incoming: &Vec3, normal: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = -normal.dot(*incoming);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    return Some(*incoming * eta + *normal * (eta * cos_i - cos_t));
+}
+
+/**
+ * Relative luminance of a linear sRGB color
+ */
+pub fn luminance(c: &Vec3) -> f32 {
+    return c.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+}
+
+/**
+ * Builds an orthonormal basis around the given unit vector
+ * Reference: https://graphics.pixar.com/library/OrthonormalB/paper.pdf
+ */
+pub fn onb(n: &Vec3) -> (Vec3, Vec3) {
+    let sign = 1.0_f32.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    let t = Vec3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+    let s = Vec3::new(b, sign + n.y * n.y * a, -n.y);
+
+    return (t, s);
+}
+
+/**
+ * Maps two uniform random numbers to a cosine-weighted direction on the hemisphere around n
+ * Reference: https://www.pbr-book.org
+ */
+pub fn sample_cosine_hemisphere(n: &Vec3, u1: f32, u2: f32) -> Vec3 {
+    let r = u1.sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u2;
+    let x = r * phi.cos();
+    let y = r * phi.sin();
+    let z = (1.0 - u1).max(0.0).sqrt();
+    let (t, s) = onb(n);
+
+    return (t * x + s * y + *n * z).normalize();
+}
+
+/**
+ * Maps two uniform random numbers to a point on the unit disk with low distortion
+ * Reference: https://www.pbr-book.org/3ed-2018/Monte_Carlo_Integration/2D_Sampling_with_Multidimensional_Transformations
+ */
+pub fn sample_concentric_disk(u1: f32, u2: f32) -> Vec2 {
+    let o = Vec2::new(u1, u2) * 2.0 - Vec2::ONE;
+    if o.x == 0.0 && o.y == 0.0 {
+        return Vec2::ZERO;
+    }
+
+    let quarter_pi = std::f32::consts::FRAC_PI_4;
+    let (r, theta) = match o.x.abs() > o.y.abs() {
+        true => (o.x, quarter_pi * (o.y / o.x)),
+        false => (o.y, 2.0 * quarter_pi - quarter_pi * (o.x / o.y)),
+    };
+
+    return Vec2::new(theta.cos(), theta.sin()) * r;
+}
@@ -1,10 +1,11 @@
-use glam::{Vec3, Vec2};
+use glam::{Vec3, Vec2, Vec4};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Vertex {
     pub pos: Vec3,
     pub nrm: Vec3,
     pub tex: Vec2,
+    pub tan: Vec4,
 }
 
 impl Vertex {
@@ -13,6 +14,7 @@ impl Vertex {
             pos,
             nrm,
             tex,
+            tan: Vec4::ZERO,
         }
     }
 }